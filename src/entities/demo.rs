@@ -0,0 +1,143 @@
+//! Showcase props for the demo room: a picture-in-picture camera, a monitor
+//! showing a security camera, material pools and a table model. Only built
+//! with the `demo` feature.
+
+use super::player::ORIGIN;
+use crate::{
+	gfx::{
+		Camera,
+		Color,
+		GraphicsContext,
+		Pipelines,
+		Sprite,
+		SpriteBundle,
+		SpriteMode,
+		camera::{ClearMode, RenderTarget},
+		material::{LavaMaterial, Materials, WaterMaterial},
+		mesh::Meshes,
+		model::load_obj,
+		textures::SpriteTextures,
+	},
+	prelude::*,
+	transform::Transform,
+};
+
+app_setup_fn!("demo", setup, after: ["player"]);
+fn setup(app: &mut App) -> JsResult {
+	app.add_systems(
+		Startup,
+		(place_overview, place_monitor, place_pools, place_table),
+	);
+
+	Ok(())
+}
+
+/// Overhead picture-in-picture view of the room.
+fn place_overview(mut cmd: Commands) {
+	cmd.spawn((
+		Camera {
+			viewport: Rect::new(0.02, 0.02, 0.27, 0.27),
+			priority: 1,
+			clear: ClearMode::Color(Color::rgb(0.05, 0.05, 0.1)),
+			..default()
+		},
+		Transform {
+			translation: ORIGIN + Vec3::new(0.0, 2.0, 8.0),
+			rotation: Quat::from_rotation_x(-90f32.to_radians()),
+		},
+	));
+}
+
+/// A security camera watching the room, shown on a screen at its far end.
+fn place_monitor(
+	mut cmd: Commands,
+	ctx: NonSend<GraphicsContext>,
+	mut textures: NonSendMut<SpriteTextures>,
+) {
+	let screen = match textures.add_render_target(&ctx) {
+		Ok(screen) => screen,
+		Err(error) => {
+			log::error!("skipping the monitor: {error}");
+			return;
+		},
+	};
+	cmd.spawn((
+		Camera {
+			target: RenderTarget::Texture {
+				texture: screen,
+				size: UVec2::new(128, 96),
+			},
+			clear: ClearMode::Color(Color::rgb(0.0, 0.05, 0.0)),
+			..default()
+		},
+		Transform::from_translation(ORIGIN + Vec3::new(1.5, 4.5, 1.8))
+			.looking_at(ORIGIN + Vec3::new(0.0, 0.0, 0.5)),
+	));
+	cmd.spawn(SpriteBundle {
+		sprite: Sprite {
+			mode: SpriteMode::Fixed,
+			size: Vec2::new(0.8, 0.6),
+			texture: Some(screen),
+			fullbright: true,
+			..default()
+		},
+		transform: Transform::from_translation(ORIGIN + Vec3::new(0.0, 5.5, 1.2))
+			.looking_along(-Transform::FORWARD),
+	});
+}
+
+/// A pool of water and one of lava on the floor, showing off materials.
+fn place_pools(
+	mut cmd: Commands,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	textures: NonSend<SpriteTextures>,
+	mut materials: NonSendMut<Materials>,
+) {
+	let water = materials.add(&ctx, &pipelines, &textures, WaterMaterial);
+	let lava = materials.add(&ctx, &pipelines, &textures, LavaMaterial);
+	for (x, material) in [(-1.0, water), (1.0, lava)] {
+		let material = match material {
+			Ok(material) => material,
+			Err(error) => {
+				log::error!("skipping a pool: {error}");
+				continue;
+			},
+		};
+		cmd.spawn(SpriteBundle {
+			sprite: Sprite {
+				mode: SpriteMode::Fixed,
+				size: Vec2::splat(0.8),
+				material: Some(material),
+				..default()
+			},
+			transform: Transform::from_translation(ORIGIN + Vec3::new(x, -0.5, 0.01))
+				.looking_along(Transform::UP),
+		});
+	}
+}
+
+/// A table model standing in the middle of the room.
+fn place_table(
+	mut cmd: Commands,
+	ctx: NonSend<GraphicsContext>,
+	mut meshes: NonSendMut<Meshes>,
+	mut textures: NonSendMut<SpriteTextures>,
+) {
+	let mesh = load_obj(include_str!("../models/table.obj"))
+		.and_then(|model| meshes.add_model(&ctx, &mut textures, &model));
+	let mut mesh = match mesh {
+		Ok(mesh) => mesh,
+		Err(error) => {
+			log::error!("skipping the table: {error}");
+			return;
+		},
+	};
+	for part in &mut mesh.parts {
+		part.tint = Color::rgb(0.55, 0.35, 0.2);
+	}
+	cmd.spawn((
+		mesh,
+		Transform::from_translation(ORIGIN + Vec3::new(0.0, 3.0, 0.0)),
+	));
+}
@@ -1 +1,3 @@
+#[cfg(feature = "demo")]
+pub mod demo;
 pub mod player;
@@ -1,27 +1,44 @@
 use std::f32::consts::PI;
 
 use crate::{
-	gfx::{Camera, Sprite, SpriteBundle, SpriteMode},
+	gfx::{
+		Camera,
+		Color,
+		Sprite,
+		SpriteBundle,
+		SpriteMode,
+		decals::{DecalParams, DecalTextures, Decals},
+		lighting::{LightFade, PointLight},
+		particles::{PUFF_FRAMES, ParticleEmitter, ParticleTextures},
+	},
+	map::TileMap,
 	prelude::*,
 	transform::Transform,
 };
 
-app_setup_fn!(setup);
+/// Center of the demo room in the tile map.
+pub(super) const ORIGIN: Vec3 = Vec3::new(8.0, 8.0, 0.0);
+
+app_setup_fn!("player", setup, after: ["gfx", "map"]);
 fn setup(app: &mut App) -> JsResult {
-	app.add_systems(Startup, (startup, place_quads));
-	app.add_systems(Update, orbit);
+	app.add_systems(Startup, (startup, place_quads, place_emitters));
+	app.add_systems(Update, (orbit, shoot));
 
 	Ok(())
 }
 
+/// The camera the player looks and shoots through.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct PlayerCamera;
+
 fn startup(mut cmd: Commands) {
-	cmd.spawn((Camera, Transform {
-		translation: Vec3::new(0.0, -1.5, 1.5),
+	cmd.spawn((PlayerCamera, Camera::default(), Transform {
+		translation: ORIGIN + Vec3::new(0.0, -1.5, 1.5),
 		rotation: Quat::from_rotation_x(-22.5f32.to_radians()),
 	}));
 }
 
-fn orbit(mut query: Query<&mut Transform, With<Camera>>, time: Res<Time<Virtual>>) {
+fn orbit(mut query: Query<&mut Transform, With<PlayerCamera>>, time: Res<Time<Virtual>>) {
 	let mut transform = query.single_mut().unwrap();
 	let yaw = ((time.elapsed_secs() * 2.0 * PI * 0.25).cos() * 45.0).to_radians();
 	transform.rotation = Quat::from_rotation_z(yaw) * Quat::from_rotation_x(-22.5f32.to_radians());
@@ -45,8 +62,112 @@ fn place_quads(mut cmd: Commands) {
 				mode: SpriteMode::Fixed,
 				..default()
 			},
-			transform: Transform::from_translation(Vec3::new(x, y, 0.5)).looking_along(forward),
-			..default()
+			transform: Transform::from_translation(ORIGIN + Vec3::new(x, y, 0.5))
+				.looking_along(forward),
 		});
 	}
 }
+
+fn place_emitters(mut cmd: Commands, textures: Res<ParticleTextures>) {
+	// smouldering smoke and a spark fountain at the end of the room
+	cmd.spawn((
+		Transform::from_translation(ORIGIN + Vec3::new(-1.0, 5.0, 0.0)),
+		ParticleEmitter {
+			rate: 12.0,
+			lifetime: 1.5 .. 2.5,
+			spread: 0.3,
+			speed: 0.3 .. 0.6,
+			start_size: 0.2,
+			end_size: 0.8,
+			start_color: Color::rgba(0.5, 0.5, 0.5, 0.8),
+			end_color: Color::rgba(0.3, 0.3, 0.3, 0.0),
+			texture: Some(textures.puff),
+			frames: PUFF_FRAMES,
+			..default()
+		},
+	));
+	cmd.spawn((
+		Transform::from_translation(ORIGIN + Vec3::new(1.0, 5.0, 0.0)),
+		ParticleEmitter {
+			rate: 40.0,
+			lifetime: 0.6 .. 1.0,
+			spread: 0.5,
+			speed: 1.5 .. 2.5,
+			gravity: 4.0,
+			start_size: 0.06,
+			end_size: 0.02,
+			start_color: Color::rgb(1.0, 0.9, 0.4),
+			end_color: Color::rgba(1.0, 0.3, 0.0, 0.0),
+			texture: Some(textures.spark),
+			..default()
+		},
+	));
+}
+
+/// Fires along the camera's view on click, leaving a bullet hole and sparks
+/// where it hits a wall.
+fn shoot(
+	mut cmd: Commands,
+	input: Res<ButtonInput<MouseButton>>,
+	camera: Query<&Transform, With<PlayerCamera>>,
+	map: Res<TileMap>,
+	mut decals: ResMut<Decals>,
+	decal_textures: Res<DecalTextures>,
+	particle_textures: Res<ParticleTextures>,
+) {
+	if !input.just_pressed(MouseButton::Left) {
+		return;
+	}
+	let Ok(camera) = camera.single() else {
+		return;
+	};
+
+	cmd.spawn((
+		camera.with_translation(camera.translation + camera.forward() * 0.3),
+		PointLight {
+			color: Color::rgb(1.0, 0.8, 0.4),
+			radius: 5.0,
+			intensity: 2.0,
+			flicker: 0.0,
+		},
+		LightFade::new(0.08),
+	));
+
+	// the ray parameter along the unnormalized ground direction is the same as
+	// along the full 3D direction
+	let direction = camera.forward();
+	let Some(hit) = map.cast_ray(camera.translation.truncate(), direction.truncate(), 64.0) else {
+		return;
+	};
+	let point = camera.translation + direction * hit.distance;
+	if !(0.0 .. 1.0).contains(&point.z) {
+		return;
+	}
+	let normal = hit.normal.as_vec2().extend(0.0);
+
+	decals.spawn(point, normal, DecalParams {
+		size: Vec2::splat(0.08),
+		texture: Some(decal_textures.bullet_hole),
+		lifetime: Some(30.0),
+		fade: 5.0,
+		..default()
+	});
+	cmd.spawn((
+		Transform::from_translation(point + normal * 0.02).looking_along(normal),
+		ParticleEmitter {
+			burst: 12,
+			one_shot: true,
+			lifetime: 0.2 .. 0.5,
+			direction: Transform::FORWARD,
+			spread: 0.8,
+			speed: 1.0 .. 3.0,
+			gravity: 6.0,
+			start_size: 0.04,
+			end_size: 0.01,
+			start_color: Color::rgb(1.0, 0.9, 0.5),
+			end_color: Color::rgba(1.0, 0.4, 0.1, 0.0),
+			texture: Some(particle_textures.spark),
+			..default()
+		},
+	));
+}
@@ -1,6 +1,12 @@
-use crate::prelude::*;
+use std::fmt::Write;
 
-app_setup_fn!(setup);
+use crate::{
+	DomElements,
+	gfx::profiling::{PassTimings, RenderStats, TimingSource},
+	prelude::*,
+};
+
+app_setup_fn!("fps_counter", setup);
 fn setup(app: &mut App) -> JsResult {
 	app.init_resource::<FpsState>();
 	app.add_systems(Update, fps_frame);
@@ -16,19 +22,60 @@ struct FpsState {
 	ticks: usize,
 }
 
-fn fps_frame(mut state: ResMut<FpsState>, time: Res<Time<Real>>) {
+fn fps_frame(
+	mut state: ResMut<FpsState>,
+	time: Res<Time<Real>>,
+	timings: Option<Res<PassTimings>>,
+	stats: Option<Res<RenderStats>>,
+	dom: NonSend<DomElements>,
+) {
 	state.frames += 1;
 	state.accum += time.delta_secs_f64();
 	let update = state.accum >= 1.0;
 	state.accum = state.accum.fract();
 
 	if update {
-		let window = web_sys::window().unwrap();
-		let document = window.document().unwrap();
-		document.set_title(&format!(
-			"wgpustein | {} fps {} tps",
-			state.frames, state.ticks
-		));
+		let fps = format!("{} fps {} tps", state.frames, state.ticks);
+		dom.document.set_title(&format!("wgpustein | {fps}"));
+
+		if let Some(overlay) = dom.document.get_element_by_id("debug-overlay") {
+			let mut text = fps;
+			if let Some(stats) = stats {
+				let _ = write!(
+					text,
+					"\n{} draws {} pipelines\n{} instances ({} culled)\n{:.1}KB uploaded {:.1}MB \
+					 textures",
+					stats.draw_calls,
+					stats.pipeline_switches,
+					stats.instances_drawn,
+					stats.instances_culled,
+					stats.bytes_uploaded as f64 / 1024.0,
+					stats.texture_bytes as f64 / (1024.0 * 1024.0),
+				);
+			}
+			if let Some(timings) = timings {
+				let source = match timings.source {
+					TimingSource::Gpu => "gpu",
+					TimingSource::Cpu => "cpu",
+				};
+				let _ = write!(
+					text,
+					"\n{source} {:.2}ms",
+					timings.total().as_secs_f64() * 1000.0
+				);
+				for timing in timings.iter() {
+					let _ = write!(
+						text,
+						"\n  {} {:.2}ms (max {:.2}ms)",
+						timing.label,
+						timing.average.as_secs_f64() * 1000.0,
+						timing.max.as_secs_f64() * 1000.0,
+					);
+				}
+			}
+			overlay.set_text_content(Some(&text));
+		}
+
 		state.ticks = 0;
 		state.frames = 0;
 	}
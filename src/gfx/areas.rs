@@ -0,0 +1,301 @@
+//! Visibility culling by areas. The open cells of the [`TileMap`] are split
+//! into areas wherever doors separate them, with each door cell an area of its
+//! own linking the areas on either side. A view sees the area its camera is in
+//! and whatever it reaches from there through door cells inside its frustum,
+//! passing only through open doors. Sprites and static chunks in other areas
+//! are skipped.
+
+use crate::{
+	gfx::{RenderPre, RenderPreSet, Sprite, SpriteMode, culling::Frustum},
+	map::{Tile, TileMap},
+	prelude::*,
+	transform::Transform,
+};
+
+const NEIGHBORS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+/// Bounding sphere radius of a cell, from the floor to the ceiling.
+const CELL_RADIUS: f32 = 0.8660254;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Area {
+	/// Set for the single-cell areas of doors.
+	door: Option<IVec2>,
+	neighbors: Vec<u32>,
+}
+
+/// The areas of the current [`TileMap`], rebuilt whenever its walls and doors
+/// move.
+#[derive(Resource, Clone, Debug, Default, PartialEq)]
+pub struct Areas {
+	size: UVec2,
+	/// Area of each cell, or [`Areas::NONE`] for walls.
+	cells: Vec<u32>,
+	areas: Vec<Area>,
+}
+
+impl Areas {
+	const NONE: u32 = u32::MAX;
+
+	pub fn new(map: &TileMap) -> Self {
+		let size = map.size();
+		let mut areas = Self {
+			size,
+			cells: vec![Self::NONE; (size.x * size.y) as usize],
+			areas: Vec::new(),
+		};
+
+		for (cell, tile) in map.cells() {
+			let index = areas.index(cell).unwrap();
+			if areas.cells[index] != Self::NONE {
+				continue;
+			}
+			let area = areas.areas.len() as u32;
+			match tile {
+				Tile::Wall(_) => continue,
+				Tile::Door { .. } => {
+					areas.cells[index] = area;
+					areas.areas.push(Area {
+						door: Some(cell),
+						neighbors: Vec::new(),
+					});
+				},
+				Tile::Empty => {
+					areas.areas.push(Area {
+						door: None,
+						neighbors: Vec::new(),
+					});
+					// flood the room, stopping at walls and doors
+					areas.cells[index] = area;
+					let mut open = vec![cell];
+					while let Some(cell) = open.pop() {
+						for neighbor in NEIGHBORS.map(|offset| cell + offset) {
+							let Some(index) = areas.index(neighbor) else {
+								continue;
+							};
+							if areas.cells[index] == Self::NONE &&
+								map.get(neighbor) == Some(Tile::Empty)
+							{
+								areas.cells[index] = area;
+								open.push(neighbor);
+							}
+						}
+					}
+				},
+			}
+		}
+
+		// doors link every area they touch
+		for area in 0 .. areas.areas.len() {
+			let Some(cell) = areas.areas[area].door else {
+				continue;
+			};
+			for neighbor in NEIGHBORS.map(|offset| cell + offset) {
+				let Some(other) = areas.get(neighbor) else {
+					continue;
+				};
+				for (from, to) in [(area as u32, other), (other, area as u32)] {
+					let neighbors = &mut areas.areas[from as usize].neighbors;
+					if !neighbors.contains(&to) {
+						neighbors.push(to);
+					}
+				}
+			}
+		}
+
+		areas
+	}
+
+	/// The area of the cell `position` is in, unless that is a wall or outside
+	/// the map.
+	pub fn area_at(&self, position: Vec3) -> Option<u32> {
+		self.get(TileMap::cell_at(position.truncate()))
+	}
+
+	/// The area a sprite is drawn in. Fixed sprites count as being in the cell
+	/// they face, as walls sit on cell boundaries.
+	pub fn sprite_area(&self, transform: &Transform, sprite: &Sprite) -> Option<u32> {
+		match sprite.mode {
+			SpriteMode::Billboard => self.area_at(transform.translation),
+			SpriteMode::Fixed => self.area_at(transform.translation + transform.forward() * 0.01),
+		}
+	}
+
+	/// Which areas can be seen through: every room, and the doors open in
+	/// `map`.
+	pub fn open(&self, map: &TileMap) -> Vec<bool> {
+		self.areas
+			.iter()
+			.map(|area| {
+				area.door
+					.is_none_or(|cell| map.get(cell) == Some(Tile::Door { open: true }))
+			})
+			.collect()
+	}
+
+	/// The areas a view from `eye` can see within `frustum`, looking only
+	/// through those [`open`](Self::open).
+	pub fn visible_from(&self, open: &[bool], eye: Vec3, frustum: &Frustum) -> AreaVisibility {
+		let Some(start) = self.area_at(eye) else {
+			// from inside a wall or outside the map, nothing can be ruled out
+			return AreaVisibility::ALL;
+		};
+
+		let mut visible = vec![false; self.areas.len()];
+		visible[start as usize] = true;
+		let mut pending = vec![start];
+		while let Some(area) = pending.pop() {
+			// look through doors only when open, or when standing in them
+			if area != start && !open.get(area as usize).copied().unwrap_or(true) {
+				continue;
+			}
+			for &neighbor in &self.areas[area as usize].neighbors {
+				if visible[neighbor as usize] {
+					continue;
+				}
+				// neighbours of a room are always doors, which have to be in
+				// view for anything behind them to be
+				if let Some(cell) = self.areas[neighbor as usize].door &&
+					!frustum.intersects_sphere(cell.as_vec2().extend(0.0) + 0.5, CELL_RADIUS)
+				{
+					continue;
+				}
+				visible[neighbor as usize] = true;
+				pending.push(neighbor);
+			}
+		}
+		AreaVisibility(Some(visible))
+	}
+
+	fn get(&self, cell: IVec2) -> Option<u32> {
+		self.index(cell)
+			.map(|index| self.cells[index])
+			.filter(|&area| area != Self::NONE)
+	}
+
+	fn index(&self, cell: IVec2) -> Option<usize> {
+		let in_bounds = cell.cmpge(IVec2::ZERO).all() && cell.as_uvec2().cmplt(self.size).all();
+		in_bounds.then(|| (cell.y as u32 * self.size.x + cell.x as u32) as usize)
+	}
+}
+
+/// Which areas a view can see.
+#[derive(Clone, Debug, Default)]
+pub struct AreaVisibility(Option<Vec<bool>>);
+
+impl AreaVisibility {
+	/// Sees every area, as for maps without areas.
+	pub const ALL: Self = Self(None);
+
+	/// Whether something in `area` can be seen. Things outside every area
+	/// always can.
+	pub fn contains(&self, area: Option<u32>) -> bool {
+		match (&self.0, area) {
+			(Some(visible), Some(area)) => visible.get(area as usize).copied().unwrap_or(true),
+			_ => true,
+		}
+	}
+}
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<Areas>();
+	app.add_systems(RenderPre, update_areas.before(RenderPreSet::Extract));
+}
+
+fn update_areas(mut areas: ResMut<Areas>, map: Option<Res<TileMap>>) {
+	let Some(map) = map.filter(|map| map.is_changed()) else {
+		return;
+	};
+	// opening and closing doors changes the map but not its areas
+	areas.set_if_neq(Areas::new(&map));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Two rooms joined by a closed door next to an open one.
+	const MAP: &str = "1111111\n1.Dd..1\n1111111\n";
+
+	/// A frustum taking in everything.
+	const EVERYWHERE: Frustum = Frustum {
+		planes: [Vec4::ZERO; 6],
+	};
+
+	fn eye(x: f32) -> Vec3 {
+		Vec3::new(x, 1.5, 0.5)
+	}
+
+	fn visible(visibility: &AreaVisibility, areas: &Areas) -> Vec<u32> {
+		(0 .. areas.areas.len() as u32)
+			.filter(|&area| visibility.contains(Some(area)))
+			.collect()
+	}
+
+	#[test]
+	fn new() {
+		let areas = Areas::new(&TileMap::parse(MAP).unwrap());
+		assert_eq!(areas.areas.len(), 4);
+		assert_eq!(areas.area_at(eye(1.5)), Some(0));
+		assert_eq!(areas.area_at(eye(2.5)), Some(1));
+		assert_eq!(areas.area_at(eye(3.5)), Some(2));
+		assert_eq!(areas.area_at(eye(4.5)), Some(3));
+		assert_eq!(areas.area_at(eye(5.5)), Some(3));
+		assert_eq!(areas.area_at(eye(0.5)), None);
+
+		assert_eq!(areas.areas[1].door, Some(IVec2::new(2, 1)));
+		assert_eq!(areas.areas[2].door, Some(IVec2::new(3, 1)));
+		let neighbors = |area: usize| {
+			let mut neighbors = areas.areas[area].neighbors.clone();
+			neighbors.sort();
+			neighbors
+		};
+		assert_eq!(neighbors(0), [1]);
+		// the doors link to each other as well as to their rooms
+		assert_eq!(neighbors(1), [0, 2]);
+		assert_eq!(neighbors(2), [1, 3]);
+		assert_eq!(neighbors(3), [2]);
+	}
+
+	#[test]
+	fn closed_door_blocks() {
+		let map = TileMap::parse(MAP).unwrap();
+		let areas = Areas::new(&map);
+		let open = areas.open(&map);
+		assert_eq!(open, [true, false, true, true]);
+
+		// the closed door itself is seen, but not past it
+		let visibility = areas.visible_from(&open, eye(1.5), &EVERYWHERE);
+		assert_eq!(visible(&visibility, &areas), [0, 1]);
+		let visibility = areas.visible_from(&open, eye(4.5), &EVERYWHERE);
+		assert_eq!(visible(&visibility, &areas), [1, 2, 3]);
+
+		// standing in the doorway sees both ways
+		let visibility = areas.visible_from(&open, eye(2.5), &EVERYWHERE);
+		assert_eq!(visible(&visibility, &areas), [0, 1, 2, 3]);
+	}
+
+	#[test]
+	fn open_doors_link_rooms() {
+		let map = TileMap::parse(&MAP.replace('D', "d")).unwrap();
+		let areas = Areas::new(&map);
+		let open = areas.open(&map);
+
+		let visibility = areas.visible_from(&open, eye(1.5), &EVERYWHERE);
+		assert_eq!(visible(&visibility, &areas), [0, 1, 2, 3]);
+
+		// doors outside the frustum hide what is behind them
+		let mut frustum = EVERYWHERE;
+		frustum.planes[0] = Vec4::new(-1.0, 0.0, 0.0, 2.5);
+		let visibility = areas.visible_from(&open, eye(1.5), &frustum);
+		assert_eq!(visible(&visibility, &areas), [0, 1]);
+	}
+
+	#[test]
+	fn eye_in_wall_sees_everything() {
+		let map = TileMap::parse(MAP).unwrap();
+		let areas = Areas::new(&map);
+		let visibility = areas.visible_from(&areas.open(&map), eye(0.5), &EVERYWHERE);
+		assert_eq!(visible(&visibility, &areas), [0, 1, 2, 3]);
+	}
+}
@@ -0,0 +1,467 @@
+//! Top-down automap of the tiles the player has seen, shown either as a
+//! rotating minimap in the corner of the canvas or over the whole canvas.
+
+use wgpu::BufferUsages;
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		Color,
+		ExtractedFrame,
+		GraphicsContext,
+		Pipelines,
+		Render,
+		RenderPre,
+		RenderPreSet,
+		RenderSet,
+		camera::Views,
+		prepare_frame,
+		profiling::GpuProfiler,
+		shader,
+	},
+	map::{Tile, TileMap},
+	prelude::*,
+	transform::Transform,
+};
+
+/// Rays cast across the view each frame to discover tiles.
+const EXPLORE_RAYS: usize = 96;
+const EXPLORE_DISTANCE: f32 = 32.0;
+/// Fraction of the canvas' shorter side taken up by the minimap.
+const MINIMAP_SIZE: f32 = 0.3;
+const MINIMAP_MARGIN: f32 = 16.0;
+
+const BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.75);
+const FLOOR: Color = Color::rgba(0.2, 0.2, 0.2, 0.75);
+const WALL: Color = Color::rgb(0.7, 0.7, 0.7);
+const DOOR: Color = Color::rgb(0.9, 0.75, 0.2);
+const PLAYER: Color = Color::GREEN;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AutomapMode {
+	#[default]
+	Off,
+	/// Small map in the top right corner.
+	Minimap,
+	Fullscreen,
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct Automap {
+	pub mode: AutomapMode,
+	/// Tiles from the player to the edge of the map, along its shorter side.
+	pub zoom: f32,
+	/// Whether the map turns with the player so that forward is always up,
+	/// rather than north.
+	pub rotate: bool,
+	explored: Vec<bool>,
+	size: UVec2,
+}
+
+impl Default for Automap {
+	fn default() -> Self {
+		Self {
+			mode: default(),
+			zoom: 8.0,
+			rotate: true,
+			explored: Vec::new(),
+			size: UVec2::ZERO,
+		}
+	}
+}
+
+impl Automap {
+	pub fn is_explored(&self, cell: IVec2) -> bool {
+		self.index(cell).is_some_and(|index| self.explored[index])
+	}
+
+	pub fn explore(&mut self, cell: IVec2) {
+		if let Some(index) = self.index(cell) {
+			self.explored[index] = true;
+		}
+	}
+
+	/// Forgets every explored tile, as when entering a new level.
+	pub fn reset(&mut self, size: UVec2) {
+		self.size = size;
+		self.explored = vec![false; (size.x * size.y) as usize];
+	}
+
+	fn index(&self, cell: IVec2) -> Option<usize> {
+		let in_bounds = cell.cmpge(IVec2::ZERO).all() && cell.as_uvec2().cmplt(self.size).all();
+		in_bounds.then(|| (cell.y as u32 * self.size.x + cell.x as u32) as usize)
+	}
+}
+
+/// Shows the entity on the automap as a dot once its tile has been explored,
+/// for items and other points of interest.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct AutomapMarker {
+	pub color: Color,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct AutomapVertex {
+	position: Vec2,
+	color: Color,
+}
+
+pub struct AutomapPipelines {
+	vertices: wgpu::Buffer,
+	vertex_count: u32,
+	/// Canvas pixels the map is drawn into, as `x, y, width, height`.
+	viewport: Vec4,
+	pipeline: wgpu::RenderPipeline,
+}
+
+impl AutomapPipelines {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		let pipeline =
+			create_pipeline(ctx, &shader::create_shader_module(ctx, "automap.wgsl", &[]));
+
+		Self {
+			vertices: create_vertex_buffer(ctx, 1024),
+			vertex_count: 0,
+			viewport: Vec4::ZERO,
+			pipeline,
+		}
+	}
+}
+
+fn create_pipeline(
+	ctx: &GraphicsContext,
+	shader_module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("automap render layout"),
+			bind_group_layouts: &[],
+			push_constant_ranges: &[],
+		});
+	ctx.device
+		.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("automap render pipeline"),
+			layout: Some(&pipeline_layout),
+			depth_stencil: Some(wgpu::DepthStencilState {
+				format: wgpu::TextureFormat::Depth24Plus,
+				depth_write_enabled: false,
+				depth_compare: wgpu::CompareFunction::Always,
+				stencil: default(),
+				bias: default(),
+			}),
+			multisample: wgpu::MultisampleState::default(),
+			multiview: None,
+			cache: None,
+			primitive: wgpu::PrimitiveState::default(),
+			vertex: wgpu::VertexState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: None,
+				buffers: &[wgpu::VertexBufferLayout {
+					step_mode: wgpu::VertexStepMode::Vertex,
+					array_stride: size_of::<AutomapVertex>() as _,
+					attributes: &wgpu::vertex_attr_array![
+						0 => Float32x2,
+						1 => Float32x4,
+					],
+				}],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: None,
+				targets: &[Some(wgpu::ColorTargetState {
+					format: ctx.surface_format(),
+					blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+			}),
+		})
+}
+
+#[cfg(debug_assertions)]
+fn reload_pipeline(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	if changes
+		.read()
+		.any(|change| change.affects("automap.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"automap.wgsl",
+			&[],
+			|shader_module| create_pipeline(&ctx, shader_module),
+			|world, pipeline| world.non_send_resource_mut::<AutomapPipelines>().pipeline = pipeline,
+		);
+	}
+}
+
+fn create_vertex_buffer(ctx: &GraphicsContext, length: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("automap vertices"),
+		size: (size_of::<AutomapVertex>() * length) as _,
+		usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
+
+/// Position and colour of every [`AutomapMarker`].
+#[derive(Resource, Default)]
+struct ExtractedMarkers(Vec<(Vec2, Color)>);
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<Automap>();
+	app.init_resource::<ExtractedMarkers>();
+	app.add_systems(Update, (explore, automap_controls));
+	app.add_systems(
+		RenderPre,
+		(
+			extract_markers.in_set(RenderPreSet::Extract),
+			build_automap
+				.in_set(RenderPreSet::Prepare)
+				.after(prepare_frame),
+		),
+	);
+	app.add_systems(Render, draw_automap.in_set(RenderSet::Overlay));
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_pipeline);
+}
+
+/// Marks the tiles visible from the main view as explored, by casting rays
+/// across its horizontal field of view.
+fn explore(
+	mut automap: ResMut<Automap>,
+	map: Option<Res<TileMap>>,
+	views: Res<Views>,
+	transforms: Query<&Transform>,
+) {
+	let Some((map, view)) = map.zip(views.main()) else {
+		return;
+	};
+	let Ok(camera) = transforms.get(view.entity) else {
+		return;
+	};
+	if automap.size != map.size() {
+		automap.reset(map.size());
+	}
+
+	let eye = camera.translation.truncate();
+	let forward = camera.forward().truncate().normalize_or(Vec2::Y);
+	let right = Vec2::new(forward.y, -forward.x);
+	let tan_x = 1.0 / view.projection.x_axis.x;
+
+	automap.explore(TileMap::cell_at(eye));
+	for ray in 0 .. EXPLORE_RAYS {
+		let camera_x = ray as f32 / (EXPLORE_RAYS - 1) as f32 * 2.0 - 1.0;
+		let direction = (forward + right * camera_x * tan_x).normalize();
+		let distance = match map.cast_ray(eye, direction, EXPLORE_DISTANCE) {
+			Some(hit) => {
+				automap.explore(hit.cell);
+				hit.distance
+			},
+			None => EXPLORE_DISTANCE,
+		};
+		// the floor the ray crossed, sampled finely enough not to skip cells
+		let mut t = 0.0;
+		while t < distance {
+			automap.explore(TileMap::cell_at(eye + direction * t));
+			t += 0.25;
+		}
+	}
+}
+
+fn automap_controls(input: Res<ButtonInput<KeyCode>>, mut automap: ResMut<Automap>) {
+	if input.just_pressed(KeyCode::Tab) {
+		automap.mode = match automap.mode {
+			AutomapMode::Off => AutomapMode::Minimap,
+			AutomapMode::Minimap => AutomapMode::Fullscreen,
+			AutomapMode::Fullscreen => AutomapMode::Off,
+		};
+	}
+	if automap.mode == AutomapMode::Off {
+		return;
+	}
+	if input.just_pressed(KeyCode::Equal) {
+		automap.zoom = (automap.zoom / 1.25).max(2.0);
+	}
+	if input.just_pressed(KeyCode::Minus) {
+		automap.zoom = (automap.zoom * 1.25).min(64.0);
+	}
+	if input.just_pressed(KeyCode::KeyR) {
+		automap.rotate = !automap.rotate;
+	}
+}
+
+fn extract_markers(
+	mut extracted: ResMut<ExtractedMarkers>,
+	markers: Query<(&Transform, &AutomapMarker)>,
+) {
+	extracted.0.clear();
+	extracted.0.extend(
+		markers
+			.iter()
+			.map(|(transform, marker)| (transform.translation.truncate(), marker.color)),
+	);
+}
+
+#[expect(clippy::too_many_arguments)]
+fn build_automap(
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	mut automap_pipelines: NonSendMut<AutomapPipelines>,
+	automap: Res<Automap>,
+	map: Option<Res<TileMap>>,
+	views: Res<Views>,
+	extracted: Res<ExtractedFrame>,
+	markers: Res<ExtractedMarkers>,
+	mut vertices: Local<Vec<AutomapVertex>>,
+) {
+	automap_pipelines.vertex_count = 0;
+	let Some(map) = map else {
+		return;
+	};
+	let Some(camera) = views.main().and_then(|view| extracted.camera(view.entity)) else {
+		return;
+	};
+	let camera = &camera.transform;
+	if automap.mode == AutomapMode::Off {
+		return;
+	}
+
+	let canvas = Vec2::new(
+		pipelines.depth_texture.width() as f32,
+		pipelines.depth_texture.height() as f32,
+	);
+	let viewport = match automap.mode {
+		AutomapMode::Off => return,
+		AutomapMode::Minimap => {
+			let size = canvas.min_element() * MINIMAP_SIZE;
+			Vec4::new(canvas.x - size - MINIMAP_MARGIN, MINIMAP_MARGIN, size, size)
+		},
+		AutomapMode::Fullscreen => Vec4::new(0.0, 0.0, canvas.x, canvas.y),
+	};
+	automap_pipelines.viewport = viewport;
+
+	// map units to clip space, centred on the player
+	let center = camera.translation.truncate();
+	let forward = camera.forward().truncate().normalize_or(Vec2::Y);
+	let rotation = if automap.rotate {
+		// turns forward to +y
+		Vec2::new(forward.y, forward.x)
+	} else {
+		Vec2::X
+	};
+	let scale = Vec2::new(viewport.w / viewport.z, 1.0) / automap.zoom;
+	let to_clip = |position: Vec2| rotation.rotate(position - center) * scale;
+
+	vertices.clear();
+	let mut quad = |corners: [Vec2; 4], color: Color| {
+		let [a, b, c, d] = corners.map(|corner| AutomapVertex {
+			position: corner,
+			color,
+		});
+		vertices.extend([a, b, c, c, b, d]);
+	};
+	quad(
+		[
+			Vec2::new(-1.0, 1.0),
+			Vec2::ONE,
+			Vec2::NEG_ONE,
+			Vec2::new(1.0, -1.0),
+		],
+		BACKGROUND,
+	);
+	let mut cell_quad = |min: Vec2, max: Vec2, color: Color| {
+		quad(
+			[Vec2::new(min.x, max.y), max, min, Vec2::new(max.x, min.y)].map(to_clip),
+			color,
+		);
+	};
+
+	// only cells that could be on screen, however the map is turned
+	let reach = automap.zoom * Vec2::new(viewport.z / viewport.w, 1.0).length();
+	let first = TileMap::cell_at(center - reach).max(IVec2::ZERO);
+	let last = TileMap::cell_at(center + reach).min(map.size().as_ivec2() - 1);
+	for y in first.y ..= last.y {
+		for x in first.x ..= last.x {
+			let cell = IVec2::new(x, y);
+			if !automap.is_explored(cell) {
+				continue;
+			}
+			let min = cell.as_vec2();
+			match map.get(cell) {
+				Some(Tile::Wall(_)) => cell_quad(min, min + 1.0, WALL),
+				Some(Tile::Door { open }) => {
+					cell_quad(min, min + 1.0, FLOOR);
+					if !open {
+						// a bar across the gap, between the walls either side
+						let bar = if map.is_solid(cell + IVec2::X) {
+							Vec2::new(0.5, 0.1)
+						} else {
+							Vec2::new(0.1, 0.5)
+						};
+						cell_quad(min + 0.5 - bar, min + 0.5 + bar, DOOR);
+					}
+				},
+				_ => cell_quad(min, min + 1.0, FLOOR),
+			}
+		}
+	}
+
+	for &(position, color) in &markers.0 {
+		if automap.is_explored(TileMap::cell_at(position)) {
+			cell_quad(position - 0.15, position + 0.15, color);
+		}
+	}
+
+	let arrow_right = Vec2::new(forward.y, -forward.x);
+	let arrow = [
+		center + forward * 0.4,
+		center - forward * 0.3 - arrow_right * 0.25,
+		center - forward * 0.3 + arrow_right * 0.25,
+	];
+	vertices.extend(arrow.map(|corner| AutomapVertex {
+		position: to_clip(corner),
+		color: PLAYER,
+	}));
+
+	let needed = size_of_val(vertices.as_slice());
+	if (automap_pipelines.vertices.size() as usize) < needed {
+		automap_pipelines.vertices = create_vertex_buffer(&ctx, vertices.len().next_power_of_two());
+	}
+	ctx.write_buffer(
+		&automap_pipelines.vertices,
+		0,
+		bytemuck::cast_slice(&vertices),
+	);
+	automap_pipelines.vertex_count = vertices.len() as _;
+}
+
+fn draw_automap(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	automap_pipelines: NonSend<AutomapPipelines>,
+	mut profiler: NonSendMut<GpuProfiler>,
+) {
+	if automap_pipelines.vertex_count == 0 {
+		return;
+	}
+
+	let [x, y, width, height] = automap_pipelines.viewport.to_array();
+	let mut pass = frame.begin_pass("automap", &mut profiler);
+	pass.set_pipeline(&automap_pipelines.pipeline);
+	ctx.record_pipeline_switch();
+	pass.set_viewport(x, y, width, height, 0.0, 1.0);
+	pass.set_vertex_buffer(0, automap_pipelines.vertices.slice(..));
+	pass.draw(0 .. automap_pipelines.vertex_count, 0 .. 1);
+	ctx.record_draw(1);
+	drop(pass);
+	profiler.end_pass();
+}
@@ -0,0 +1,445 @@
+//! Cameras and the per-camera views they are rendered through. Each active
+//! camera gets its own slot in the uniforms buffer and its own run of the
+//! [`RenderView`] schedule, drawing into its viewport of the canvas or of its
+//! offscreen [`RenderTarget`].
+
+use std::ops::Range;
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		Color,
+		GraphicsContext,
+		Pipelines,
+		Render,
+		RenderSet,
+		SpriteBatch,
+		areas::AreaVisibility,
+		culling::Frustum,
+		profiling::GpuProfiler,
+		render_target::RenderTargets,
+		shader,
+		textures::TextureId,
+		vertical_fov,
+		visibility::RenderLayers,
+	},
+	prelude::*,
+	transform::Transform,
+};
+
+/// Most cameras rendered in a frame. Further cameras are skipped.
+pub const MAX_VIEWS: usize = 8;
+
+/// What a camera's viewport is cleared to before it is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClearMode {
+	Color(Color),
+	/// Keeps what cameras drawn earlier left in the viewport, but not their
+	/// depth, for overlays like a weapon view.
+	DepthOnly,
+	/// Draws over earlier cameras, depth and all.
+	None,
+}
+
+/// Where a camera draws to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RenderTarget {
+	#[default]
+	Canvas,
+	/// A sprite texture added with
+	/// [`SpriteTextures::add_render_target`](crate::gfx::textures::SpriteTextures::add_render_target),
+	/// updated every frame for sprites to show. The view is drawn at `size`
+	/// pixels, then scaled to fit the texture.
+	Texture { texture: TextureId, size: UVec2 },
+}
+
+#[derive(Clone, Copy, Debug, Component)]
+pub struct Camera {
+	pub target: RenderTarget,
+	/// Part of the target drawn to, in `0.0 ..= 1.0` with the origin at the
+	/// top left.
+	pub viewport: Rect,
+	/// Cameras are drawn in increasing order of priority, so higher ones end
+	/// up on top.
+	pub priority: i32,
+	pub clear: ClearMode,
+	pub active: bool,
+}
+
+impl Default for Camera {
+	fn default() -> Self {
+		Self {
+			target: RenderTarget::Canvas,
+			viewport: Rect::new(0.0, 0.0, 1.0, 1.0),
+			priority: 0,
+			clear: ClearMode::Color(Color::BLACK),
+			active: true,
+		}
+	}
+}
+
+/// A camera as rendered this frame.
+#[derive(Clone, Debug)]
+pub struct View {
+	pub entity: Entity,
+	pub target: RenderTarget,
+	/// Size of the target in pixels.
+	pub target_size: Vec2,
+	/// Target pixels drawn to.
+	pub viewport: Rect,
+	pub clear: ClearMode,
+	pub layers: RenderLayers,
+	pub eye: Vec3,
+	pub projection: Mat4,
+	pub view: Mat4,
+	pub frustum: Frustum,
+	/// Areas of the map seen from the camera, filled in after the view is
+	/// collected.
+	pub areas: AreaVisibility,
+	/// Dynamic offset of this view's uniforms in [`Pipelines::uniforms`].
+	pub uniforms_offset: u32,
+	/// This view's sprite instances in [`Pipelines::instances`], one run per
+	/// material.
+	pub(crate) sprite_batches: Vec<SpriteBatch>,
+}
+
+impl View {
+	/// The world-space ray through `position` in target pixels, starting on
+	/// the near plane, or `None` outside the viewport.
+	pub fn ray(&self, position: Vec2) -> Option<Ray3d> {
+		if !self.viewport.contains(position) {
+			return None;
+		}
+		let uv = (position - self.viewport.min) / self.viewport.size();
+		let ndc = Vec2::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+		let clip_to_world = (self.projection * self.view).inverse();
+		let near = clip_to_world.project_point3(ndc.extend(0.0));
+		let far = clip_to_world.project_point3(ndc.extend(1.0));
+		Some(Ray3d::new(near, Dir3::new(far - near).ok()?))
+	}
+}
+
+/// Views of the current frame, in drawing order: views rendering to textures
+/// come first so the canvas views can show them. The first canvas view is the
+/// main view, which things with only one point of view such as the raycaster
+/// and the automap follow.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Views(pub Vec<View>);
+
+impl Views {
+	pub fn main_index(&self) -> Option<usize> {
+		self.0
+			.iter()
+			.position(|view| view.target == RenderTarget::Canvas)
+	}
+
+	pub fn main(&self) -> Option<&View> {
+		self.main_index().map(|index| &self.0[index])
+	}
+}
+
+/// Index of the view being drawn in [`Views`], present only while
+/// [`RenderView`] runs.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ActiveView(pub usize);
+
+/// The view being drawn by systems in [`RenderView`].
+#[derive(SystemParam)]
+pub struct CurrentView<'w> {
+	views: Res<'w, Views>,
+	active: Res<'w, ActiveView>,
+}
+
+impl CurrentView<'_> {
+	pub fn index(&self) -> usize {
+		self.active.0
+	}
+
+	pub fn get(&self) -> &View {
+		&self.views.0[self.active.0]
+	}
+
+	pub fn is_main(&self) -> bool {
+		self.views.main_index() == Some(self.active.0)
+	}
+}
+
+/// Run once per view during [`RenderSet::Views`], with the view in
+/// [`ActiveView`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ScheduleLabel)]
+pub struct RenderView;
+
+/// Ordering of the systems within [`RenderView`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, SystemSet)]
+pub enum ViewSet {
+	Clear,
+	World,
+	/// Alpha-blended geometry, drawn over the opaque world. Sprites and
+	/// particles are each sorted back to front and decals drawn oldest first,
+	/// but each of them is drawn in turn rather than sorted against the
+	/// others.
+	Transparent,
+	Overlay,
+	/// Copies offscreen views into their target textures.
+	Resolve,
+}
+
+/// A camera as copied out of the world by
+/// [`extract_frame`](crate::gfx::extract_frame).
+#[derive(Clone, Debug)]
+pub(crate) struct ExtractedCamera {
+	pub entity: Entity,
+	pub camera: Camera,
+	pub transform: Transform,
+	pub layers: RenderLayers,
+}
+
+/// Works out the views of every active camera for a canvas of `canvas`
+/// pixels, leaving their sprite batches empty and their areas unculled.
+pub(crate) fn collect_views(
+	cameras: &[ExtractedCamera],
+	canvas: Vec2,
+	uniforms_stride: u32,
+) -> Vec<View> {
+	let mut cameras: Vec<_> = cameras
+		.iter()
+		.filter(|extracted| extracted.camera.active)
+		.collect();
+	cameras.sort_by_key(|extracted| {
+		(
+			extracted.camera.target == RenderTarget::Canvas,
+			extracted.camera.priority,
+		)
+	});
+	if cameras.len() > MAX_VIEWS {
+		log::warn!(
+			"only the first {MAX_VIEWS} of {} cameras are drawn",
+			cameras.len()
+		);
+		cameras.truncate(MAX_VIEWS);
+	}
+
+	cameras
+		.into_iter()
+		.enumerate()
+		.map(|(index, extracted)| {
+			let ExtractedCamera {
+				entity,
+				camera,
+				transform,
+				layers,
+			} = extracted;
+			let target_size = match camera.target {
+				RenderTarget::Canvas => canvas,
+				RenderTarget::Texture { size, .. } => size.as_vec2(),
+			};
+			let viewport = Rect::from_corners(
+				camera.viewport.min * target_size,
+				camera.viewport.max * target_size,
+			);
+			let aspect = viewport.width() / viewport.height().max(1.0);
+			let projection = Mat4::perspective_rh(vertical_fov(aspect), aspect, 0.01, 1000.0);
+			let view = transform.as_view_matrix();
+			View {
+				entity: *entity,
+				target: camera.target,
+				target_size,
+				viewport,
+				clear: camera.clear,
+				layers: *layers,
+				eye: transform.translation,
+				projection,
+				view,
+				frustum: Frustum::from_view_projection(projection * view),
+				areas: AreaVisibility::ALL,
+				uniforms_offset: index as u32 * uniforms_stride,
+				sprite_batches: Vec::new(),
+			}
+		})
+		.collect()
+}
+
+impl ActiveFrame {
+	/// Begins a render pass limited to `view`'s viewport.
+	/// [`GpuProfiler::end_pass`] must be called after the returned pass is
+	/// dropped.
+	pub fn begin_view_pass(
+		&mut self,
+		label: &'static str,
+		profiler: &mut GpuProfiler,
+		view: &View,
+	) -> wgpu::RenderPass<'_> {
+		let mut pass = self.begin_pass(label, profiler);
+		let Rect { min, max } = view.viewport;
+		pass.set_viewport(min.x, min.y, max.x - min.x, max.y - min.y, 0.0, 1.0);
+		pass
+	}
+}
+
+/// Fills a viewport with a constant colour and the far depth, for clearing
+/// views that don't cover the whole canvas.
+pub struct ClearPipelines {
+	color: wgpu::RenderPipeline,
+	depth: wgpu::RenderPipeline,
+}
+
+impl ClearPipelines {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		create_clear_pipelines(ctx, &shader::create_shader_module(ctx, "clear.wgsl", &[]))
+	}
+}
+
+fn create_clear_pipelines(
+	ctx: &GraphicsContext,
+	shader_module: &wgpu::ShaderModule,
+) -> ClearPipelines {
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("clear render layout"),
+			bind_group_layouts: &[],
+			push_constant_ranges: &[],
+		});
+	// the colour comes from the blend constant
+	let blend = wgpu::BlendComponent {
+		src_factor: wgpu::BlendFactor::Constant,
+		dst_factor: wgpu::BlendFactor::Zero,
+		operation: wgpu::BlendOperation::Add,
+	};
+	let create_pipeline = |label, write_mask| {
+		ctx.device
+			.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+				label: Some(label),
+				layout: Some(&pipeline_layout),
+				depth_stencil: Some(wgpu::DepthStencilState {
+					format: wgpu::TextureFormat::Depth24Plus,
+					depth_write_enabled: true,
+					depth_compare: wgpu::CompareFunction::Always,
+					stencil: default(),
+					bias: default(),
+				}),
+				multisample: wgpu::MultisampleState::default(),
+				multiview: None,
+				cache: None,
+				primitive: wgpu::PrimitiveState::default(),
+				vertex: wgpu::VertexState {
+					module: shader_module,
+					compilation_options: wgpu::PipelineCompilationOptions::default(),
+					entry_point: None,
+					buffers: &[],
+				},
+				fragment: Some(wgpu::FragmentState {
+					module: shader_module,
+					compilation_options: wgpu::PipelineCompilationOptions::default(),
+					entry_point: None,
+					targets: &[Some(wgpu::ColorTargetState {
+						format: ctx.surface_format(),
+						blend: Some(wgpu::BlendState {
+							color: blend,
+							alpha: blend,
+						}),
+						write_mask,
+					})],
+				}),
+			})
+	};
+
+	ClearPipelines {
+		color: create_pipeline("clear color pipeline", wgpu::ColorWrites::ALL),
+		depth: create_pipeline("clear depth pipeline", wgpu::ColorWrites::empty()),
+	}
+}
+
+#[cfg(debug_assertions)]
+fn reload_clear_pipelines(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	if changes
+		.read()
+		.any(|change| change.affects("clear.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"clear.wgsl",
+			&[],
+			|shader_module| create_clear_pipelines(&ctx, shader_module),
+			|world, pipelines| world.insert_non_send_resource(pipelines),
+		);
+	}
+}
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<Views>();
+	app.init_schedule(RenderView);
+	app.configure_sets(
+		RenderView,
+		(
+			ViewSet::Clear,
+			ViewSet::World,
+			ViewSet::Transparent,
+			ViewSet::Overlay,
+			ViewSet::Resolve,
+		)
+			.chain(),
+	);
+	app.add_systems(Render, draw_views.in_set(RenderSet::Views));
+	app.add_systems(RenderView, clear_view.in_set(ViewSet::Clear));
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_clear_pipelines);
+}
+
+fn draw_views(world: &mut World) {
+	for index in 0 .. world.resource::<Views>().0.len() {
+		let offscreen = match world.resource::<Views>().0[index].target {
+			RenderTarget::Canvas => None,
+			RenderTarget::Texture { texture, size } => {
+				let targets = world.non_send_resource::<RenderTargets>();
+				let Some(target) = targets.frame_target(texture, size) else {
+					continue;
+				};
+				Some(target)
+			},
+		};
+		world.non_send_resource_mut::<ActiveFrame>().offscreen = offscreen;
+		world.insert_resource(ActiveView(index));
+		world.run_schedule(RenderView);
+	}
+	world.non_send_resource_mut::<ActiveFrame>().offscreen = None;
+	world.remove_resource::<ActiveView>();
+}
+
+pub(super) fn clear_view(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	clear: NonSend<ClearPipelines>,
+	view: CurrentView,
+	mut profiler: NonSendMut<GpuProfiler>,
+) {
+	let view = view.get();
+	let covers_target = view.viewport.min.cmple(Vec2::ZERO).all() &&
+		view.viewport.max.cmpge(view.target_size).all();
+
+	let (pipeline, color) = match view.clear {
+		ClearMode::Color(color) if covers_target => {
+			// cheaper to clear the whole attachment with the next pass
+			frame.clear(color);
+			return;
+		},
+		ClearMode::Color(color) => (&clear.color, color),
+		ClearMode::DepthOnly => (&clear.depth, Color::BLACK),
+		ClearMode::None => return,
+	};
+
+	let mut pass = frame.begin_view_pass("clear", &mut profiler, view);
+	pass.set_pipeline(pipeline);
+	pass.set_blend_constant(color.to_wgpu());
+	pass.draw(0 .. 3, 0 .. 1);
+	ctx.record_pipeline_switch();
+	ctx.record_draw(1);
+	drop(pass);
+	profiler.end_pass();
+}
@@ -0,0 +1,63 @@
+use crate::prelude::*;
+
+/// Linear RGBA colour, laid out as it is uploaded to shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Color {
+	pub r: f32,
+	pub g: f32,
+	pub b: f32,
+	pub a: f32,
+}
+
+impl Color {
+	pub const BLACK: Self = Self::rgb(0.0, 0.0, 0.0);
+	pub const BLUE: Self = Self::rgb(0.0, 0.0, 1.0);
+	pub const CYAN: Self = Self::rgb(0.0, 1.0, 1.0);
+	pub const GRAY: Self = Self::rgb(0.5, 0.5, 0.5);
+	pub const GREEN: Self = Self::rgb(0.0, 1.0, 0.0);
+	pub const MAGENTA: Self = Self::rgb(1.0, 0.0, 1.0);
+	pub const NONE: Self = Self::rgba(0.0, 0.0, 0.0, 0.0);
+	pub const RED: Self = Self::rgb(1.0, 0.0, 0.0);
+	pub const WHITE: Self = Self::rgb(1.0, 1.0, 1.0);
+	pub const YELLOW: Self = Self::rgb(1.0, 1.0, 0.0);
+
+	pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+		Self::rgba(r, g, b, 1.0)
+	}
+
+	pub const fn rgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+		Self { r, g, b, a }
+	}
+
+	pub const fn with_alpha(self, a: f32) -> Self {
+		let Self { r, g, b, .. } = self;
+		Self { r, g, b, a }
+	}
+
+	pub fn to_vec4(self) -> Vec4 {
+		Vec4::new(self.r, self.g, self.b, self.a)
+	}
+
+	pub fn to_wgpu(self) -> wgpu::Color {
+		let Self { r, g, b, a } = self;
+		wgpu::Color {
+			r: r as _,
+			g: g as _,
+			b: b as _,
+			a: a as _,
+		}
+	}
+}
+
+impl Default for Color {
+	fn default() -> Self {
+		Self::WHITE
+	}
+}
+
+impl From<Vec4> for Color {
+	fn from(value: Vec4) -> Self {
+		Self::rgba(value.x, value.y, value.z, value.w)
+	}
+}
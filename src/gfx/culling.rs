@@ -0,0 +1,435 @@
+//! Frustum culling of sprite instances, done in a compute shader that writes a
+//! compacted instance buffer and indirect draw arguments when the device
+//! supports it, and on the CPU while building instances otherwise. Sorted
+//! batches are always culled on the CPU, as compaction would reorder them.
+
+use std::{num::NonZero, ops::Range};
+
+use wgpu::{BufferUsages, ShaderStages, util::DrawIndirectArgs};
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		GraphicsContext,
+		Pipelines,
+		Render,
+		RenderSet,
+		SpriteBatch,
+		SpriteInstance,
+		camera::{View, Views},
+		profiling::GpuProfiler,
+		shader,
+	},
+	prelude::*,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+/// Batches the culling buffers initially have room for.
+const INITIAL_SLOTS: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Frustum {
+	/// Inward-facing planes as `normal.xyz, distance`.
+	pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+	/// Extracts the frustum planes of a combined projection and view matrix,
+	/// for a `0 .. 1` clip space depth range.
+	pub fn from_view_projection(view_projection: Mat4) -> Self {
+		let rows = [
+			view_projection.row(0),
+			view_projection.row(1),
+			view_projection.row(2),
+			view_projection.row(3),
+		];
+		let planes = [
+			rows[3] + rows[0],
+			rows[3] - rows[0],
+			rows[3] + rows[1],
+			rows[3] - rows[1],
+			rows[2],
+			rows[3] - rows[2],
+		]
+		.map(|plane| plane / plane.truncate().length());
+		Self { planes }
+	}
+
+	pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+		self.planes
+			.iter()
+			.all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+	}
+}
+
+/// Bounding sphere radius of a sprite instance's quad, however it is turned.
+pub(crate) fn instance_radius(instance: &SpriteInstance) -> f32 {
+	instance.size.length() / 2.0
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(super) struct CullParams {
+	pub(super) planes: [Vec4; 6],
+	/// The batch's run of instances, which its visible instances are written
+	/// to the same place in.
+	pub(super) first: u32,
+	pub(super) count: u32,
+	/// The batch's draw arguments.
+	pub(super) slot: u32,
+	_padding: u32,
+}
+
+struct GpuCulling {
+	/// One [`CullParams`] per batch, `params_stride` bytes apart.
+	params: wgpu::Buffer,
+	params_stride: u32,
+	visible: wgpu::Buffer,
+	/// One [`DrawIndirectArgs`] per batch.
+	args: wgpu::Buffer,
+	/// Batches `params` and `args` have room for.
+	slots: usize,
+	bind_group_layout: wgpu::BindGroupLayout,
+	/// Rebuilt whenever the instance buffer it reads from is replaced.
+	bind_group: Option<wgpu::BindGroup>,
+	pipeline: wgpu::ComputePipeline,
+}
+
+pub struct SpriteCulling {
+	gpu: Option<GpuCulling>,
+}
+
+impl SpriteCulling {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		if !ctx.supports_compute {
+			log::info!("compute shaders unavailable, culling sprites on the CPU");
+			return Self { gpu: None };
+		}
+
+		let params_stride = (size_of::<CullParams>() as u32)
+			.next_multiple_of(ctx.device.limits().min_uniform_buffer_offset_alignment);
+		let params = create_params_buffer(ctx, params_stride, INITIAL_SLOTS);
+		let args = create_args_buffer(ctx, INITIAL_SLOTS);
+
+		let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+			binding,
+			count: None,
+			visibility: ShaderStages::COMPUTE,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Storage { read_only },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+		};
+		let bind_group_layout =
+			ctx.device
+				.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+					label: Some("cull layout"),
+					entries: &[
+						wgpu::BindGroupLayoutEntry {
+							binding: 0,
+							count: None,
+							visibility: ShaderStages::COMPUTE,
+							ty: wgpu::BindingType::Buffer {
+								ty: wgpu::BufferBindingType::Uniform,
+								has_dynamic_offset: true,
+								min_binding_size: NonZero::new(size_of::<CullParams>() as _),
+							},
+						},
+						storage_entry(1, true),
+						storage_entry(2, false),
+						storage_entry(3, false),
+					],
+				});
+
+		let pipeline = create_pipeline(
+			ctx,
+			&bind_group_layout,
+			&shader::create_shader_module(ctx, "cull.wgsl", &[]),
+		);
+
+		Self {
+			gpu: Some(GpuCulling {
+				params,
+				params_stride,
+				visible: create_visible_buffer(ctx, 64),
+				args,
+				slots: INITIAL_SLOTS,
+				bind_group_layout,
+				bind_group: None,
+				pipeline,
+			}),
+		}
+	}
+
+	pub fn is_gpu(&self) -> bool {
+		self.gpu.is_some()
+	}
+
+	/// Whether `batch` is culled on the GPU and drawn indirectly.
+	pub(crate) fn culls_on_gpu(&self, batch: &SpriteBatch) -> bool {
+		self.gpu.is_some() && !batch.sorted
+	}
+
+	/// Uploads this frame's culling inputs. `instances` is the buffer the
+	/// instances were just written to, holding the views' batches.
+	pub(crate) fn prepare(
+		&mut self,
+		ctx: &GraphicsContext,
+		views: &[View],
+		instances: &wgpu::Buffer,
+		instances_replaced: bool,
+	) {
+		let Some(gpu) = &mut self.gpu else {
+			return;
+		};
+
+		let batches = || views.iter().flat_map(|view| &view.sprite_batches);
+		let count = batches()
+			.map(|batch| batch.instances.end)
+			.max()
+			.unwrap_or(0);
+		let slots = batches().count();
+		let needed = (size_of::<SpriteInstance>() * count as usize) as u64;
+		let mut rebind = instances_replaced || gpu.bind_group.is_none();
+		if gpu.visible.size() < needed {
+			gpu.visible = create_visible_buffer(ctx, (count as usize).next_power_of_two());
+			rebind = true;
+		}
+		if gpu.slots < slots {
+			gpu.slots = slots.next_power_of_two();
+			gpu.params = create_params_buffer(ctx, gpu.params_stride, gpu.slots);
+			gpu.args = create_args_buffer(ctx, gpu.slots);
+			rebind = true;
+		}
+		if rebind {
+			gpu.bind_group = Some(ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+				label: Some("cull group"),
+				layout: &gpu.bind_group_layout,
+				entries: &[
+					wgpu::BindGroupEntry {
+						binding: 0,
+						resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+							buffer: &gpu.params,
+							offset: 0,
+							size: NonZero::new(size_of::<CullParams>() as _),
+						}),
+					},
+					wgpu::BindGroupEntry {
+						binding: 1,
+						resource: instances.as_entire_binding(),
+					},
+					wgpu::BindGroupEntry {
+						binding: 2,
+						resource: gpu.visible.as_entire_binding(),
+					},
+					wgpu::BindGroupEntry {
+						binding: 3,
+						resource: gpu.args.as_entire_binding(),
+					},
+				],
+			}));
+		}
+
+		for view in views {
+			for batch in view.sprite_batches.iter().filter(|batch| !batch.sorted) {
+				let params = CullParams {
+					planes: view.frustum.planes,
+					first: batch.instances.start,
+					count: batch.instances.len() as _,
+					slot: batch.slot,
+					_padding: 0,
+				};
+				ctx.write_buffer(
+					&gpu.params,
+					(batch.slot * gpu.params_stride) as _,
+					bytemuck::bytes_of(&params),
+				);
+			}
+		}
+		if slots > 0 {
+			let args = DrawIndirectArgs {
+				vertex_count: 4,
+				instance_count: 0,
+				first_vertex: 0,
+				first_instance: 0,
+			};
+			let args = args.as_bytes().repeat(slots);
+			ctx.write_buffer(&gpu.args, 0, &args);
+		}
+	}
+
+	/// Binds the instances of `batch` and issues its draw, indirectly when
+	/// culled on the GPU.
+	pub(crate) fn draw(
+		&self,
+		pass: &mut wgpu::RenderPass<'_>,
+		instances: &wgpu::Buffer,
+		batch: &SpriteBatch,
+	) {
+		let range = batch.instances.clone();
+		if range.is_empty() {
+			return;
+		}
+		let stride = size_of::<SpriteInstance>() as u64;
+		match &self.gpu {
+			Some(gpu) if self.culls_on_gpu(batch) => {
+				pass.set_vertex_buffer(0, gpu.visible.slice(range.start as u64 * stride ..));
+				pass.draw_indirect(
+					&gpu.args,
+					(batch.slot as usize * size_of::<DrawIndirectArgs>()) as _,
+				);
+			},
+			_ => {
+				pass.set_vertex_buffer(
+					0,
+					instances.slice(range.start as u64 * stride .. range.end as u64 * stride),
+				);
+				pass.draw(0 .. 4, 0 .. range.len() as _);
+			},
+		}
+	}
+}
+
+fn create_pipeline(
+	ctx: &GraphicsContext,
+	bind_group_layout: &wgpu::BindGroupLayout,
+	shader_module: &wgpu::ShaderModule,
+) -> wgpu::ComputePipeline {
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("cull layout"),
+			bind_group_layouts: &[bind_group_layout],
+			push_constant_ranges: &[],
+		});
+	ctx.device
+		.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+			label: Some("cull pipeline"),
+			layout: Some(&pipeline_layout),
+			module: shader_module,
+			entry_point: None,
+			compilation_options: wgpu::PipelineCompilationOptions::default(),
+			cache: None,
+		})
+}
+
+#[cfg(debug_assertions)]
+fn reload_pipeline(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	culling: NonSend<SpriteCulling>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	let Some(gpu) = &culling.gpu else {
+		return;
+	};
+	if changes
+		.read()
+		.any(|change| change.affects("cull.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"cull.wgsl",
+			&[],
+			|shader_module| create_pipeline(&ctx, &gpu.bind_group_layout, shader_module),
+			|world, pipeline| {
+				if let Some(gpu) = &mut world.non_send_resource_mut::<SpriteCulling>().gpu {
+					gpu.pipeline = pipeline;
+				}
+			},
+		);
+	}
+}
+
+fn create_params_buffer(ctx: &GraphicsContext, stride: u32, slots: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("cull params"),
+		size: (stride as usize * slots) as _,
+		usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
+
+fn create_args_buffer(ctx: &GraphicsContext, slots: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("sprite draw args"),
+		size: (size_of::<DrawIndirectArgs>() * slots) as _,
+		usage: BufferUsages::STORAGE |
+			BufferUsages::INDIRECT |
+			BufferUsages::COPY_SRC |
+			BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
+
+fn create_visible_buffer(ctx: &GraphicsContext, length: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("visible instances"),
+		size: (size_of::<SpriteInstance>() * length) as _,
+		usage: BufferUsages::STORAGE | BufferUsages::VERTEX,
+		mapped_at_creation: false,
+	})
+}
+
+pub(super) fn setup(app: &mut App) {
+	app.add_systems(Render, cull_sprites.in_set(RenderSet::Compute));
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_pipeline);
+}
+
+fn cull_sprites(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	culling: NonSend<SpriteCulling>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	views: Res<Views>,
+) {
+	let Some(gpu) = &culling.gpu else {
+		return;
+	};
+	let Some(bind_group) = &gpu.bind_group else {
+		return;
+	};
+	let mut batches = views
+		.0
+		.iter()
+		.flat_map(|view| &view.sprite_batches)
+		.filter(|batch| !batch.sorted)
+		.peekable();
+	if batches.peek().is_none() {
+		return;
+	}
+
+	let slots = views
+		.0
+		.iter()
+		.map(|view| view.sprite_batches.len())
+		.sum::<usize>();
+	let submitted = batches
+		.clone()
+		.map(|batch| batch.instances.len())
+		.sum::<usize>();
+	profiler.read_back_draws(&gpu.args, slots as _, submitted as _);
+
+	let mut pass = frame
+		.encoder
+		.begin_compute_pass(&wgpu::ComputePassDescriptor {
+			label: Some("cull"),
+			timestamp_writes: profiler.begin_compute_pass("cull"),
+		});
+	pass.set_pipeline(&gpu.pipeline);
+	ctx.record_pipeline_switch();
+	for batch in batches {
+		pass.set_bind_group(0, bind_group, &[batch.slot * gpu.params_stride]);
+		pass.dispatch_workgroups(
+			batch.instances.len().div_ceil(WORKGROUP_SIZE as usize) as _,
+			1,
+			1,
+		);
+	}
+	drop(pass);
+	profiler.end_pass();
+}
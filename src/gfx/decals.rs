@@ -0,0 +1,323 @@
+//! Decals such as bullet holes and blood splats: small quads laid flat against
+//! wall faces, kept in a fixed-size ring buffer that drops the oldest decal
+//! to make room for new ones.
+
+use std::{collections::VecDeque, ops::Range};
+
+use wgpu::BufferUsages;
+
+use crate::{
+	gfx::{
+		ActiveFrame,
+		Color,
+		GraphicsContext,
+		Pipelines,
+		RenderPre,
+		RenderPreSet,
+		SpriteInstance,
+		WorldRenderer,
+		camera::{CurrentView, RenderView, ViewSet, Views},
+		particles::draw_particles,
+		prepare_frame,
+		profiling::GpuProfiler,
+		textures::{SPRITE_TEXTURE_SIZE, SpriteTextures, Texel, TextureId},
+		visibility::RenderLayers,
+	},
+	map::TileMap,
+	prelude::*,
+	transform::Transform,
+};
+
+/// Most decals kept at once.
+pub const MAX_DECALS: usize = 256;
+/// Distance decals are lifted off the surface they're placed on, so they win
+/// the `LessEqual` depth test against it.
+pub const DECAL_OFFSET: f32 = 0.002;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DecalParams {
+	pub size: Vec2,
+	pub texture: Option<TextureId>,
+	pub color: Color,
+	/// Seconds the decal lasts, or forever if `None`.
+	pub lifetime: Option<f32>,
+	/// Seconds over which the decal fades out at the end of its lifetime.
+	pub fade: f32,
+	/// Layers of the cameras that draw the decal.
+	pub layers: RenderLayers,
+}
+
+impl Default for DecalParams {
+	fn default() -> Self {
+		Self {
+			size: Vec2::splat(0.1),
+			texture: None,
+			color: Color::WHITE,
+			lifetime: None,
+			fade: 1.0,
+			layers: RenderLayers::DEFAULT,
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+struct Decal {
+	transform: Transform,
+	params: DecalParams,
+	age: f32,
+}
+
+impl Decal {
+	fn alpha(&self) -> f32 {
+		let Some(lifetime) = self.params.lifetime else {
+			return 1.0;
+		};
+		let remaining = lifetime - self.age;
+		(remaining / self.params.fade.max(f32::EPSILON)).clamp(0.0, 1.0)
+	}
+
+	fn is_expired(&self) -> bool {
+		self.params
+			.lifetime
+			.is_some_and(|lifetime| self.age >= lifetime)
+	}
+}
+
+/// Decals in the order they were placed.
+#[derive(Resource, Default)]
+pub struct Decals {
+	decals: VecDeque<Decal>,
+}
+
+impl Decals {
+	/// Places a decal on the surface at `point` facing along `normal`,
+	/// replacing the oldest decal if there are already [`MAX_DECALS`].
+	pub fn spawn(&mut self, point: Vec3, normal: Vec3, params: DecalParams) {
+		let decal = Decal {
+			transform: Transform::from_translation(point + normal * DECAL_OFFSET)
+				.looking_along(normal),
+			params,
+			age: 0.0,
+		};
+		if self.decals.len() == MAX_DECALS {
+			self.decals.pop_front();
+		}
+		self.decals.push_back(decal);
+	}
+
+	pub fn clear(&mut self) {
+		self.decals.clear();
+	}
+
+	pub fn len(&self) -> usize {
+		self.decals.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.decals.is_empty()
+	}
+}
+
+/// Textures for the built-in decals.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct DecalTextures {
+	pub bullet_hole: TextureId,
+	pub blood: TextureId,
+}
+
+impl DecalTextures {
+	pub(super) fn new(
+		ctx: &GraphicsContext,
+		textures: &mut SpriteTextures,
+	) -> Result<Self, String> {
+		let size = SPRITE_TEXTURE_SIZE as usize;
+		let texture = |texel: &dyn Fn(Vec2) -> Texel| -> Vec<Texel> {
+			(0 .. size * size)
+				.map(|index| {
+					let position = Vec2::new((index % size) as f32, (index / size) as f32) + 0.5;
+					texel(position / size as f32 * 2.0 - 1.0)
+				})
+				.collect()
+		};
+
+		let bullet_hole = texture(&|position| {
+			let distance = position.length();
+			if distance < 0.3 {
+				[16, 16, 16, 255]
+			} else if distance < 0.55 {
+				[64, 60, 56, 200]
+			} else {
+				[0; 4]
+			}
+		});
+		let blood = texture(&|position| {
+			// lumpy outline so splats don't read as perfect circles
+			let angle = position.y.atan2(position.x);
+			let edge = 0.7 + (angle * 5.0).sin() * 0.12 + (angle * 3.0 + 1.0).sin() * 0.08;
+			if position.length() < edge {
+				[120, 8, 8, 230]
+			} else {
+				[0; 4]
+			}
+		});
+
+		Ok(Self {
+			bullet_hole: textures.add(ctx, &bullet_hole)?,
+			blood: textures.add(ctx, &blood)?,
+		})
+	}
+}
+
+pub struct DecalInstances {
+	buffer: wgpu::Buffer,
+	/// Each view's run of instances.
+	ranges: Vec<Range<u32>>,
+}
+
+impl DecalInstances {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		Self {
+			buffer: create_instances_buffer(ctx, MAX_DECALS),
+			ranges: Vec::new(),
+		}
+	}
+}
+
+fn create_instances_buffer(ctx: &GraphicsContext, length: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("decal instances"),
+		size: (size_of::<SpriteInstance>() * length) as _,
+		usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
+
+/// Instances of every decal, lit by the map, with their layers.
+#[derive(Resource, Default)]
+struct ExtractedDecals(Vec<(SpriteInstance, RenderLayers)>);
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<Decals>();
+	app.init_resource::<ExtractedDecals>();
+	app.add_systems(Update, age_decals);
+	app.add_systems(
+		RenderPre,
+		(
+			extract_decals.in_set(RenderPreSet::Extract),
+			prepare_decals
+				.in_set(RenderPreSet::Prepare)
+				.after(prepare_frame),
+		),
+	);
+	app.add_systems(
+		RenderView,
+		draw_decals
+			.in_set(ViewSet::Transparent)
+			.before(draw_particles)
+			.run_if(resource_equals(WorldRenderer::Polygonal)),
+	);
+}
+
+fn age_decals(mut decals: ResMut<Decals>, time: Res<Time<Virtual>>) {
+	if decals.is_empty() {
+		return;
+	}
+	let delta = time.delta_secs();
+	for decal in &mut decals.decals {
+		decal.age += delta;
+	}
+	decals.decals.retain(|decal| !decal.is_expired());
+}
+
+fn extract_decals(
+	mut extracted: ResMut<ExtractedDecals>,
+	decals: Res<Decals>,
+	map: Option<Res<TileMap>>,
+) {
+	extracted.0.clear();
+	for decal in &decals.decals {
+		let DecalParams {
+			size,
+			texture,
+			color,
+			layers,
+			..
+		} = decal.params;
+		let position = decal.transform.translation;
+		let light = map.as_ref().map_or(1.0, |map| map.light_at(position));
+		let instance = SpriteInstance {
+			model: decal.transform.as_model_matrix(),
+			size,
+			billboard: 0,
+			texture: TextureId::instance_index(texture),
+			tint: color.with_alpha(color.a * decal.alpha()),
+			uv_rect: SpriteInstance::FULL_UV_RECT,
+			light,
+			flags: TextureId::instance_flags(texture),
+			_padding: default(),
+		};
+		extracted.0.push((instance, layers));
+	}
+}
+
+fn prepare_decals(
+	ctx: NonSend<GraphicsContext>,
+	mut decal_instances: NonSendMut<DecalInstances>,
+	extracted: Res<ExtractedDecals>,
+	views: Res<Views>,
+	mut instances: Local<Vec<SpriteInstance>>,
+) {
+	// few enough to draw every one on a view's layers rather than cull them
+	decal_instances.ranges.clear();
+	instances.clear();
+	for view in &views.0 {
+		let first = instances.len() as u32;
+		instances.extend(
+			extracted
+				.0
+				.iter()
+				.filter(|(_, layers)| layers.intersects(view.layers))
+				.map(|&(instance, _)| instance),
+		);
+		decal_instances.ranges.push(first .. instances.len() as u32);
+	}
+
+	if instances.is_empty() {
+		return;
+	}
+	let bytes = bytemuck::cast_slice::<SpriteInstance, u8>(&instances);
+	if (decal_instances.buffer.size() as usize) < bytes.len() {
+		decal_instances.buffer = create_instances_buffer(&ctx, instances.len().next_power_of_two());
+	}
+	ctx.write_buffer(&decal_instances.buffer, 0, bytes);
+}
+
+pub(super) fn draw_decals(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	decal_instances: NonSend<DecalInstances>,
+	textures: NonSend<SpriteTextures>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+) {
+	let Some(range) = decal_instances
+		.ranges
+		.get(view.index())
+		.filter(|range| !range.is_empty())
+	else {
+		return;
+	};
+
+	let view = view.get();
+	let mut pass = frame.begin_view_pass("decals", &mut profiler, view);
+	pass.set_pipeline(&pipelines.transparent_pipeline);
+	ctx.record_pipeline_switch();
+	pass.set_bind_group(0, &pipelines.uniforms_group, &[view.uniforms_offset]);
+	pass.set_bind_group(1, &textures.group, &[]);
+	pass.set_vertex_buffer(0, decal_instances.buffer.slice(..));
+	pass.draw(0 .. 4, range.clone());
+	ctx.record_draw(range.len() as _);
+	drop(pass);
+	profiler.end_pass();
+}
@@ -0,0 +1,385 @@
+use std::f32::consts::TAU;
+
+use wgpu::BufferUsages;
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		Color,
+		GraphicsContext,
+		Pipelines,
+		RenderPre,
+		RenderPreSet,
+		camera::{CurrentView, RenderView, ViewSet},
+		profiling::GpuProfiler,
+		shader,
+	},
+	prelude::*,
+	transform::Transform,
+};
+
+const CIRCLE_SEGMENTS: usize = 24;
+
+#[derive(Resource)]
+pub struct GizmoConfig {
+	pub enabled: bool,
+	/// Whether gizmos are hidden behind world geometry.
+	pub depth_test: bool,
+}
+
+impl Default for GizmoConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			depth_test: false,
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoVertex {
+	position: Vec3,
+	color: Color,
+}
+
+/// Lines queued for the current frame.
+#[derive(Resource, Default)]
+struct GizmoStorage(Vec<GizmoVertex>);
+
+/// Queues world-space debug lines to be drawn over the current frame.
+///
+/// Lines are discarded after each rendered frame, so systems in
+/// [`FixedUpdate`] will see their gizmos flicker when ticks and frames don't
+/// line up.
+#[derive(SystemParam)]
+pub struct Gizmos<'w> {
+	storage: ResMut<'w, GizmoStorage>,
+	config: Res<'w, GizmoConfig>,
+}
+
+impl Gizmos<'_> {
+	pub fn line(&mut self, start: Vec3, end: Vec3, color: Color) {
+		if !self.config.enabled {
+			return;
+		}
+		self.storage.0.extend([
+			GizmoVertex {
+				position: start,
+				color,
+			},
+			GizmoVertex {
+				position: end,
+				color,
+			},
+		]);
+	}
+
+	pub fn ray(&mut self, origin: Vec3, direction: Vec3, color: Color) {
+		self.line(origin, origin + direction, color);
+	}
+
+	pub fn linestrip(&mut self, points: impl IntoIterator<Item = Vec3>, color: Color) {
+		let mut points = points.into_iter();
+		let Some(mut prev) = points.next() else {
+			return;
+		};
+		for point in points {
+			self.line(prev, point, color);
+			prev = point;
+		}
+	}
+
+	pub fn arrow(&mut self, start: Vec3, end: Vec3, color: Color) {
+		self.line(start, end, color);
+
+		let direction = end - start;
+		let length = direction.length();
+		if length <= f32::EPSILON {
+			return;
+		}
+		let direction = direction / length;
+		let side = direction.any_orthonormal_vector();
+		let head = length * 0.2;
+		for offset in [side, -side, direction.cross(side), -direction.cross(side)] {
+			self.line(end, end - direction * head + offset * head * 0.5, color);
+		}
+	}
+
+	/// Draws a circle around `normal`.
+	pub fn circle(&mut self, center: Vec3, normal: Vec3, radius: f32, color: Color) {
+		let (u, v) = normal.normalize_or(Transform::UP).any_orthonormal_pair();
+		self.linestrip(
+			(0 ..= CIRCLE_SEGMENTS).map(|i| {
+				let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+				center + (u * angle.cos() + v * angle.sin()) * radius
+			}),
+			color,
+		);
+	}
+
+	/// Draws a rectangle in the plane of a `Fixed` sprite with this transform.
+	pub fn rect(&mut self, transform: &Transform, size: Vec2, color: Color) {
+		let half = size / 2.0;
+		let model = transform.as_model_matrix();
+		let corners = [
+			Vec3::new(-half.x, 0.0, -half.y),
+			Vec3::new(half.x, 0.0, -half.y),
+			Vec3::new(half.x, 0.0, half.y),
+			Vec3::new(-half.x, 0.0, half.y),
+			Vec3::new(-half.x, 0.0, -half.y),
+		];
+		self.linestrip(corners.map(|corner| model.transform_point3(corner)), color);
+	}
+
+	/// Draws an oriented box of the given size centered on `transform`.
+	pub fn cuboid(&mut self, transform: &Transform, size: Vec3, color: Color) {
+		let half = size / 2.0;
+		let model = transform.as_model_matrix();
+		let corner = |i: usize| {
+			let sign = |bit: usize| if i & bit != 0 { 1.0 } else { -1.0 };
+			model.transform_point3(half * Vec3::new(sign(1), sign(2), sign(4)))
+		};
+		for i in 0 .. 8 {
+			for bit in [1, 2, 4] {
+				if i & bit == 0 {
+					self.line(corner(i), corner(i | bit), color);
+				}
+			}
+		}
+	}
+
+	pub fn aabb(&mut self, min: Vec3, max: Vec3, color: Color) {
+		let transform = Transform::from_translation((min + max) / 2.0);
+		self.cuboid(&transform, max - min, color);
+	}
+
+	/// Draws the right (red), forward (green) and up (blue) axes of
+	/// `transform`.
+	pub fn axes(&mut self, transform: &Transform, length: f32) {
+		let origin = transform.translation;
+		let rotation = transform.rotation;
+		self.arrow(
+			origin,
+			origin + rotation * Transform::RIGHT * length,
+			Color::RED,
+		);
+		self.arrow(
+			origin,
+			origin + rotation * Transform::FORWARD * length,
+			Color::GREEN,
+		);
+		self.arrow(
+			origin,
+			origin + rotation * Transform::UP * length,
+			Color::BLUE,
+		);
+	}
+}
+
+pub struct GizmoPipelines {
+	vertices: wgpu::Buffer,
+	vertex_count: u32,
+	depth_tested: wgpu::RenderPipeline,
+	overlay: wgpu::RenderPipeline,
+}
+
+impl GizmoPipelines {
+	pub fn new(ctx: &GraphicsContext, pipelines: &Pipelines) -> Self {
+		let (depth_tested, overlay) = create_pipelines(
+			ctx,
+			&pipelines.uniforms_layout,
+			&shader::create_shader_module(ctx, "gizmo.wgsl", &[]),
+		);
+
+		Self {
+			vertices: create_vertex_buffer(ctx, 1024),
+			vertex_count: 0,
+			depth_tested,
+			overlay,
+		}
+	}
+}
+
+/// The depth-tested and overlay pipelines.
+fn create_pipelines(
+	ctx: &GraphicsContext,
+	uniforms_layout: &wgpu::BindGroupLayout,
+	shader_module: &wgpu::ShaderModule,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("gizmo render layout"),
+			bind_group_layouts: &[uniforms_layout],
+			push_constant_ranges: &[],
+		});
+	let create_pipeline = |label, depth_compare| {
+		ctx.device
+			.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+				label: Some(label),
+				layout: Some(&pipeline_layout),
+				depth_stencil: Some(wgpu::DepthStencilState {
+					format: wgpu::TextureFormat::Depth24Plus,
+					depth_write_enabled: false,
+					depth_compare,
+					stencil: default(),
+					bias: default(),
+				}),
+				multisample: wgpu::MultisampleState::default(),
+				multiview: None,
+				cache: None,
+				primitive: wgpu::PrimitiveState {
+					topology: wgpu::PrimitiveTopology::LineList,
+					..default()
+				},
+				vertex: wgpu::VertexState {
+					module: shader_module,
+					compilation_options: wgpu::PipelineCompilationOptions::default(),
+					entry_point: None,
+					buffers: &[wgpu::VertexBufferLayout {
+						step_mode: wgpu::VertexStepMode::Vertex,
+						array_stride: size_of::<GizmoVertex>() as _,
+						attributes: &wgpu::vertex_attr_array![
+							0 => Float32x3,
+							1 => Float32x4,
+						],
+					}],
+				},
+				fragment: Some(wgpu::FragmentState {
+					module: shader_module,
+					compilation_options: wgpu::PipelineCompilationOptions::default(),
+					entry_point: None,
+					targets: &[Some(wgpu::ColorTargetState {
+						format: ctx.surface_format(),
+						blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+						write_mask: wgpu::ColorWrites::ALL,
+					})],
+				}),
+			})
+	};
+	(
+		create_pipeline(
+			"depth-tested gizmo pipeline",
+			wgpu::CompareFunction::LessEqual,
+		),
+		create_pipeline("overlay gizmo pipeline", wgpu::CompareFunction::Always),
+	)
+}
+
+#[cfg(debug_assertions)]
+fn reload_pipelines(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	if changes
+		.read()
+		.any(|change| change.affects("gizmo.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"gizmo.wgsl",
+			&[],
+			|shader_module| create_pipelines(&ctx, &pipelines.uniforms_layout, shader_module),
+			|world, (depth_tested, overlay)| {
+				let mut gizmos = world.non_send_resource_mut::<GizmoPipelines>();
+				gizmos.depth_tested = depth_tested;
+				gizmos.overlay = overlay;
+			},
+		);
+	}
+}
+
+fn create_vertex_buffer(ctx: &GraphicsContext, length: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("gizmo vertices"),
+		size: (size_of::<GizmoVertex>() * length) as _,
+		usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
+
+/// The lines queued for the frame being rendered.
+#[derive(Resource, Default)]
+pub(super) struct ExtractedGizmos {
+	vertices: Vec<GizmoVertex>,
+	depth_test: bool,
+}
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<GizmoConfig>();
+	app.init_resource::<GizmoStorage>();
+	app.init_resource::<ExtractedGizmos>();
+	app.add_systems(
+		RenderPre,
+		(
+			extract_gizmos.in_set(RenderPreSet::Extract),
+			upload_gizmos.in_set(RenderPreSet::Prepare),
+		),
+	);
+	app.add_systems(RenderView, draw_gizmos.in_set(ViewSet::Overlay));
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_pipelines);
+}
+
+fn extract_gizmos(
+	mut extracted: ResMut<ExtractedGizmos>,
+	mut storage: ResMut<GizmoStorage>,
+	config: Res<GizmoConfig>,
+) {
+	// swapped rather than copied, leaving the game an empty queue to refill
+	extracted.vertices.clear();
+	std::mem::swap(&mut extracted.vertices, &mut storage.0);
+	extracted.depth_test = config.depth_test;
+}
+
+fn upload_gizmos(
+	ctx: NonSend<GraphicsContext>,
+	mut pipelines: NonSendMut<GizmoPipelines>,
+	extracted: Res<ExtractedGizmos>,
+) {
+	let vertices = &extracted.vertices;
+	pipelines.vertex_count = vertices.len() as _;
+	if vertices.is_empty() {
+		return;
+	}
+
+	let needed = size_of_val(vertices.as_slice());
+	if (pipelines.vertices.size() as usize) < needed {
+		pipelines.vertices = create_vertex_buffer(&ctx, vertices.len().next_power_of_two());
+	}
+	ctx.write_buffer(&pipelines.vertices, 0, bytemuck::cast_slice(vertices));
+}
+
+pub(super) fn draw_gizmos(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	gizmo_pipelines: NonSend<GizmoPipelines>,
+	pipelines: NonSend<Pipelines>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	extracted: Res<ExtractedGizmos>,
+	view: CurrentView,
+) {
+	if gizmo_pipelines.vertex_count == 0 {
+		return;
+	}
+
+	let view = view.get();
+	let mut pass = frame.begin_view_pass("gizmos", &mut profiler, view);
+	pass.set_pipeline(if extracted.depth_test {
+		&gizmo_pipelines.depth_tested
+	} else {
+		&gizmo_pipelines.overlay
+	});
+	ctx.record_pipeline_switch();
+	pass.set_vertex_buffer(0, gizmo_pipelines.vertices.slice(..));
+	pass.set_bind_group(0, &pipelines.uniforms_group, &[view.uniforms_offset]);
+	pass.draw(0 .. gizmo_pipelines.vertex_count, 0 .. 1);
+	ctx.record_draw(1);
+	drop(pass);
+	profiler.end_pass();
+}
@@ -0,0 +1,201 @@
+use crate::{
+	gfx::{
+		Color,
+		GraphicsContext,
+		Pipelines,
+		RenderPre,
+		RenderPreSet,
+		camera::Views,
+		prepare_frame,
+	},
+	prelude::*,
+	transform::Transform,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FogMode {
+	#[default]
+	Off,
+	/// Fades in linearly between two distances from the camera.
+	Linear { start: f32, end: f32 },
+	/// Thickens exponentially with distance, `1 - e^(-density * distance)`.
+	Exponential { density: f32 },
+}
+
+/// Distance fog blended over everything drawn in the world pass. A black fog
+/// gives Doom-style darkening with depth.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct Fog {
+	pub mode: FogMode,
+	pub color: Color,
+}
+
+impl Fog {
+	/// Mode, start, end and density as laid out in the shader uniforms.
+	pub(crate) fn uniform_params(&self) -> (u32, f32, f32, f32) {
+		match self.mode {
+			FogMode::Off => (0, 0.0, 0.0, 0.0),
+			FogMode::Linear { start, end } => (1, start, end, 0.0),
+			FogMode::Exponential { density } => (2, 0.0, 0.0, density),
+		}
+	}
+}
+
+/// Most point lights that can affect a frame. When more are present, those
+/// closest to the camera win.
+pub const MAX_POINT_LIGHTS: usize = 16;
+
+/// Light radiating from the entity's `Transform`, falling off to nothing at
+/// `radius`.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct PointLight {
+	pub color: Color,
+	pub radius: f32,
+	pub intensity: f32,
+	/// How much the intensity randomly dips, from `0.0` (steady) to `1.0`
+	/// (flickering down to nothing).
+	pub flicker: f32,
+}
+
+impl Default for PointLight {
+	fn default() -> Self {
+		Self {
+			color: Color::WHITE,
+			radius: 4.0,
+			intensity: 1.0,
+			flicker: 0.0,
+		}
+	}
+}
+
+/// Fades a [`PointLight`] out over its duration and then despawns the entity,
+/// for short-lived lights such as muzzle flashes.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct LightFade {
+	pub duration: f32,
+	pub elapsed: f32,
+}
+
+impl LightFade {
+	pub fn new(duration: f32) -> Self {
+		Self {
+			duration,
+			elapsed: 0.0,
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub(super) struct PointLightData {
+	pub(super) position: Vec3,
+	pub(super) radius: f32,
+	/// Colour premultiplied by intensity.
+	pub(super) color: Vec3,
+	_padding: f32,
+}
+
+/// Matches `PointLights` in the WGSL sources.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct PointLightUniforms {
+	pub(super) count: u32,
+	_padding: [u32; 3],
+	pub(super) lights: [PointLightData; MAX_POINT_LIGHTS],
+}
+
+/// Every point light in the world, with flicker and fading applied.
+#[derive(Resource, Default)]
+struct ExtractedLights(Vec<PointLightData>);
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<Fog>();
+	app.init_resource::<ExtractedLights>();
+	app.add_systems(Update, fade_lights);
+	app.add_systems(
+		RenderPre,
+		(
+			extract_lights.in_set(RenderPreSet::Extract),
+			upload_lights
+				.in_set(RenderPreSet::Prepare)
+				.after(prepare_frame),
+		),
+	);
+}
+
+fn fade_lights(
+	mut cmd: Commands,
+	mut lights: Query<(Entity, &mut LightFade)>,
+	time: Res<Time<Virtual>>,
+) {
+	for (entity, mut fade) in lights.iter_mut() {
+		fade.elapsed += time.delta_secs();
+		if fade.elapsed >= fade.duration {
+			cmd.entity(entity).despawn();
+		}
+	}
+}
+
+/// Cheap smooth noise in `0.0 ..= 1.0`, offset per light so they don't flicker
+/// in unison.
+fn flicker_noise(time: f32, seed: u32) -> f32 {
+	let phase = seed as f32 * 12.9898;
+	let wave = (time * 13.0 + phase).sin() * 0.5 +
+		(time * 29.0 + phase * 1.7).sin() * 0.3 +
+		(time * 7.0 + phase * 0.3).sin() * 0.2;
+	wave * 0.5 + 0.5
+}
+
+fn extract_lights(
+	mut extracted: ResMut<ExtractedLights>,
+	time: Res<Time<Virtual>>,
+	lights: Query<(Entity, &Transform, &PointLight, Option<&LightFade>)>,
+) {
+	let now = time.elapsed_secs();
+	extracted.0.clear();
+	for (entity, transform, light, fade) in lights.iter() {
+		let mut intensity = light.intensity;
+		if light.flicker > 0.0 {
+			intensity *= 1.0 - light.flicker * flicker_noise(now, entity.index());
+		}
+		if let Some(fade) = fade {
+			intensity *= 1.0 - (fade.elapsed / fade.duration).clamp(0.0, 1.0);
+		}
+		extracted.0.push(PointLightData {
+			position: transform.translation,
+			radius: light.radius,
+			color: light.color.to_vec4().truncate() * intensity,
+			_padding: 0.0,
+		});
+	}
+}
+
+fn upload_lights(
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	views: Res<Views>,
+	lights: Res<ExtractedLights>,
+	mut nearest: Local<Vec<(f32, PointLightData)>>,
+) {
+	let eye = views.main().map_or(Vec3::ZERO, |view| view.eye);
+
+	nearest.clear();
+	// lights whose reach doesn't extend to the camera lose out first
+	nearest.extend(
+		lights
+			.0
+			.iter()
+			.map(|&light| (light.position.distance(eye) - light.radius, light)),
+	);
+	nearest.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+	let mut uniforms = PointLightUniforms {
+		count: nearest.len().min(MAX_POINT_LIGHTS) as _,
+		_padding: default(),
+		lights: default(),
+	};
+	for (slot, &(_, light)) in uniforms.lights.iter_mut().zip(nearest.iter()) {
+		*slot = light;
+	}
+	ctx.write_buffer(&pipelines.lights, 0, bytemuck::bytes_of(&uniforms));
+}
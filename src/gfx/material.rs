@@ -0,0 +1,381 @@
+//! Custom sprite shading. A [`Material`] supplies a fragment stage built on
+//! `quad.wgsl`, and optionally its own bind group and blending. Each distinct
+//! material gets one pipeline, shared between every material added with the
+//! same shader and state, and sprites are drawn in one batch per material.
+
+use std::borrow::Cow;
+
+use bevy_platform::collections::HashMap;
+use futures_util::FutureExt;
+use wgpu::util::DeviceExt;
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		Color,
+		GraphicsContext,
+		Pipelines,
+		create_quad_pipeline,
+		shader,
+		textures::SpriteTextures,
+	},
+	prelude::*,
+};
+
+/// Shading for sprites that opt out of the default.
+pub trait Material: 'static {
+	/// WGSL source, run through the [`shader`] preprocessor. It should
+	/// `#include "quad.wgsl"` and define a `material_main` fragment entry point
+	/// taking its `VOut`, which can call `shade(in)` for the default textured,
+	/// lit and fogged colour, and read `uniforms.time`.
+	fn shader(&self) -> Cow<'static, str>;
+
+	/// Layout of `@group(2)`, if the shader uses it.
+	fn layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+		Vec::new()
+	}
+
+	/// Contents of the material's uniform buffer, if it has one. The buffer is
+	/// created when the material is added and rewritten by
+	/// [`Materials::update`].
+	fn uniforms(&self) -> Option<Vec<u8>> {
+		None
+	}
+
+	/// The group bound at `@group(2)`, matching
+	/// [`layout_entries`](Self::layout_entries). `uniforms` holds
+	/// [`uniforms`](Self::uniforms).
+	fn bind_group(
+		&self,
+		ctx: &GraphicsContext,
+		layout: &wgpu::BindGroupLayout,
+		uniforms: Option<&wgpu::Buffer>,
+	) -> Option<wgpu::BindGroup> {
+		let _ = (ctx, layout, uniforms);
+		None
+	}
+
+	/// Blending of the output, which also moves the material's sprites to
+	/// [`ViewSet::Transparent`](crate::gfx::camera::ViewSet::Transparent).
+	fn blend(&self) -> Option<wgpu::BlendState> {
+		None
+	}
+
+	fn depth_write(&self) -> bool {
+		self.blend().is_none()
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialId(u32);
+
+/// Everything a material's pipeline is built from.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PipelineKey {
+	shader: Cow<'static, str>,
+	layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+	blend: Option<wgpu::BlendState>,
+	depth_write: bool,
+}
+
+#[derive(Clone)]
+struct MaterialPipeline {
+	layout: Option<wgpu::BindGroupLayout>,
+	pipeline: wgpu::RenderPipeline,
+}
+
+pub(crate) struct MaterialEntry {
+	pub pipeline: wgpu::RenderPipeline,
+	pub bind_group: Option<wgpu::BindGroup>,
+	pub uniforms: Option<wgpu::Buffer>,
+	pub transparent: bool,
+}
+
+#[derive(Default)]
+pub struct Materials {
+	materials: Vec<MaterialEntry>,
+	cache: HashMap<PipelineKey, MaterialPipeline>,
+}
+
+impl Materials {
+	/// Registers `material`, building its pipeline unless an identical one
+	/// already exists. Fails if its shader doesn't preprocess, or if the device
+	/// rejects the shader or pipeline right away; devices that report errors
+	/// later, like the browser's, have them logged instead.
+	pub fn add(
+		&mut self,
+		ctx: &GraphicsContext,
+		pipelines: &Pipelines,
+		textures: &SpriteTextures,
+		material: impl Material,
+	) -> Result<MaterialId, String> {
+		let key = PipelineKey {
+			shader: material.shader(),
+			layout_entries: material.layout_entries(),
+			blend: material.blend(),
+			depth_write: material.depth_write(),
+		};
+		let cached = match self.cache.get(&key) {
+			Some(cached) => cached.clone(),
+			None => {
+				let cached = create_material_pipeline(ctx, pipelines, textures, &key)?;
+				self.cache.insert(key.clone(), cached.clone());
+				cached
+			},
+		};
+
+		let uniforms = material.uniforms().map(|contents| {
+			ctx.device
+				.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+					label: Some("material uniforms"),
+					contents: &contents,
+					usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+				})
+		});
+		let bind_group = cached
+			.layout
+			.as_ref()
+			.and_then(|layout| material.bind_group(ctx, layout, uniforms.as_ref()));
+		self.materials.push(MaterialEntry {
+			pipeline: cached.pipeline,
+			bind_group,
+			uniforms,
+			transparent: key.blend.is_some(),
+		});
+		Ok(MaterialId(self.materials.len() as u32 - 1))
+	}
+
+	/// Rewrites the uniforms of the material added as `id` from `material`,
+	/// which should be the same kind of material with new parameters.
+	pub fn update(&self, ctx: &GraphicsContext, id: MaterialId, material: &impl Material) {
+		let entry = self.get(id);
+		if let (Some(buffer), Some(contents)) = (&entry.uniforms, material.uniforms()) {
+			ctx.write_buffer(buffer, 0, &contents);
+		}
+	}
+
+	pub(crate) fn get(&self, material: MaterialId) -> &MaterialEntry {
+		&self.materials[material.0 as usize]
+	}
+
+	/// Whether sprites with `material` are drawn in the transparent set.
+	pub(crate) fn is_transparent(&self, material: Option<MaterialId>) -> bool {
+		material.is_some_and(|material| self.get(material).transparent)
+	}
+}
+
+fn create_material_pipeline(
+	ctx: &GraphicsContext,
+	pipelines: &Pipelines,
+	textures: &SpriteTextures,
+	key: &PipelineKey,
+) -> Result<MaterialPipeline, String> {
+	let source = shader::preprocess(&key.shader, &[])
+		.map_err(|error| format!("material shader: {error}"))?;
+	ctx.device.push_error_scope(wgpu::ErrorFilter::Validation);
+	let shader_module = ctx
+		.device
+		.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some("material shader"),
+			source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+		});
+	let layout = (!key.layout_entries.is_empty()).then(|| {
+		ctx.device
+			.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+				label: Some("material layout"),
+				entries: &key.layout_entries,
+			})
+	});
+	let pipeline = create_render_pipeline(
+		ctx,
+		pipelines,
+		textures,
+		layout.as_ref(),
+		&shader_module,
+		key,
+	);
+
+	// polled once here, as some devices only report errors asynchronously
+	let mut error = Box::pin(ctx.device.pop_error_scope());
+	match (&mut error).now_or_never() {
+		Some(Some(error)) => return Err(format!("material pipeline: {error}")),
+		Some(None) => {},
+		None => wasm_bindgen_futures::spawn_local(async move {
+			if let Some(error) = error.await {
+				log::error!("material pipeline: {error}");
+			}
+		}),
+	}
+	Ok(MaterialPipeline { layout, pipeline })
+}
+
+fn create_render_pipeline(
+	ctx: &GraphicsContext,
+	pipelines: &Pipelines,
+	textures: &SpriteTextures,
+	layout: Option<&wgpu::BindGroupLayout>,
+	shader_module: &wgpu::ShaderModule,
+	key: &PipelineKey,
+) -> wgpu::RenderPipeline {
+	let mut bind_group_layouts = vec![&pipelines.uniforms_layout, &textures.layout];
+	bind_group_layouts.extend(layout);
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("material render layout"),
+			bind_group_layouts: &bind_group_layouts,
+			push_constant_ranges: &[],
+		});
+	create_quad_pipeline(
+		ctx,
+		"material render pipeline",
+		&pipeline_layout,
+		shader_module,
+		"material_main",
+		key.blend,
+		key.depth_write,
+	)
+}
+
+pub(super) fn setup(app: &mut App) {
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_pipelines);
+}
+
+/// Rebuilds every cached pipeline whose shader includes a changed source,
+/// keeping each one's group layout so existing bind groups stay usable.
+#[cfg(debug_assertions)]
+fn reload_pipelines(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	textures: NonSend<SpriteTextures>,
+	materials: NonSend<Materials>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	let changes: Vec<_> = changes.read().collect();
+	if changes.is_empty() {
+		return;
+	}
+	for (key, cached) in &materials.cache {
+		if !changes
+			.iter()
+			.any(|change| change.affects_source(&key.shader, &[]))
+		{
+			continue;
+		}
+		let changed_key = key.clone();
+		reloads.rebuild_source(
+			&ctx,
+			"material shader",
+			shader::preprocess(&key.shader, &[]),
+			|shader_module| {
+				create_render_pipeline(
+					&ctx,
+					&pipelines,
+					&textures,
+					cached.layout.as_ref(),
+					shader_module,
+					key,
+				)
+			},
+			move |world, pipeline| {
+				let mut materials = world.non_send_resource_mut::<Materials>();
+				let Some(cached) = materials.cache.get_mut(&changed_key) else {
+					return;
+				};
+				let old = std::mem::replace(&mut cached.pipeline, pipeline.clone());
+				for material in &mut materials.materials {
+					if material.pipeline == old {
+						material.pipeline = pipeline.clone();
+					}
+				}
+			},
+		);
+	}
+}
+
+/// Rippling, translucent water.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WaterMaterial;
+
+impl Material for WaterMaterial {
+	fn shader(&self) -> Cow<'static, str> {
+		Cow::Borrowed("#include \"materials/water.wgsl\"")
+	}
+
+	fn blend(&self) -> Option<wgpu::BlendState> {
+		Some(wgpu::BlendState::ALPHA_BLENDING)
+	}
+}
+
+/// Slowly churning lava that glows regardless of lighting.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LavaMaterial;
+
+impl Material for LavaMaterial {
+	fn shader(&self) -> Cow<'static, str> {
+		Cow::Borrowed("#include \"materials/lava.wgsl\"")
+	}
+}
+
+/// Eats away the sprite in a noisy pattern, leaving a glowing edge.
+#[derive(Clone, Copy, Debug)]
+pub struct DissolveMaterial {
+	/// Fraction of the sprite dissolved, from `0.0` to `1.0`.
+	pub amount: f32,
+	pub edge_color: Color,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(super) struct DissolveUniforms {
+	pub(super) edge_color: Color,
+	pub(super) amount: f32,
+	_padding: [f32; 3],
+}
+
+impl Material for DissolveMaterial {
+	fn shader(&self) -> Cow<'static, str> {
+		Cow::Borrowed("#include \"materials/dissolve.wgsl\"")
+	}
+
+	fn layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+		vec![wgpu::BindGroupLayoutEntry {
+			binding: 0,
+			count: None,
+			visibility: wgpu::ShaderStages::FRAGMENT,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+		}]
+	}
+
+	fn uniforms(&self) -> Option<Vec<u8>> {
+		let uniforms = DissolveUniforms {
+			edge_color: self.edge_color,
+			amount: self.amount,
+			_padding: default(),
+		};
+		Some(bytemuck::bytes_of(&uniforms).to_vec())
+	}
+
+	fn bind_group(
+		&self,
+		ctx: &GraphicsContext,
+		layout: &wgpu::BindGroupLayout,
+		uniforms: Option<&wgpu::Buffer>,
+	) -> Option<wgpu::BindGroup> {
+		Some(ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("dissolve group"),
+			layout,
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: uniforms?.as_entire_binding(),
+			}],
+		}))
+	}
+}
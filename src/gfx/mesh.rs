@@ -0,0 +1,474 @@
+//! Arbitrary triangle meshes, for 3D props among the sprites. Meshes are
+//! uploaded once into [`Meshes`] and drawn wherever an entity has a [`Mesh`],
+//! placed by its [`Transform`] and lit like sprites. Instances of the same
+//! mesh are drawn together in one call.
+
+use std::ops::Range;
+
+use wgpu::BufferUsages;
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		Color,
+		GraphicsContext,
+		Pipelines,
+		RenderPre,
+		RenderPreSet,
+		WorldRenderer,
+		camera::{CurrentView, RenderView, ViewSet, Views},
+		model::Model,
+		prepare_frame,
+		profiling::GpuProfiler,
+		shader,
+		textures::{SpriteTextures, TextureId},
+		visibility::{InheritedVisibility, RenderLayers, Visibility},
+	},
+	map::TileMap,
+	prelude::*,
+	transform::Transform,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshVertex {
+	pub position: Vec3,
+	pub normal: Vec3,
+	pub uv: Vec2,
+}
+
+/// Triangle list geometry, in model space.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+	pub vertices: Vec<MeshVertex>,
+	pub indices: Vec<u32>,
+}
+
+impl MeshData {
+	/// Replaces every normal with the average of the faces around its vertex.
+	pub fn compute_normals(&mut self) {
+		for vertex in &mut self.vertices {
+			vertex.normal = Vec3::ZERO;
+		}
+		for triangle in self.indices.chunks_exact(3) {
+			let [a, b, c] =
+				[0, 1, 2].map(|corner| self.vertices[triangle[corner] as usize].position);
+			let normal = (b - a).cross(c - a);
+			for &index in triangle {
+				self.vertices[index as usize].normal += normal;
+			}
+		}
+		for vertex in &mut self.vertices {
+			vertex.normal = vertex.normal.normalize_or_zero();
+		}
+	}
+
+	/// Moves every vertex by `transform`, keeping triangles front facing when
+	/// it mirrors them.
+	pub fn transform(&mut self, transform: Mat4) {
+		let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+		for vertex in &mut self.vertices {
+			vertex.position = transform.transform_point3(vertex.position);
+			vertex.normal = (normal_matrix * vertex.normal).normalize_or_zero();
+		}
+		if transform.determinant() < 0.0 {
+			for triangle in self.indices.chunks_exact_mut(3) {
+				triangle.swap(1, 2);
+			}
+		}
+	}
+
+	/// Radius of the sphere around the origin holding every vertex.
+	fn radius(&self) -> f32 {
+		self.vertices
+			.iter()
+			.map(|vertex| vertex.position.length())
+			.fold(0.0, f32::max)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshId(u32);
+
+/// Draws one or more meshes at the entity's [`Transform`].
+#[derive(Clone, Debug, Component)]
+#[require(Visibility)]
+pub struct Mesh {
+	pub parts: Vec<MeshPart>,
+	/// Brightness multiplier, combined with the light level of the tile the
+	/// mesh is in.
+	pub light: f32,
+}
+
+impl Default for Mesh {
+	fn default() -> Self {
+		Self {
+			parts: Vec::new(),
+			light: 1.0,
+		}
+	}
+}
+
+/// One mesh of a [`Mesh`], with its own texture and colour.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshPart {
+	pub mesh: MeshId,
+	/// Plain `tint` when unset.
+	pub texture: Option<TextureId>,
+	pub tint: Color,
+}
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct MeshInstance {
+	pub model: Mat4,
+	pub tint: Color,
+	pub light: f32,
+	/// Layer of the sprite texture array plus one, or `0` if untextured.
+	pub texture: u32,
+	pub _padding: [f32; 2],
+}
+
+/// Vertex layout of [`MeshVertex`] for `mesh.wgsl`.
+pub(crate) const MESH_VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+	0 => Float32x3,
+	1 => Float32x3,
+	2 => Float32x2,
+];
+
+/// Vertex layout of [`MeshInstance`] for `mesh.wgsl`, following
+/// [`MESH_VERTEX_ATTRIBUTES`].
+pub(crate) const MESH_INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+	3 => Float32x4,
+	4 => Float32x4,
+	5 => Float32x4,
+	6 => Float32x4,
+	7 => Float32x4,
+	8 => Float32,
+	9 => Uint32,
+];
+
+struct GpuMesh {
+	vertices: wgpu::Buffer,
+	indices: wgpu::Buffer,
+	index_count: u32,
+	radius: f32,
+}
+
+/// A run of one view's instances of a mesh.
+struct MeshBatch {
+	mesh: MeshId,
+	instances: Range<u32>,
+}
+
+pub struct Meshes {
+	meshes: Vec<GpuMesh>,
+	instances: wgpu::Buffer,
+	/// Each view's batches.
+	batches: Vec<Vec<MeshBatch>>,
+	pipeline: wgpu::RenderPipeline,
+}
+
+impl Meshes {
+	pub fn new(ctx: &GraphicsContext, pipelines: &Pipelines, textures: &SpriteTextures) -> Self {
+		let pipeline = create_pipeline(
+			ctx,
+			&pipelines.uniforms_layout,
+			textures,
+			&shader::create_shader_module(ctx, "mesh.wgsl", &[]),
+		);
+
+		Self {
+			meshes: Vec::new(),
+			instances: create_instances_buffer(ctx, 64),
+			batches: Vec::new(),
+			pipeline,
+		}
+	}
+
+	/// Uploads `data`, which can then be drawn by any number of [`Mesh`]es.
+	pub fn add(&mut self, ctx: &GraphicsContext, data: &MeshData) -> MeshId {
+		let create_buffer = |label, usage, contents: &[u8]| {
+			let buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+				label: Some(label),
+				size: contents.len() as _,
+				usage: usage | BufferUsages::COPY_DST,
+				mapped_at_creation: false,
+			});
+			ctx.write_buffer(&buffer, 0, contents);
+			buffer
+		};
+		self.meshes.push(GpuMesh {
+			vertices: create_buffer(
+				"mesh vertices",
+				BufferUsages::VERTEX,
+				bytemuck::cast_slice(&data.vertices),
+			),
+			indices: create_buffer(
+				"mesh indices",
+				BufferUsages::INDEX,
+				bytemuck::cast_slice(&data.indices),
+			),
+			index_count: data.indices.len() as _,
+			radius: data.radius(),
+		});
+		MeshId(self.meshes.len() as u32 - 1)
+	}
+
+	/// Uploads every part of `model` and its textures, returning a [`Mesh`]
+	/// drawing all of them. Fails if the textures don't fit.
+	pub fn add_model(
+		&mut self,
+		ctx: &GraphicsContext,
+		textures: &mut SpriteTextures,
+		model: &Model,
+	) -> Result<Mesh, String> {
+		let images = model
+			.images
+			.iter()
+			.map(|texels| textures.add(ctx, texels))
+			.collect::<Result<Vec<_>, _>>()?;
+		let parts = model
+			.parts
+			.iter()
+			.map(|part| MeshPart {
+				mesh: self.add(ctx, &part.data),
+				texture: part.image.map(|image| images[image]),
+				tint: part.base_color,
+			})
+			.collect();
+		Ok(Mesh { parts, ..default() })
+	}
+}
+
+fn create_pipeline(
+	ctx: &GraphicsContext,
+	uniforms_layout: &wgpu::BindGroupLayout,
+	textures: &SpriteTextures,
+	shader_module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("mesh render layout"),
+			bind_group_layouts: &[uniforms_layout, &textures.layout],
+			push_constant_ranges: &[],
+		});
+	ctx.device
+		.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("mesh render pipeline"),
+			layout: Some(&pipeline_layout),
+			depth_stencil: Some(wgpu::DepthStencilState {
+				format: wgpu::TextureFormat::Depth24Plus,
+				depth_write_enabled: true,
+				depth_compare: wgpu::CompareFunction::LessEqual,
+				stencil: default(),
+				bias: default(),
+			}),
+			multisample: wgpu::MultisampleState::default(),
+			multiview: None,
+			cache: None,
+			primitive: wgpu::PrimitiveState {
+				topology: wgpu::PrimitiveTopology::TriangleList,
+				cull_mode: Some(wgpu::Face::Back),
+				..default()
+			},
+			vertex: wgpu::VertexState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: None,
+				buffers: &[
+					wgpu::VertexBufferLayout {
+						step_mode: wgpu::VertexStepMode::Vertex,
+						array_stride: size_of::<MeshVertex>() as _,
+						attributes: &MESH_VERTEX_ATTRIBUTES,
+					},
+					wgpu::VertexBufferLayout {
+						step_mode: wgpu::VertexStepMode::Instance,
+						array_stride: size_of::<MeshInstance>() as _,
+						attributes: &MESH_INSTANCE_ATTRIBUTES,
+					},
+				],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: None,
+				targets: &[Some(ctx.surface_format().into())],
+			}),
+		})
+}
+
+#[cfg(debug_assertions)]
+fn reload_pipeline(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	textures: NonSend<SpriteTextures>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	if changes
+		.read()
+		.any(|change| change.affects("mesh.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"mesh.wgsl",
+			&[],
+			|shader_module| {
+				create_pipeline(&ctx, &pipelines.uniforms_layout, &textures, shader_module)
+			},
+			|world, pipeline| world.non_send_resource_mut::<Meshes>().pipeline = pipeline,
+		);
+	}
+}
+
+fn create_instances_buffer(ctx: &GraphicsContext, length: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("mesh instances"),
+		size: (size_of::<MeshInstance>() * length) as _,
+		usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
+
+/// Every visible mesh part's instance, sorted by mesh.
+#[derive(Resource, Default)]
+struct ExtractedMeshes(Vec<(MeshId, MeshInstance, RenderLayers)>);
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<ExtractedMeshes>();
+	app.add_systems(
+		RenderPre,
+		(
+			extract_meshes.in_set(RenderPreSet::Extract),
+			prepare_meshes
+				.in_set(RenderPreSet::Prepare)
+				.after(prepare_frame),
+		),
+	);
+	app.add_systems(
+		RenderView,
+		draw_meshes
+			.in_set(ViewSet::World)
+			.run_if(resource_equals(WorldRenderer::Polygonal)),
+	);
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_pipeline);
+}
+
+fn extract_meshes(
+	mut extracted: ResMut<ExtractedMeshes>,
+	query: Query<(
+		&Transform,
+		&Mesh,
+		&InheritedVisibility,
+		Option<&RenderLayers>,
+	)>,
+	map: Option<Res<TileMap>>,
+) {
+	let candidates = &mut extracted.0;
+	candidates.clear();
+	for (transform, mesh, visibility, layers) in query.iter() {
+		if !visibility.get() {
+			continue;
+		}
+		let model = transform.as_model_matrix();
+		let light = mesh.light *
+			map.as_ref()
+				.map_or(1.0, |map| map.light_at(transform.translation));
+		let layers = layers.copied().unwrap_or_default();
+		candidates.extend(mesh.parts.iter().map(|part| {
+			let instance = MeshInstance {
+				model,
+				tint: part.tint,
+				light,
+				texture: TextureId::instance_index(part.texture),
+				_padding: default(),
+			};
+			(part.mesh, instance, layers)
+		}));
+	}
+	candidates.sort_by_key(|&(mesh, ..)| mesh);
+}
+
+fn prepare_meshes(
+	ctx: NonSend<GraphicsContext>,
+	mut meshes: NonSendMut<Meshes>,
+	views: Res<Views>,
+	extracted: Res<ExtractedMeshes>,
+	mut instances: Local<Vec<MeshInstance>>,
+) {
+	let meshes = &mut *meshes;
+	meshes.batches.clear();
+	instances.clear();
+	for view in &views.0 {
+		let mut batches = Vec::new();
+		for group in extracted.0.chunk_by(|a, b| a.0 == b.0) {
+			let mesh = group[0].0;
+			let radius = meshes.meshes[mesh.0 as usize].radius;
+			let first = instances.len() as u32;
+			instances.extend(
+				group
+					.iter()
+					.filter(|(_, instance, layers)| {
+						layers.intersects(view.layers) &&
+							view.frustum
+								.intersects_sphere(instance.model.w_axis.truncate(), radius)
+					})
+					.map(|&(_, instance, _)| instance),
+			);
+			let instances = first .. instances.len() as u32;
+			if !instances.is_empty() {
+				batches.push(MeshBatch { mesh, instances });
+			}
+		}
+		meshes.batches.push(batches);
+	}
+
+	if instances.is_empty() {
+		return;
+	}
+	let bytes = bytemuck::cast_slice::<MeshInstance, u8>(&instances);
+	if (meshes.instances.size() as usize) < bytes.len() {
+		meshes.instances = create_instances_buffer(&ctx, instances.len().next_power_of_two());
+	}
+	ctx.write_buffer(&meshes.instances, 0, bytes);
+}
+
+fn draw_meshes(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	meshes: NonSend<Meshes>,
+	pipelines: NonSend<Pipelines>,
+	textures: NonSend<SpriteTextures>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+) {
+	let Some(batches) = meshes
+		.batches
+		.get(view.index())
+		.filter(|batches| !batches.is_empty())
+	else {
+		return;
+	};
+
+	let view = view.get();
+	let mut pass = frame.begin_view_pass("meshes", &mut profiler, view);
+	pass.set_pipeline(&meshes.pipeline);
+	ctx.record_pipeline_switch();
+	pass.set_bind_group(0, &pipelines.uniforms_group, &[view.uniforms_offset]);
+	pass.set_bind_group(1, &textures.group, &[]);
+	pass.set_vertex_buffer(1, meshes.instances.slice(..));
+	for batch in batches {
+		let mesh = &meshes.meshes[batch.mesh.0 as usize];
+		pass.set_vertex_buffer(0, mesh.vertices.slice(..));
+		pass.set_index_buffer(mesh.indices.slice(..), wgpu::IndexFormat::Uint32);
+		pass.draw_indexed(0 .. mesh.index_count, 0, batch.instances.clone());
+		ctx.record_draw(batch.instances.len() as _);
+	}
+	drop(pass);
+	profiler.end_pass();
+}
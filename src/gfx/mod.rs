@@ -0,0 +1,1365 @@
+pub mod areas;
+pub mod automap;
+pub mod camera;
+pub mod color;
+pub mod culling;
+pub mod decals;
+pub mod gizmos;
+pub mod lighting;
+pub mod material;
+pub mod mesh;
+pub mod model;
+pub mod palette;
+pub mod particles;
+pub mod picking;
+pub mod profiling;
+pub mod raycast;
+pub mod render_target;
+pub mod shader;
+pub mod sky;
+pub mod static_batch;
+pub mod textures;
+pub mod visibility;
+
+use std::{cell::Cell, f64::consts::PI, num::NonZero, ops::Range, ptr::NonNull};
+
+use bevy_app::MainScheduleOrder;
+use bevy_math::DVec2;
+use futures_util::FutureExt;
+use js_sys::{Array as JsArray, Object as JsObject};
+use wasm_bindgen::{
+	JsCast,
+	JsError,
+	JsValue,
+	prelude::{Closure, wasm_bindgen},
+};
+use wasm_bindgen_futures::JsFuture;
+use wgpu::{
+	BufferUsages,
+	ShaderStages,
+	rwh::{
+		RawDisplayHandle,
+		RawWindowHandle,
+		WebCanvasWindowHandle,
+		WebDisplayHandle,
+		WebWindowHandle,
+	},
+};
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+pub use crate::gfx::{camera::Camera, color::Color};
+use crate::{
+	DomElements,
+	gfx::{
+		areas::Areas,
+		automap::AutomapPipelines,
+		camera::{
+			ClearPipelines,
+			CurrentView,
+			ExtractedCamera,
+			MAX_VIEWS,
+			RenderView,
+			View,
+			ViewSet,
+			Views,
+			collect_views,
+		},
+		culling::{Frustum, SpriteCulling, instance_radius},
+		decals::{DecalInstances, DecalTextures, draw_decals},
+		gizmos::GizmoPipelines,
+		lighting::{Fog, PointLightUniforms},
+		material::{MaterialId, Materials},
+		mesh::Meshes,
+		palette::ColorMode,
+		particles::{ParticleInstances, ParticleTextures},
+		profiling::{GpuProfiler, PassTimings, RenderStats},
+		raycast::RaycastPipelines,
+		render_target::RenderTargets,
+		sky::{Skies, SkyPipelines},
+		static_batch::{Static, StaticBatches},
+		textures::{SpriteTextures, TextureId},
+		visibility::{InheritedVisibility, RenderLayers, Visibility, VisibilitySystems},
+	},
+	map::TileMap,
+	prelude::*,
+	transform::Transform,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ScheduleLabel)]
+pub struct RenderPre;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ScheduleLabel)]
+pub struct Render;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ScheduleLabel)]
+pub struct RenderPost;
+
+/// Ordering of the systems within [`RenderPre`], after
+/// [`VisibilitySystems`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, SystemSet)]
+pub enum RenderPreSet {
+	/// Copies what the frame needs out of the game world into render-side
+	/// resources. Only these systems query game components.
+	Extract,
+	/// Turns what was extracted into GPU state.
+	Prepare,
+}
+
+/// Ordering of the systems that encode passes within [`Render`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, SystemSet)]
+pub enum RenderSet {
+	/// Acquires the canvas texture and inserts the [`ActiveFrame`].
+	Begin,
+	/// Compute passes preparing data for the render passes.
+	Compute,
+	/// Runs [`RenderView`] for each camera.
+	Views,
+	/// Drawn over the whole canvas after every camera.
+	Overlay,
+	/// Submits the frame's commands and presents.
+	End,
+}
+
+pub struct GraphicsContext {
+	pub instance: wgpu::Instance,
+	pub surface: wgpu::Surface<'static>,
+	pub adapter: wgpu::Adapter,
+	pub device: wgpu::Device,
+	pub queue: wgpu::Queue,
+	/// Whether compute shaders and indirect draws are usable, which the
+	/// WebGL2-level limits otherwise requested don't allow.
+	pub supports_compute: bool,
+	/// Counts for the frame being rendered, moved into [`RenderStats`] once
+	/// it is done.
+	stats: Cell<RenderStats>,
+}
+
+impl GraphicsContext {
+	pub fn surface_format(&self) -> wgpu::TextureFormat {
+		self.surface.get_capabilities(&self.adapter).formats[0]
+	}
+
+	/// Like [`wgpu::Queue::write_buffer`], counted in
+	/// [`RenderStats::bytes_uploaded`].
+	pub fn write_buffer(&self, buffer: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+		self.record(|stats| stats.bytes_uploaded += data.len() as u64);
+		self.queue.write_buffer(buffer, offset, data);
+	}
+
+	/// Like [`wgpu::Queue::write_texture`], counted in
+	/// [`RenderStats::bytes_uploaded`].
+	pub fn write_texture(
+		&self,
+		texture: wgpu::TexelCopyTextureInfo,
+		data: &[u8],
+		layout: wgpu::TexelCopyBufferLayout,
+		size: wgpu::Extent3d,
+	) {
+		self.record(|stats| stats.bytes_uploaded += data.len() as u64);
+		self.queue.write_texture(texture, data, layout, size);
+	}
+
+	/// Like [`wgpu::Device::create_texture`], counted in
+	/// [`RenderStats::texture_bytes`] until passed to
+	/// [`destroy_texture`](Self::destroy_texture).
+	pub fn create_texture(&self, desc: &wgpu::TextureDescriptor) -> wgpu::Texture {
+		let texture = self.device.create_texture(desc);
+		self.record(|stats| stats.texture_bytes += texture_bytes(&texture));
+		texture
+	}
+
+	pub fn destroy_texture(&self, texture: &wgpu::Texture) {
+		self.record(|stats| stats.texture_bytes -= texture_bytes(texture));
+		texture.destroy();
+	}
+
+	/// Counts a draw call of `instances` instances.
+	pub fn record_draw(&self, instances: u32) {
+		self.record(|stats| {
+			stats.draw_calls += 1;
+			stats.instances_drawn += instances;
+		});
+	}
+
+	/// Counts a `set_pipeline` call.
+	pub fn record_pipeline_switch(&self) {
+		self.record(|stats| stats.pipeline_switches += 1);
+	}
+
+	fn record(&self, count: impl FnOnce(&mut RenderStats)) {
+		let mut stats = self.stats.get();
+		count(&mut stats);
+		self.stats.set(stats);
+	}
+}
+
+/// Memory taken by `texture`, not counting any padding the driver adds.
+fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+	// depth formats can't be copied, but take about four bytes a texel
+	let texel = texture.format().block_copy_size(None).unwrap_or(4) as u64;
+	let texels: u64 = (0 .. texture.mip_level_count())
+		.map(|level| {
+			let size = texture.size().mip_level_size(level, texture.dimension());
+			size.width as u64 * size.height as u64 * size.depth_or_array_layers as u64
+		})
+		.sum();
+	texels * texel * texture.sample_count() as u64
+}
+
+pub struct Pipelines {
+	pub depth_texture: wgpu::Texture,
+
+	/// One [`Uniforms`] per view, `uniforms_stride` bytes apart.
+	pub uniforms: wgpu::Buffer,
+	pub uniforms_stride: u32,
+	pub lights: wgpu::Buffer,
+	pub uniforms_layout: wgpu::BindGroupLayout,
+	pub uniforms_group: wgpu::BindGroup,
+
+	pub instances: wgpu::Buffer,
+
+	pub pipeline: wgpu::RenderPipeline,
+	/// Alpha-blended variant of `pipeline` for [`ViewSet::Transparent`].
+	pub transparent_pipeline: wgpu::RenderPipeline,
+}
+
+/// Per-frame values shared by every world shader, matching `Uniforms` in
+/// `common/view.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct Uniforms {
+	projection: Mat4,
+	view: Mat4,
+	time: f32,
+	fog_mode: u32,
+	fog_start: f32,
+	fog_end: f32,
+	fog_color: Color,
+	fog_density: f32,
+	color_mode: u32,
+	_padding: [f32; 2],
+}
+
+/// Colour and depth attachments passes are drawn to.
+pub struct FrameTarget {
+	pub color: wgpu::TextureView,
+	pub depth: wgpu::TextureView,
+	cleared: bool,
+	clear_color: Color,
+}
+
+impl FrameTarget {
+	pub fn new(color: wgpu::TextureView, depth: wgpu::TextureView) -> Self {
+		Self {
+			color,
+			depth,
+			cleared: false,
+			clear_color: Color::BLACK,
+		}
+	}
+}
+
+/// Canvas texture and command encoder for the frame being rendered, present
+/// only while systems in [`Render`] run.
+pub struct ActiveFrame {
+	pub encoder: wgpu::CommandEncoder,
+	pub canvas: FrameTarget,
+	/// Drawn to instead of the canvas while set, for views rendering to a
+	/// texture.
+	pub offscreen: Option<FrameTarget>,
+	surface_texture: wgpu::SurfaceTexture,
+}
+
+impl ActiveFrame {
+	/// The attachments passes are currently drawn to.
+	pub fn target(&mut self) -> &mut FrameTarget {
+		self.offscreen.as_mut().unwrap_or(&mut self.canvas)
+	}
+
+	/// Makes the next pass clear the whole target to `color`.
+	pub fn clear(&mut self, color: Color) {
+		let target = self.target();
+		target.cleared = false;
+		target.clear_color = color;
+	}
+
+	/// Begins a render pass over the current target, clearing it if this is
+	/// the first pass drawing to it this frame or [`clear`](Self::clear) was
+	/// called. [`GpuProfiler::end_pass`] must be called after the returned
+	/// pass is dropped.
+	pub fn begin_pass(
+		&mut self,
+		label: &'static str,
+		profiler: &mut GpuProfiler,
+	) -> wgpu::RenderPass<'_> {
+		let target = self.offscreen.as_mut().unwrap_or(&mut self.canvas);
+		let (color_load, depth_load) = if target.cleared {
+			(wgpu::LoadOp::Load, wgpu::LoadOp::Load)
+		} else {
+			target.cleared = true;
+			(
+				wgpu::LoadOp::Clear(target.clear_color.to_wgpu()),
+				wgpu::LoadOp::Clear(1.0),
+			)
+		};
+		self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some(label),
+			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+				view: &target.color,
+				depth_slice: None,
+				resolve_target: None,
+				ops: wgpu::Operations {
+					load: color_load,
+					store: wgpu::StoreOp::Store,
+				},
+			})],
+			depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+				view: &target.depth,
+				depth_ops: Some(wgpu::Operations {
+					load: depth_load,
+					store: wgpu::StoreOp::Store,
+				}),
+				stencil_ops: None,
+			}),
+			timestamp_writes: profiler.begin_pass(label),
+			..default()
+		})
+	}
+}
+
+#[derive(Clone, Copy, Debug, Event)]
+pub struct WindowResized(pub UVec2);
+
+/// How the world is drawn from the camera's point of view.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WorldRenderer {
+	/// Sprites and walls drawn as textured quads.
+	#[default]
+	Polygonal,
+	/// Classic column raycasting over the [`TileMap`](crate::map::TileMap).
+	Raycast,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum SpriteMode {
+	#[default]
+	Billboard,
+	Fixed,
+}
+
+#[derive(Clone, Debug, Component)]
+#[require(Visibility)]
+pub struct Sprite {
+	pub mode: SpriteMode,
+	pub size: Vec2,
+	/// Brightness multiplier, combined with the light level of the tile the
+	/// sprite is in.
+	pub light: f32,
+	/// Drawn with the debug UV colouring when unset.
+	pub texture: Option<TextureId>,
+	/// Multiplies the texture colour, alpha included.
+	pub tint: Color,
+	/// Part of the texture shown, in `0.0 ..= 1.0` with the origin at the top
+	/// left, for sprites packed into an atlas.
+	pub uv_rect: Rect,
+	pub flip_x: bool,
+	pub flip_y: bool,
+	/// Ignores tile and point lights, drawing at full brightness.
+	pub fullbright: bool,
+	/// Drawn with the default shading when unset.
+	pub material: Option<MaterialId>,
+}
+
+impl Default for Sprite {
+	fn default() -> Self {
+		Self {
+			mode: default(),
+			size: Vec2::ONE,
+			light: 1.0,
+			texture: None,
+			tint: Color::WHITE,
+			uv_rect: Rect::new(0.0, 0.0, 1.0, 1.0),
+			flip_x: false,
+			flip_y: false,
+			fullbright: false,
+			material: None,
+		}
+	}
+}
+
+impl Sprite {
+	/// Value of `SpriteInstance::uv_rect`.
+	pub(crate) fn instance_uv_rect(&self) -> Vec4 {
+		let Rect { min, max } = self.uv_rect;
+		Vec4::new(min.x, min.y, max.x, max.y)
+	}
+
+	/// Value of `SpriteInstance::flags`.
+	pub(crate) fn instance_flags(&self) -> u32 {
+		let mut flags = TextureId::instance_flags(self.texture);
+		if self.flip_x {
+			flags |= SpriteInstance::FLIP_X;
+		}
+		if self.flip_y {
+			flags |= SpriteInstance::FLIP_Y;
+		}
+		if self.fullbright {
+			flags |= SpriteInstance::FULLBRIGHT;
+		}
+		flags
+	}
+}
+
+#[derive(Default, Bundle)]
+pub struct SpriteBundle {
+	pub sprite: Sprite,
+	pub transform: Transform,
+}
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct SpriteInstance {
+	pub model: Mat4,
+	pub size: Vec2,
+	pub billboard: u32,
+	/// Layer of the sprite texture array plus one, or `0` if untextured.
+	pub texture: u32,
+	pub tint: Color,
+	/// Texture sub-rectangle as `min.xy, max.xy`.
+	pub uv_rect: Vec4,
+	pub light: f32,
+	pub flags: u32,
+	pub _padding: [f32; 2],
+}
+
+impl SpriteInstance {
+	pub const FLIP_X: u32 = 1 << 0;
+	pub const FLIP_Y: u32 = 1 << 1;
+	pub const FULLBRIGHT: u32 = 1 << 2;
+	/// The whole texture, for instances without a [`Sprite`].
+	pub const FULL_UV_RECT: Vec4 = Vec4::new(0.0, 0.0, 1.0, 1.0);
+	/// The texture holds palette indices.
+	pub const INDEXED: u32 = 1 << 3;
+}
+
+/// A run of one view's sprite instances sharing a material.
+#[derive(Clone, Debug)]
+pub(crate) struct SpriteBatch {
+	pub material: Option<MaterialId>,
+	pub instances: Range<u32>,
+	/// Index of the batch's culling parameters and draw arguments, counting
+	/// across every view.
+	pub slot: u32,
+	/// Whether the instances are sorted back to front, as blended sprites are.
+	/// Sorted batches are culled on the CPU and drawn directly, since GPU
+	/// compaction doesn't keep their order.
+	pub sorted: bool,
+}
+
+/// Vertex layout of [`SpriteInstance`], shared by every pipeline drawing
+/// instances with `quad.wgsl`.
+pub(crate) const SPRITE_INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 11] = wgpu::vertex_attr_array![
+	0 => Float32x4,
+	1 => Float32x4,
+	2 => Float32x4,
+	3 => Float32x4,
+	4 => Float32x2,
+	5 => Uint32,
+	6 => Uint32,
+	7 => Float32x4,
+	8 => Float32x4,
+	9 => Float32,
+	10 => Uint32,
+];
+
+thread_local! {
+	static PENDING_RESIZE: Cell<Option<UVec2>> =
+		panic!("trying to use PENDING_RESIZE from background thread");
+}
+
+app_setup_fn!("gfx", async setup);
+fn setup(app: &mut App) -> crate::AsyncSetupResult<'_> {
+	async {
+		log::info!("setting up graphics context");
+
+		let DomElements { window, canvas, .. } = app.world().non_send_resource::<DomElements>();
+		let resize = {
+			let window = window.clone();
+			let canvas = canvas.clone();
+			move || -> JsResult<()> {
+				let size = UVec2::new(
+					window.inner_width()?.as_f64().unwrap() as u32,
+					window.inner_height()?.as_f64().unwrap() as u32,
+				);
+				canvas.set_width(size.x);
+				canvas.set_height(size.y);
+				PENDING_RESIZE.set(Some(size));
+				Ok(())
+			}
+		};
+		let resize = Closure::<dyn Fn() -> JsResult<()>>::new(resize);
+		window.add_event_listener_with_callback("resize", resize.as_ref().unchecked_ref())?;
+		resize.forget();
+		window.dispatch_event(&web_sys::Event::new("resize")?)?;
+
+		let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+			backends: wgpu::Backends::BROWSER_WEBGPU,
+			..default()
+		});
+
+		let handle = WebCanvasWindowHandle::new(NonNull::from(canvas).cast());
+		let target = wgpu::SurfaceTargetUnsafe::RawHandle {
+			raw_display_handle: RawDisplayHandle::Web(WebDisplayHandle::new()),
+			raw_window_handle: RawWindowHandle::WebCanvas(handle),
+		};
+		let surface = unsafe { instance.create_surface_unsafe(target) }.map_err(JsError::from)?;
+
+		let adapter = instance
+			.request_adapter(&wgpu::RequestAdapterOptions {
+				power_preference: wgpu::PowerPreference::HighPerformance,
+				compatible_surface: Some(&surface),
+				..default()
+			})
+			.await
+			.map_err(JsError::from)?;
+
+		let compute_limits = wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits());
+		let supports_compute = adapter.get_downlevel_capabilities().flags.contains(
+			wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION,
+		) && compute_limits.check_limits(&adapter.limits());
+		let (device, queue) = adapter
+			.request_device(&wgpu::DeviceDescriptor {
+				required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+				required_limits: if supports_compute {
+					compute_limits
+				} else {
+					wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+				},
+				..default()
+			})
+			.await
+			.map_err(JsError::from)?;
+
+		let ctx = GraphicsContext {
+			instance,
+			surface,
+			adapter,
+			device,
+			queue,
+			supports_compute,
+			stats: default(),
+		};
+		let mut textures = SpriteTextures::new(&ctx);
+		app.insert_resource(ParticleTextures::new(&ctx, &mut textures)?);
+		app.insert_resource(DecalTextures::new(&ctx, &mut textures)?);
+		let pipelines = setup_pipelines(&ctx, &textures).await?;
+		app.insert_non_send_resource(GizmoPipelines::new(&ctx, &pipelines));
+		app.insert_non_send_resource(AutomapPipelines::new(&ctx));
+		app.insert_non_send_resource(ClearPipelines::new(&ctx));
+		app.insert_non_send_resource(Materials::default());
+		app.insert_non_send_resource(Meshes::new(&ctx, &pipelines, &textures));
+		app.insert_non_send_resource(RenderTargets::new(&ctx));
+		app.insert_non_send_resource(ParticleInstances::new(&ctx));
+		app.insert_non_send_resource(DecalInstances::new(&ctx));
+		app.insert_non_send_resource(RaycastPipelines::new(&ctx));
+		app.insert_non_send_resource(StaticBatches::new(&ctx, &pipelines, &textures));
+		app.insert_non_send_resource(SpriteCulling::new(&ctx));
+		let mut sky_pipelines = SkyPipelines::new(&ctx, &pipelines);
+		app.insert_resource(Skies::new(&ctx, &mut sky_pipelines));
+		app.insert_non_send_resource(sky_pipelines);
+		app.insert_non_send_resource(pipelines);
+		app.insert_non_send_resource(textures);
+		app.insert_non_send_resource(GpuProfiler::new(&ctx));
+		app.insert_non_send_resource(ctx);
+		app.init_resource::<PassTimings>();
+		app.init_resource::<RenderStats>();
+		app.init_resource::<WorldRenderer>();
+
+		app.init_schedule(RenderPre);
+		app.init_schedule(Render);
+		app.init_schedule(RenderPost);
+		let mut order = app.world_mut().resource_mut::<MainScheduleOrder>();
+		order.insert_after(Update, RenderPre);
+		order.insert_after(RenderPre, Render);
+		order.insert_after(Render, RenderPost);
+
+		app.add_event::<WindowResized>();
+
+		app.configure_sets(
+			RenderPre,
+			(
+				RenderPreSet::Extract.after(VisibilitySystems),
+				RenderPreSet::Prepare,
+			)
+				.chain(),
+		);
+		app.configure_sets(
+			Render,
+			(
+				RenderSet::Begin,
+				RenderSet::Compute,
+				RenderSet::Views,
+				RenderSet::Overlay,
+				RenderSet::End,
+			)
+				.chain(),
+		);
+
+		app.add_systems(Update, dispatch_resize);
+		app.init_resource::<ExtractedFrame>();
+		app.add_systems(
+			RenderPre,
+			(
+				extract_frame.in_set(RenderPreSet::Extract),
+				prepare_frame.in_set(RenderPreSet::Prepare),
+			),
+		);
+		app.add_systems(
+			Render,
+			(
+				frame_begin.in_set(RenderSet::Begin),
+				frame_end.in_set(RenderSet::End),
+			),
+		);
+		app.add_systems(RenderPost, collect_stats);
+		#[cfg(debug_assertions)]
+		app.add_systems(Update, reload_sprite_pipelines);
+		camera::setup(app);
+		app.add_systems(
+			RenderView,
+			(
+				draw_sprites.in_set(ViewSet::World),
+				draw_transparent_sprites
+					.in_set(ViewSet::Transparent)
+					.before(draw_decals),
+			)
+				.run_if(resource_equals(WorldRenderer::Polygonal)),
+		);
+		areas::setup(app);
+		automap::setup(app);
+		culling::setup(app);
+		decals::setup(app);
+		gizmos::setup(app);
+		lighting::setup(app);
+		material::setup(app);
+		mesh::setup(app);
+		palette::setup(app);
+		particles::setup(app);
+		raycast::setup(app);
+		render_target::setup(app);
+		#[cfg(debug_assertions)]
+		shader::setup(app);
+		sky::setup(app);
+		static_batch::setup(app);
+		visibility::setup(app);
+
+		Ok(())
+	}
+	.boxed_local()
+}
+
+// TODO: configurable FOV
+/// Horizontal field of view of every view in degrees, whatever its shape.
+const HORIZONTAL_FOV: f32 = 100.0;
+
+/// Vertical field of view for a viewport with the given aspect ratio, keeping
+/// the horizontal field of view fixed. Stays below 180° however narrow the
+/// viewport is.
+pub fn vertical_fov(aspect: f32) -> f32 {
+	let tan_x = (HORIZONTAL_FOV.to_radians() / 2.0).tan();
+	2.0 * (tan_x / aspect).atan()
+}
+
+async fn setup_pipelines(ctx: &GraphicsContext, textures: &SpriteTextures) -> JsResult<Pipelines> {
+	let depth_texture = ctx.create_texture(&wgpu::TextureDescriptor {
+		label: Some("initial depth texture"),
+		size: default(),
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: wgpu::TextureFormat::Depth24Plus,
+		usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+		view_formats: &[],
+	});
+
+	let uniforms_stride = (size_of::<Uniforms>() as u32)
+		.next_multiple_of(ctx.device.limits().min_uniform_buffer_offset_alignment);
+	let uniforms = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("uniforms"),
+		size: (uniforms_stride as usize * MAX_VIEWS) as _,
+		usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	});
+
+	let lights = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("point lights"),
+		size: size_of::<PointLightUniforms>() as _,
+		usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	});
+
+	let uniforms_layout = ctx
+		.device
+		.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("uniforms layout"),
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					count: None,
+					visibility: ShaderStages::VERTEX_FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: true,
+						min_binding_size: NonZero::new(size_of::<Uniforms>() as _),
+					},
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					count: None,
+					visibility: ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: Some(NonZero::new(lights.size()).unwrap()),
+					},
+				},
+			],
+		});
+	let uniforms_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("uniforms group"),
+		layout: &uniforms_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+					buffer: &uniforms,
+					offset: 0,
+					size: NonZero::new(size_of::<Uniforms>() as _),
+				}),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: lights.as_entire_binding(),
+			},
+		],
+	});
+
+	let instances = create_instances_buffer(ctx, size_of::<SpriteInstance>() * 64);
+
+	let pipeline_layout = create_sprite_pipeline_layout(ctx, &uniforms_layout, textures);
+	let pipeline = create_sprite_pipeline(
+		ctx,
+		&pipeline_layout,
+		&shader::create_shader_module(ctx, "quad.wgsl", &[]),
+	);
+	let transparent_pipeline = create_transparent_sprite_pipeline(
+		ctx,
+		&pipeline_layout,
+		&shader::create_shader_module(ctx, "quad.wgsl", &["TRANSPARENT"]),
+	);
+
+	Ok(Pipelines {
+		depth_texture,
+
+		uniforms,
+		uniforms_stride,
+		lights,
+		uniforms_layout,
+		uniforms_group,
+
+		instances,
+
+		pipeline,
+		transparent_pipeline,
+	})
+}
+
+fn create_sprite_pipeline_layout(
+	ctx: &GraphicsContext,
+	uniforms_layout: &wgpu::BindGroupLayout,
+	textures: &SpriteTextures,
+) -> wgpu::PipelineLayout {
+	ctx.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("quad render layout"),
+			bind_group_layouts: &[uniforms_layout, &textures.layout],
+			push_constant_ranges: &[],
+		})
+}
+
+fn create_sprite_pipeline(
+	ctx: &GraphicsContext,
+	layout: &wgpu::PipelineLayout,
+	shader_module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+	create_quad_pipeline(
+		ctx,
+		"quad render pipeline",
+		layout,
+		shader_module,
+		"fragment_main",
+		None,
+		true,
+	)
+}
+
+/// Transparent quads are blended back to front over the opaque world, so they
+/// test against depth without writing it.
+fn create_transparent_sprite_pipeline(
+	ctx: &GraphicsContext,
+	layout: &wgpu::PipelineLayout,
+	shader_module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+	create_quad_pipeline(
+		ctx,
+		"transparent quad render pipeline",
+		layout,
+		shader_module,
+		"fragment_main",
+		Some(wgpu::BlendState::ALPHA_BLENDING),
+		false,
+	)
+}
+
+#[cfg(debug_assertions)]
+fn reload_sprite_pipelines(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	textures: NonSend<SpriteTextures>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	let changes: Vec<_> = changes.read().collect();
+	if changes.is_empty() {
+		return;
+	}
+	let layout = create_sprite_pipeline_layout(&ctx, &pipelines.uniforms_layout, &textures);
+	if changes
+		.iter()
+		.any(|change| change.affects("quad.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"quad.wgsl",
+			&[],
+			|shader_module| create_sprite_pipeline(&ctx, &layout, shader_module),
+			|world, pipeline| world.non_send_resource_mut::<Pipelines>().pipeline = pipeline,
+		);
+	}
+	if changes
+		.iter()
+		.any(|change| change.affects("quad.wgsl", &["TRANSPARENT"]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"quad.wgsl",
+			&["TRANSPARENT"],
+			|shader_module| create_transparent_sprite_pipeline(&ctx, &layout, shader_module),
+			|world, pipeline| {
+				world
+					.non_send_resource_mut::<Pipelines>()
+					.transparent_pipeline = pipeline;
+			},
+		);
+	}
+}
+
+/// A pipeline drawing [`SpriteInstance`]s with the vertex stage of
+/// `quad.wgsl` and the given fragment stage.
+pub(crate) fn create_quad_pipeline(
+	ctx: &GraphicsContext,
+	label: &str,
+	layout: &wgpu::PipelineLayout,
+	shader_module: &wgpu::ShaderModule,
+	fragment_entry: &str,
+	blend: Option<wgpu::BlendState>,
+	depth_write: bool,
+) -> wgpu::RenderPipeline {
+	ctx.device
+		.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some(label),
+			layout: Some(layout),
+			depth_stencil: Some(wgpu::DepthStencilState {
+				format: wgpu::TextureFormat::Depth24Plus,
+				depth_write_enabled: depth_write,
+				depth_compare: wgpu::CompareFunction::LessEqual,
+				stencil: default(),
+				bias: default(),
+			}),
+			multisample: wgpu::MultisampleState::default(),
+			multiview: None,
+			cache: None,
+			primitive: wgpu::PrimitiveState {
+				topology: wgpu::PrimitiveTopology::TriangleStrip,
+				cull_mode: Some(wgpu::Face::Back),
+				..default()
+			},
+			vertex: wgpu::VertexState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: Some("vertex_main"),
+				buffers: &[wgpu::VertexBufferLayout {
+					step_mode: wgpu::VertexStepMode::Instance,
+					array_stride: size_of::<SpriteInstance>() as _,
+					attributes: &SPRITE_INSTANCE_ATTRIBUTES,
+				}],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: Some(fragment_entry),
+				targets: &[Some(wgpu::ColorTargetState {
+					format: ctx.surface_format(),
+					blend,
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+			}),
+		})
+}
+
+/// Sprite instance buffer of `size` bytes.
+fn create_instances_buffer(ctx: &GraphicsContext, size: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("instances"),
+		size: size as _,
+		usage: if ctx.supports_compute {
+			// read by the culling shader
+			BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::STORAGE
+		} else {
+			BufferUsages::VERTEX | BufferUsages::COPY_DST
+		},
+		mapped_at_creation: false,
+	})
+}
+
+fn dispatch_resize(mut resize: EventWriter<WindowResized>, _: Option<NonSend<NonSendMarker>>) {
+	let Some(new_size) = PENDING_RESIZE.take() else {
+		return;
+	};
+	log::trace!("resizing to {new_size}");
+	resize.write(WindowResized(new_size));
+}
+
+/// What [`extract_frame`] copies out of the game world each frame, so that
+/// [`prepare_frame`] and the render schedules never borrow game components.
+/// Features with their own data extract it into their own resources, in
+/// [`RenderPreSet::Extract`].
+#[derive(Resource, Default)]
+pub(crate) struct ExtractedFrame {
+	/// Size the canvas changed to since the last frame.
+	resize: Option<UVec2>,
+	time: f32,
+	fog: Fog,
+	color_mode: ColorMode,
+	cameras: Vec<ExtractedCamera>,
+	/// Sprites outside static chunks, sorted by material.
+	sprites: Vec<ExtractedSprite>,
+	/// Which areas can be seen through, or `None` without a map.
+	open_areas: Option<Vec<bool>>,
+}
+
+impl ExtractedFrame {
+	/// The camera extracted from `entity`.
+	pub(crate) fn camera(&self, entity: Entity) -> Option<&ExtractedCamera> {
+		self.cameras.iter().find(|camera| camera.entity == entity)
+	}
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct ExtractedSprite {
+	instance: SpriteInstance,
+	layers: RenderLayers,
+	/// Area of the map the sprite is in.
+	area: Option<u32>,
+	material: Option<MaterialId>,
+}
+
+impl ExtractedSprite {
+	/// Whether the sprite is in an area `view` can see, and unless
+	/// `gpu_culled` within its frustum.
+	fn is_visible(&self, view: &View, gpu_culled: bool) -> bool {
+		view.areas.contains(self.area) &&
+			(gpu_culled ||
+				view.frustum.intersects_sphere(
+					self.instance.model.w_axis.truncate(),
+					instance_radius(&self.instance),
+				))
+	}
+}
+
+type ExtractSprite<'a> = (
+	&'a Transform,
+	&'a Sprite,
+	&'a InheritedVisibility,
+	Option<&'a RenderLayers>,
+	Has<Static>,
+);
+
+#[expect(clippy::too_many_arguments)]
+pub(crate) fn extract_frame(
+	mut extracted: ResMut<ExtractedFrame>,
+	time: Res<Time<Virtual>>,
+	fog: Res<Fog>,
+	color_mode: Res<ColorMode>,
+	map: Option<Res<TileMap>>,
+	areas: Res<Areas>,
+	mut resizes: EventReader<WindowResized>,
+	cameras: Query<(Entity, &Camera, &Transform, Option<&RenderLayers>)>,
+	sprites: Query<ExtractSprite>,
+) {
+	let extracted = &mut *extracted;
+	extracted.resize = resizes.read().next().map(|&WindowResized(size)| size);
+	extracted.time = time.elapsed_secs();
+	extracted.fog = *fog;
+	extracted.color_mode = *color_mode;
+	extracted.open_areas = map.as_ref().map(|map| areas.open(map));
+
+	extracted.cameras.clear();
+	extracted
+		.cameras
+		.extend(
+			cameras
+				.iter()
+				.map(|(entity, camera, transform, layers)| ExtractedCamera {
+					entity,
+					camera: *camera,
+					transform: transform.clone(),
+					layers: layers.copied().unwrap_or_default(),
+				}),
+		);
+
+	extracted.sprites.clear();
+	for (transform, sprite, visibility, layers, is_static) in sprites.iter() {
+		if is_static && matches!(sprite.mode, SpriteMode::Fixed) && sprite.material.is_none() {
+			// baked into its chunk by `static_batch`
+			continue;
+		}
+		if !visibility.get() {
+			continue;
+		}
+
+		let model = transform.as_model_matrix();
+		let size = sprite.size;
+		let billboard = matches!(sprite.mode, SpriteMode::Billboard) as u32;
+		let light = sprite.light *
+			map.as_ref()
+				.map_or(1.0, |map| map.light_at(transform.translation));
+		let instance = SpriteInstance {
+			model,
+			size,
+			billboard,
+			texture: TextureId::instance_index(sprite.texture),
+			tint: sprite.tint,
+			uv_rect: sprite.instance_uv_rect(),
+			light,
+			flags: sprite.instance_flags(),
+			_padding: default(),
+		};
+		extracted.sprites.push(ExtractedSprite {
+			instance,
+			layers: layers.copied().unwrap_or_default(),
+			area: areas.sprite_area(transform, sprite),
+			material: sprite.material,
+		});
+	}
+	extracted.sprites.sort_by_key(|sprite| sprite.material);
+}
+
+/// Works out the frame's views from what [`extract_frame`] copied, and
+/// uploads their uniforms and sprite instances.
+#[expect(clippy::too_many_arguments)]
+pub(crate) fn prepare_frame(
+	ctx: NonSend<GraphicsContext>,
+	mut pipelines: NonSendMut<Pipelines>,
+	extracted: Res<ExtractedFrame>,
+	areas: Res<Areas>,
+	materials: NonSend<Materials>,
+	mut views: ResMut<Views>,
+	mut instances: Local<Vec<SpriteInstance>>,
+	mut blended: Local<Vec<(f32, ExtractedSprite)>>,
+	mut culling: NonSendMut<SpriteCulling>,
+) {
+	if let Some(size) = extracted.resize {
+		let surface_config = ctx
+			.surface
+			.get_default_config(&ctx.adapter, size.x, size.y)
+			.expect("couldn't get surface config");
+		ctx.surface.configure(&ctx.device, &surface_config);
+
+		ctx.destroy_texture(&pipelines.depth_texture);
+		pipelines.depth_texture = ctx.create_texture(&wgpu::TextureDescriptor {
+			label: Some("depth texture"),
+			size: wgpu::Extent3d {
+				width: size.x,
+				height: size.y,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: wgpu::TextureFormat::Depth24Plus,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+			view_formats: &[],
+		});
+	}
+
+	let canvas = Vec2::new(
+		pipelines.depth_texture.width() as f32,
+		pipelines.depth_texture.height() as f32,
+	);
+	let mut new_views = collect_views(&extracted.cameras, canvas, pipelines.uniforms_stride);
+	if let Some(open) = &extracted.open_areas {
+		for view in &mut new_views {
+			view.areas = areas.visible_from(open, view.eye, &view.frustum);
+		}
+	}
+
+	let fog = extracted.fog;
+	let (fog_mode, fog_start, fog_end, fog_density) = fog.uniform_params();
+	for view in &new_views {
+		let uniforms = Uniforms {
+			projection: view.projection,
+			view: view.view,
+			time: extracted.time,
+			fog_mode,
+			fog_start,
+			fog_end,
+			fog_color: fog.color,
+			fog_density,
+			color_mode: extracted.color_mode.uniform(),
+			_padding: default(),
+		};
+		ctx.write_buffer(
+			&pipelines.uniforms,
+			view.uniforms_offset as _,
+			bytemuck::bytes_of(&uniforms),
+		);
+	}
+
+	// each view gets its own runs of instances per material, holding the
+	// sprites on its layers in areas it can see, already frustum culled unless
+	// that happens on the GPU
+	let instances = &mut *instances;
+	instances.clear();
+	let mut slot = 0;
+	let mut culled = 0;
+	for view in &mut new_views {
+		for group in extracted.sprites.chunk_by(|a, b| a.material == b.material) {
+			if materials.is_transparent(group[0].material) {
+				continue;
+			}
+			let first = instances.len() as u32;
+			let mut on_layers = 0;
+			instances.extend(
+				group
+					.iter()
+					.filter(|sprite| sprite.layers.intersects(view.layers))
+					.inspect(|_| on_layers += 1)
+					.filter(|sprite| sprite.is_visible(view, culling.is_gpu()))
+					.map(|sprite| sprite.instance),
+			);
+			culled += on_layers - (instances.len() as u32 - first);
+			if instances.len() as u32 > first {
+				view.sprite_batches.push(SpriteBatch {
+					material: group[0].material,
+					instances: first .. instances.len() as u32,
+					slot,
+					sorted: false,
+				});
+				slot += 1;
+			}
+		}
+
+		// blended sprites are sorted back to front across materials, split
+		// into a batch wherever the material changes
+		blended.clear();
+		let mut on_layers = 0;
+		blended.extend(
+			extracted
+				.sprites
+				.iter()
+				.filter(|sprite| {
+					materials.is_transparent(sprite.material) &&
+						sprite.layers.intersects(view.layers)
+				})
+				.inspect(|_| on_layers += 1)
+				.filter(|sprite| sprite.is_visible(view, false))
+				.map(|sprite| {
+					let position = sprite.instance.model.w_axis.truncate();
+					(view.view.transform_point3(position).z, *sprite)
+				}),
+		);
+		culled += on_layers - blended.len() as u32;
+		// view space looks down -z, so the farthest sprites have the lowest
+		// depth and are drawn first
+		blended.sort_by(|a, b| a.0.total_cmp(&b.0));
+		for run in blended.chunk_by(|a, b| a.1.material == b.1.material) {
+			let first = instances.len() as u32;
+			instances.extend(run.iter().map(|(_, sprite)| sprite.instance));
+			view.sprite_batches.push(SpriteBatch {
+				material: run[0].1.material,
+				instances: first .. instances.len() as u32,
+				slot,
+				sorted: true,
+			});
+			slot += 1;
+		}
+	}
+
+	let bytes = bytemuck::cast_slice::<SpriteInstance, u8>(instances);
+	let replaced = (pipelines.instances.size() as usize) < bytes.len();
+	if replaced {
+		pipelines.instances = create_instances_buffer(&ctx, bytes.len().next_power_of_two());
+	}
+	ctx.write_buffer(&pipelines.instances, 0, bytes);
+	culling.prepare(&ctx, &new_views, &pipelines.instances, replaced);
+	ctx.record(|stats| stats.instances_culled += culled);
+
+	views.0 = new_views;
+}
+
+fn frame_begin(world: &mut World) {
+	let ctx = world.non_send_resource::<GraphicsContext>();
+	let pipelines = world.non_send_resource::<Pipelines>();
+
+	let surface_texture = ctx
+		.surface
+		.get_current_texture()
+		.expect("couldn't get canvas texture");
+	let color = surface_texture
+		.texture
+		.create_view(&wgpu::TextureViewDescriptor::default());
+	let depth = pipelines
+		.depth_texture
+		.create_view(&wgpu::TextureViewDescriptor::default());
+	let encoder = ctx
+		.device
+		.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+	world.insert_non_send_resource(ActiveFrame {
+		encoder,
+		canvas: FrameTarget::new(color, depth),
+		offscreen: None,
+		surface_texture,
+	});
+}
+
+#[expect(clippy::too_many_arguments)]
+fn draw_sprites(
+	frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	culling: NonSend<SpriteCulling>,
+	textures: NonSend<SpriteTextures>,
+	materials: NonSend<Materials>,
+	profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+) {
+	draw_sprite_batches(
+		frame, ctx, pipelines, culling, textures, materials, profiler, view, false,
+	);
+}
+
+/// Draws sprites whose material blends.
+#[expect(clippy::too_many_arguments)]
+fn draw_transparent_sprites(
+	frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	culling: NonSend<SpriteCulling>,
+	textures: NonSend<SpriteTextures>,
+	materials: NonSend<Materials>,
+	profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+) {
+	draw_sprite_batches(
+		frame, ctx, pipelines, culling, textures, materials, profiler, view, true,
+	);
+}
+
+#[expect(clippy::too_many_arguments)]
+fn draw_sprite_batches(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	culling: NonSend<SpriteCulling>,
+	textures: NonSend<SpriteTextures>,
+	materials: NonSend<Materials>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+	transparent: bool,
+) {
+	let view = view.get();
+	let mut batches = view
+		.sprite_batches
+		.iter()
+		.filter(|batch| materials.is_transparent(batch.material) == transparent)
+		.peekable();
+	// the opaque pass also clears the view's target, so it always runs
+	if transparent && batches.peek().is_none() {
+		return;
+	}
+
+	let label = if transparent {
+		"transparent sprites"
+	} else {
+		"sprites"
+	};
+	let mut pass = frame.begin_view_pass(label, &mut profiler, view);
+	pass.set_bind_group(0, &pipelines.uniforms_group, &[view.uniforms_offset]);
+	pass.set_bind_group(1, &textures.group, &[]);
+	for batch in batches {
+		match batch.material {
+			Some(material) => {
+				let material = materials.get(material);
+				pass.set_pipeline(&material.pipeline);
+				ctx.record_pipeline_switch();
+				if let Some(bind_group) = &material.bind_group {
+					pass.set_bind_group(2, bind_group, &[]);
+				}
+			},
+			None => {
+				pass.set_pipeline(&pipelines.pipeline);
+				ctx.record_pipeline_switch();
+			},
+		}
+		culling.draw(&mut pass, &pipelines.instances, batch);
+		// instances drawn after GPU culling are counted once read back
+		ctx.record_draw(if culling.culls_on_gpu(batch) {
+			0
+		} else {
+			batch.instances.len() as _
+		});
+	}
+	drop(pass);
+	profiler.end_pass();
+}
+
+fn frame_end(world: &mut World) {
+	let Some(mut frame) = world.remove_non_send_resource::<ActiveFrame>() else {
+		return;
+	};
+	let mut profiler = world.non_send_resource_mut::<GpuProfiler>();
+	profiler.resolve(&mut frame.encoder);
+
+	let ctx = world.non_send_resource::<GraphicsContext>();
+	ctx.queue.submit([frame.encoder.finish()]);
+	world.non_send_resource_mut::<GpuProfiler>().after_submit();
+	frame.surface_texture.present();
+}
+
+fn collect_stats(
+	ctx: NonSend<GraphicsContext>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	mut timings: ResMut<PassTimings>,
+	mut stats: ResMut<RenderStats>,
+) {
+	*stats = ctx.stats.get();
+	// textures stay allocated across frames, the rest starts over
+	ctx.stats.set(RenderStats {
+		texture_bytes: stats.texture_bytes,
+		..default()
+	});
+	profiler.collect(&mut timings, &mut stats);
+}
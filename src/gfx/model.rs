@@ -0,0 +1,205 @@
+//! Loading of 3D models from glTF and OBJ files into [`MeshData`] and
+//! base-colour textures, ready for [`Meshes::add_model`]. Models are converted
+//! from the files' +Y up to this crate's +Z up, with their front facing -Y.
+//!
+//! [`Meshes::add_model`]: crate::gfx::mesh::Meshes::add_model
+
+use std::f32::consts::FRAC_PI_2;
+
+use bevy_platform::collections::HashMap;
+use image::imageops::FilterType;
+
+use crate::{
+	gfx::{
+		Color,
+		mesh::{MeshData, MeshVertex},
+		textures::{SPRITE_TEXTURE_SIZE, Texel},
+	},
+	prelude::*,
+};
+
+/// A loaded model, not yet uploaded.
+#[derive(Clone, Debug, Default)]
+pub struct Model {
+	pub parts: Vec<ModelPart>,
+	/// Textures of [`SPRITE_TEXTURE_SIZE`] squared texels, shared between
+	/// parts.
+	pub images: Vec<Vec<Texel>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ModelPart {
+	pub data: MeshData,
+	pub base_color: Color,
+	/// Index into [`Model::images`].
+	pub image: Option<usize>,
+}
+
+/// Turns the +Y up coordinates of glTF and OBJ into +Z up.
+fn y_up_to_z_up() -> Mat4 {
+	Mat4::from_rotation_x(FRAC_PI_2)
+}
+
+/// Loads the default scene of a binary glTF (`.glb`) file, flattening its node
+/// hierarchy. Only buffers and images stored in the binary chunk are read.
+pub fn load_gltf(bytes: &[u8]) -> Result<Model, String> {
+	let gltf = gltf::Gltf::from_slice(bytes).map_err(|error| error.to_string())?;
+	let blob = gltf.blob.as_deref();
+	let buffer_data = |buffer: gltf::Buffer<'_>| match buffer.source() {
+		gltf::buffer::Source::Bin => blob,
+		gltf::buffer::Source::Uri(_) => None,
+	};
+
+	let mut model = Model::default();
+	let mut images = HashMap::<usize, usize>::default();
+	let Some(scene) = gltf.default_scene().or_else(|| gltf.scenes().next()) else {
+		return Err("glTF file has no scenes".into());
+	};
+	let mut nodes: Vec<_> = scene.nodes().map(|node| (node, y_up_to_z_up())).collect();
+	while let Some((node, parent)) = nodes.pop() {
+		let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+		nodes.extend(node.children().map(|child| (child, transform)));
+		let Some(mesh) = node.mesh() else {
+			continue;
+		};
+
+		for primitive in mesh.primitives() {
+			if primitive.mode() != gltf::mesh::Mode::Triangles {
+				log::warn!("skipping glTF primitive with mode {:?}", primitive.mode());
+				continue;
+			}
+			let reader = primitive.reader(buffer_data);
+			let Some(positions) = reader.read_positions() else {
+				return Err("glTF primitive has no positions".into());
+			};
+			let mut vertices: Vec<MeshVertex> = positions
+				.map(|position| MeshVertex {
+					position: Vec3::from(position),
+					..default()
+				})
+				.collect();
+			if let Some(normals) = reader.read_normals() {
+				for (vertex, normal) in vertices.iter_mut().zip(normals) {
+					vertex.normal = Vec3::from(normal);
+				}
+			}
+			if let Some(uvs) = reader.read_tex_coords(0) {
+				for (vertex, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+					vertex.uv = Vec2::from(uv);
+				}
+			}
+			let indices = match reader.read_indices() {
+				Some(indices) => indices.into_u32().collect(),
+				None => (0 .. vertices.len() as u32).collect(),
+			};
+			let mut data = MeshData { vertices, indices };
+			if reader.read_normals().is_none() {
+				data.compute_normals();
+			}
+			data.transform(transform);
+
+			let material = primitive.material().pbr_metallic_roughness();
+			let [r, g, b, a] = material.base_color_factor();
+			let image = match material.base_color_texture() {
+				Some(info) => {
+					let image = info.texture().source();
+					let index = match images.get(&image.index()) {
+						Some(&index) => index,
+						None => {
+							model.images.push(gltf_image(&image, blob)?);
+							images.insert(image.index(), model.images.len() - 1);
+							model.images.len() - 1
+						},
+					};
+					Some(index)
+				},
+				None => None,
+			};
+			model.parts.push(ModelPart {
+				data,
+				base_color: Color::rgba(r, g, b, a),
+				image,
+			});
+		}
+	}
+
+	Ok(model)
+}
+
+fn gltf_image(image: &gltf::Image<'_>, blob: Option<&[u8]>) -> Result<Vec<Texel>, String> {
+	let gltf::image::Source::View { view, .. } = image.source() else {
+		return Err("external glTF images are not supported".into());
+	};
+	let bytes = blob
+		.filter(|_| matches!(view.buffer().source(), gltf::buffer::Source::Bin))
+		.and_then(|blob| blob.get(view.offset() .. view.offset() + view.length()))
+		.ok_or("glTF image is not in the binary chunk")?;
+	decode_image(bytes)
+}
+
+/// Decodes a PNG or JPEG, scaling it to [`SPRITE_TEXTURE_SIZE`] squared.
+pub fn decode_image(bytes: &[u8]) -> Result<Vec<Texel>, String> {
+	let image = image::load_from_memory(bytes)
+		.map_err(|error| error.to_string())?
+		.to_rgba8();
+	let image = image::imageops::resize(
+		&image,
+		SPRITE_TEXTURE_SIZE,
+		SPRITE_TEXTURE_SIZE,
+		FilterType::Triangle,
+	);
+	Ok(image.pixels().map(|pixel| pixel.0).collect())
+}
+
+/// Loads every object of a Wavefront OBJ file as a white, untextured part.
+/// Materials are not read; give the parts textures and colours afterwards.
+pub fn load_obj(source: &str) -> Result<Model, String> {
+	let options = tobj::LoadOptions {
+		triangulate: true,
+		single_index: true,
+		..default()
+	};
+	let (models, _) = tobj::load_obj_buf(&mut source.as_bytes(), &options, |_| {
+		Err(tobj::LoadError::OpenFileFailed)
+	})
+	.map_err(|error| error.to_string())?;
+
+	let parts = models
+		.into_iter()
+		.map(|model| {
+			let mesh = model.mesh;
+			let vertices = (0 .. mesh.positions.len() / 3)
+				.map(|index| MeshVertex {
+					position: Vec3::from_slice(&mesh.positions[index * 3 ..]),
+					normal: mesh
+						.normals
+						.get(index * 3 .. index * 3 + 3)
+						.map_or(Vec3::ZERO, Vec3::from_slice),
+					// OBJ texture coordinates run bottom to top
+					uv: mesh
+						.texcoords
+						.get(index * 2 .. index * 2 + 2)
+						.map_or(Vec2::ZERO, |uv| Vec2::new(uv[0], 1.0 - uv[1])),
+				})
+				.collect();
+			let mut data = MeshData {
+				vertices,
+				indices: mesh.indices,
+			};
+			if mesh.normals.is_empty() {
+				data.compute_normals();
+			}
+			data.transform(y_up_to_z_up());
+			ModelPart {
+				data,
+				base_color: Color::WHITE,
+				image: None,
+			}
+		})
+		.collect();
+
+	Ok(Model {
+		parts,
+		images: Vec::new(),
+	})
+}
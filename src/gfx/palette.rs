@@ -0,0 +1,191 @@
+//! Palette-indexed rendering, as in Wolf3D and Doom. Textures added with
+//! [`SpriteTextures::add_indexed`] hold indices into the [`Palette`], and in
+//! [`ColorMode::Palette`] they are lit by remapping their indices through a
+//! row of the [`Colormap`] rather than by scaling their colour. Replacing the
+//! palette recolours every indexed texture at once, for palette shifts like
+//! damage flashes.
+
+use crate::{
+	gfx::{Color, GraphicsContext, RenderPre, RenderPreSet, textures::SpriteTextures},
+	prelude::*,
+};
+
+/// Colours in a palette.
+pub const PALETTE_SIZE: usize = 256;
+/// Rows of the colormap, from full brightness to black. Must match
+/// `LIGHT_LEVELS` in `common/sprite_textures.wgsl`.
+pub const LIGHT_LEVELS: usize = 32;
+
+/// How indexed textures are lit. Textures added as colours are unaffected.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+	/// Palette colours scaled by the light, like any other texture.
+	#[default]
+	TrueColor,
+	/// Palette colours picked through the [`Colormap`] row for the light
+	/// level, banding like the originals.
+	Palette,
+}
+
+impl ColorMode {
+	/// Value of `Uniforms::color_mode`.
+	pub(crate) fn uniform(self) -> u32 {
+		match self {
+			Self::TrueColor => 0,
+			Self::Palette => 1,
+		}
+	}
+}
+
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; PALETTE_SIZE]);
+
+impl Default for Palette {
+	/// A 6×7×6 colour cube followed by a grey ramp.
+	fn default() -> Self {
+		let mut colors = [[0; 3]; PALETTE_SIZE];
+		for (index, color) in colors.iter_mut().enumerate() {
+			*color = if index < 6 * 7 * 6 {
+				let channel = |value: usize, steps: usize| (value * 255 / (steps - 1)) as u8;
+				[
+					channel(index / 42, 6),
+					channel(index / 6 % 7, 7),
+					channel(index % 6, 6),
+				]
+			} else {
+				let grey = ((index - 6 * 7 * 6) * 255 / (PALETTE_SIZE - 6 * 7 * 6 - 1)) as u8;
+				[grey; 3]
+			};
+		}
+		Self(colors)
+	}
+}
+
+impl Palette {
+	/// This palette blended `amount` of the way towards `color`, for palette
+	/// shifts such as red when hurt or gold on pickups.
+	pub fn shifted(&self, color: Color, amount: f32) -> Self {
+		let target = color.to_vec4().truncate() * 255.0;
+		Self(self.0.map(|rgb| {
+			let rgb = Vec3::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32);
+			let mixed = rgb.lerp(target, amount.clamp(0.0, 1.0)).round();
+			[mixed.x as u8, mixed.y as u8, mixed.z as u8]
+		}))
+	}
+
+	/// Index of the colour closest to `rgb`.
+	pub fn nearest(&self, rgb: [u8; 3]) -> u8 {
+		let distance = |color: &[u8; 3]| {
+			(0 .. 3)
+				.map(|channel| (color[channel] as i32 - rgb[channel] as i32).pow(2))
+				.sum::<i32>()
+		};
+		(0 .. PALETTE_SIZE)
+			.min_by_key(|&index| distance(&self.0[index]))
+			.unwrap() as u8
+	}
+}
+
+/// For each light level, the palette index each palette index is drawn as.
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct Colormap(pub Box<[[u8; PALETTE_SIZE]; LIGHT_LEVELS]>);
+
+impl Colormap {
+	/// Darkens every colour of `palette` linearly towards black, mapping each
+	/// back to the nearest palette colour.
+	pub fn from_palette(palette: &Palette) -> Self {
+		let mut levels = Box::new([[0; PALETTE_SIZE]; LIGHT_LEVELS]);
+		for (level, row) in levels.iter_mut().enumerate() {
+			let brightness = 1.0 - level as f32 / (LIGHT_LEVELS - 1) as f32;
+			for (index, mapped) in row.iter_mut().enumerate() {
+				let dimmed =
+					palette.0[index].map(|channel| (channel as f32 * brightness).round() as u8);
+				*mapped = palette.nearest(dimmed);
+			}
+		}
+		Self(levels)
+	}
+}
+
+impl Default for Colormap {
+	fn default() -> Self {
+		Self::from_palette(&Palette::default())
+	}
+}
+
+/// Palette and colormap changes waiting to be uploaded.
+#[derive(Resource, Default)]
+struct ExtractedPalette {
+	palette: Option<Palette>,
+	colormap: Option<Colormap>,
+}
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<ColorMode>();
+	app.init_resource::<Palette>();
+	app.init_resource::<Colormap>();
+	app.init_resource::<ExtractedPalette>();
+	app.add_systems(
+		RenderPre,
+		(
+			extract_palette.in_set(RenderPreSet::Extract),
+			upload_palette.in_set(RenderPreSet::Prepare),
+		),
+	);
+}
+
+fn extract_palette(
+	mut extracted: ResMut<ExtractedPalette>,
+	palette: Res<Palette>,
+	colormap: Res<Colormap>,
+) {
+	if palette.is_changed() {
+		extracted.palette = Some(palette.clone());
+	}
+	if colormap.is_changed() {
+		extracted.colormap = Some(colormap.clone());
+	}
+}
+
+fn upload_palette(
+	ctx: NonSend<GraphicsContext>,
+	textures: NonSend<SpriteTextures>,
+	mut extracted: ResMut<ExtractedPalette>,
+) {
+	if let Some(palette) = extracted.palette.take() {
+		let texels = palette.0.map(|[r, g, b]| [r, g, b, 255]);
+		write_texture(&ctx, &textures.palette, bytemuck::cast_slice(&texels), 4, 1);
+	}
+	if let Some(colormap) = extracted.colormap.take() {
+		write_texture(
+			&ctx,
+			&textures.colormap,
+			bytemuck::cast_slice(colormap.0.as_slice()),
+			1,
+			LIGHT_LEVELS as _,
+		);
+	}
+}
+
+fn write_texture(
+	ctx: &GraphicsContext,
+	texture: &wgpu::Texture,
+	data: &[u8],
+	texel_size: u32,
+	rows: u32,
+) {
+	ctx.write_texture(
+		texture.as_image_copy(),
+		data,
+		wgpu::TexelCopyBufferLayout {
+			offset: 0,
+			bytes_per_row: Some(PALETTE_SIZE as u32 * texel_size),
+			rows_per_image: None,
+		},
+		wgpu::Extent3d {
+			width: PALETTE_SIZE as _,
+			height: rows,
+			depth_or_array_layers: 1,
+		},
+	);
+}
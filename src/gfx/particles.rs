@@ -0,0 +1,467 @@
+//! Particle effects such as sparks, blood and smoke. Particles are plain data
+//! in a shared pool rather than entities, simulated in [`FixedUpdate`] and
+//! drawn as alpha-blended billboard sprite instances.
+
+use std::ops::Range;
+
+use wgpu::BufferUsages;
+
+use crate::{
+	gfx::{
+		ActiveFrame,
+		Color,
+		GraphicsContext,
+		Pipelines,
+		RenderPre,
+		RenderPreSet,
+		SpriteInstance,
+		WorldRenderer,
+		camera::{CurrentView, RenderView, ViewSet, Views},
+		prepare_frame,
+		profiling::GpuProfiler,
+		textures::{SPRITE_TEXTURE_SIZE, SpriteTextures, Texel, TextureId},
+		visibility::{InheritedVisibility, RenderLayers},
+	},
+	map::TileMap,
+	prelude::*,
+	transform::Transform,
+};
+
+/// Most particles alive at once. Emitters stop spawning while the pool is
+/// full.
+pub const MAX_PARTICLES: usize = 4096;
+
+/// Spawns particles at the entity's `Transform`, both in an initial burst and
+/// continuously. The particles are drawn on the entity's [`RenderLayers`], and
+/// hidden along with it while it is alive.
+#[derive(Clone, Debug, Component)]
+#[require(EmitterState)]
+pub struct ParticleEmitter {
+	/// Particles spawned per second.
+	pub rate: f32,
+	/// Particles spawned at once when the emitter is added.
+	pub burst: u32,
+	/// Despawns the emitter after its burst, for one-off effects like hits and
+	/// explosions.
+	pub one_shot: bool,
+	/// Seconds each particle lives, picked at random from the range.
+	pub lifetime: Range<f32>,
+	/// Axis of the cone particles are launched in, relative to the emitter's
+	/// rotation.
+	pub direction: Vec3,
+	/// Angle in radians between the cone's axis and its edge.
+	pub spread: f32,
+	pub speed: Range<f32>,
+	/// Downward acceleration, in units per second squared.
+	pub gravity: f32,
+	/// Particle size at the start of its life, shrinking or growing towards
+	/// `end_size`.
+	pub start_size: f32,
+	pub end_size: f32,
+	/// Particle colour at the start of its life, fading towards `end_color`.
+	/// Multiplied with the texture.
+	pub start_color: Color,
+	pub end_color: Color,
+	pub texture: Option<TextureId>,
+	/// Animation frames following `texture`, played once over a particle's
+	/// life.
+	pub frames: u32,
+}
+
+impl Default for ParticleEmitter {
+	fn default() -> Self {
+		Self {
+			rate: 0.0,
+			burst: 0,
+			one_shot: false,
+			lifetime: 1.0 .. 1.0,
+			direction: Transform::UP,
+			spread: 0.0,
+			speed: 1.0 .. 1.0,
+			gravity: 0.0,
+			start_size: 0.1,
+			end_size: 0.1,
+			start_color: Color::WHITE,
+			end_color: Color::WHITE,
+			texture: None,
+			frames: 1,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, Component)]
+struct EmitterState {
+	/// Fraction of a particle left over from previous ticks.
+	pending: f32,
+	burst_done: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Particle {
+	emitter: Entity,
+	layers: RenderLayers,
+	position: Vec3,
+	velocity: Vec3,
+	age: f32,
+	lifetime: f32,
+	gravity: f32,
+	start_size: f32,
+	end_size: f32,
+	start_color: Color,
+	end_color: Color,
+	texture: Option<TextureId>,
+	frames: u32,
+}
+
+impl Particle {
+	fn instance(&self, light: f32) -> SpriteInstance {
+		let t = (self.age / self.lifetime).clamp(0.0, 1.0);
+		let size = self.start_size.lerp(self.end_size, t);
+		let color = self.start_color.to_vec4().lerp(self.end_color.to_vec4(), t);
+		let frame = ((t * self.frames as f32) as u32).min(self.frames.saturating_sub(1));
+		SpriteInstance {
+			model: Mat4::from_translation(self.position),
+			size: Vec2::splat(size),
+			billboard: 1,
+			texture: TextureId::instance_index(self.texture.map(|texture| texture.frame(frame))),
+			tint: color.into(),
+			uv_rect: SpriteInstance::FULL_UV_RECT,
+			light,
+			flags: TextureId::instance_flags(self.texture),
+			_padding: default(),
+		}
+	}
+}
+
+/// Pool of live particles from every emitter.
+#[derive(Resource)]
+pub struct Particles {
+	particles: Vec<Particle>,
+	rng: Rng,
+}
+
+impl Default for Particles {
+	fn default() -> Self {
+		Self {
+			particles: Vec::new(),
+			rng: Rng(0x2545_F491),
+		}
+	}
+}
+
+impl Particles {
+	pub fn len(&self) -> usize {
+		self.particles.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.particles.is_empty()
+	}
+
+	/// Spawns `count` particles from the emitter `entity` at `transform`, on
+	/// `layers`.
+	pub fn emit(
+		&mut self,
+		entity: Entity,
+		emitter: &ParticleEmitter,
+		transform: &Transform,
+		layers: RenderLayers,
+		count: u32,
+	) {
+		let axis = transform.rotation * emitter.direction.normalize_or(Transform::UP);
+		let count = (count as usize).min(MAX_PARTICLES - self.particles.len());
+		for _ in 0 .. count {
+			let direction = self.rng.in_cone(axis, emitter.spread);
+			let speed = self.rng.range(&emitter.speed);
+			let particle = Particle {
+				emitter: entity,
+				layers,
+				position: transform.translation,
+				velocity: direction * speed,
+				age: 0.0,
+				lifetime: self.rng.range(&emitter.lifetime).max(f32::EPSILON),
+				gravity: emitter.gravity,
+				start_size: emitter.start_size,
+				end_size: emitter.end_size,
+				start_color: emitter.start_color,
+				end_color: emitter.end_color,
+				texture: emitter.texture,
+				frames: emitter.frames.max(1),
+			};
+			self.particles.push(particle);
+		}
+	}
+}
+
+/// Xorshift generator, plenty for scattering particles.
+struct Rng(u32);
+
+impl Rng {
+	fn next_f32(&mut self) -> f32 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 17;
+		self.0 ^= self.0 << 5;
+		(self.0 >> 8) as f32 / (1 << 24) as f32
+	}
+
+	fn range(&mut self, range: &Range<f32>) -> f32 {
+		range.start + (range.end - range.start) * self.next_f32()
+	}
+
+	/// Uniformly distributed direction within `angle` radians of `axis`.
+	fn in_cone(&mut self, axis: Vec3, angle: f32) -> Vec3 {
+		let cos = 1.0 - (1.0 - angle.cos()) * self.next_f32();
+		let sin = (1.0 - cos * cos).max(0.0).sqrt();
+		let around = self.next_f32() * std::f32::consts::TAU;
+		let (x, y) = axis.any_orthonormal_pair();
+		axis * cos + (x * around.cos() + y * around.sin()) * sin
+	}
+}
+
+/// Textures for the built-in effects.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ParticleTextures {
+	/// Small hard-edged dot.
+	pub spark: TextureId,
+	/// Soft round puff, in [`PUFF_FRAMES`] frames that spread out and thin.
+	pub puff: TextureId,
+}
+
+pub const PUFF_FRAMES: u32 = 4;
+
+impl ParticleTextures {
+	pub(super) fn new(
+		ctx: &GraphicsContext,
+		textures: &mut SpriteTextures,
+	) -> Result<Self, String> {
+		let spark = disc(|distance| if distance < 0.35 { 1.0 } else { 0.0 });
+		let puffs: Vec<_> = (0 .. PUFF_FRAMES)
+			.map(|frame| {
+				let radius = 0.6 + frame as f32 * 0.1;
+				let density = 1.0 - frame as f32 / PUFF_FRAMES as f32 * 0.6;
+				disc(|distance| (1.0 - distance / radius).clamp(0.0, 1.0) * density)
+			})
+			.collect();
+		Ok(Self {
+			spark: textures.add(ctx, &spark)?,
+			puff: textures.add_frames(ctx, puffs.iter().map(Vec::as_slice))?,
+		})
+	}
+}
+
+/// White texture with alpha given by a function of the distance from the
+/// centre, where `1.0` is the edge.
+fn disc(alpha: impl Fn(f32) -> f32) -> Vec<Texel> {
+	let size = SPRITE_TEXTURE_SIZE as usize;
+	(0 .. size * size)
+		.map(|index| {
+			let texel = Vec2::new((index % size) as f32, (index / size) as f32) + 0.5;
+			let distance = (texel / size as f32 * 2.0 - 1.0).length();
+			[255, 255, 255, (alpha(distance) * 255.0) as u8]
+		})
+		.collect()
+}
+
+pub struct ParticleInstances {
+	buffer: wgpu::Buffer,
+	/// Each view's run of instances, sorted back to front for that view.
+	ranges: Vec<Range<u32>>,
+}
+
+impl ParticleInstances {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		Self {
+			buffer: create_instances_buffer(ctx, 256),
+			ranges: Vec::new(),
+		}
+	}
+}
+
+fn create_instances_buffer(ctx: &GraphicsContext, length: usize) -> wgpu::Buffer {
+	ctx.device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("particle instances"),
+		size: (size_of::<SpriteInstance>() * length) as _,
+		usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
+
+#[derive(Clone, Copy)]
+struct ExtractedParticle {
+	position: Vec3,
+	/// Bounding sphere radius over the particle's whole life.
+	radius: f32,
+	layers: RenderLayers,
+	instance: SpriteInstance,
+}
+
+/// Every particle whose emitter isn't hidden, lit by the map.
+#[derive(Resource, Default)]
+struct ExtractedParticles(Vec<ExtractedParticle>);
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<Particles>();
+	app.init_resource::<ExtractedParticles>();
+
+	app.add_systems(FixedUpdate, (emit_particles, simulate_particles).chain());
+	app.add_systems(
+		RenderPre,
+		(
+			extract_particles.in_set(RenderPreSet::Extract),
+			prepare_particles
+				.in_set(RenderPreSet::Prepare)
+				.after(prepare_frame),
+		),
+	);
+	app.add_systems(
+		RenderView,
+		draw_particles
+			.in_set(ViewSet::Transparent)
+			.run_if(resource_equals(WorldRenderer::Polygonal)),
+	);
+}
+
+fn emit_particles(
+	mut cmd: Commands,
+	mut particles: ResMut<Particles>,
+	mut emitters: Query<(
+		Entity,
+		&Transform,
+		&ParticleEmitter,
+		&mut EmitterState,
+		Option<&RenderLayers>,
+	)>,
+	time: Res<Time<Fixed>>,
+) {
+	for (entity, transform, emitter, mut state, layers) in emitters.iter_mut() {
+		let layers = layers.copied().unwrap_or_default();
+		if !state.burst_done {
+			state.burst_done = true;
+			particles.emit(entity, emitter, transform, layers, emitter.burst);
+			if emitter.one_shot {
+				cmd.entity(entity).despawn();
+				continue;
+			}
+		}
+
+		state.pending += emitter.rate * time.delta_secs();
+		let count = state.pending as u32;
+		state.pending -= count as f32;
+		particles.emit(entity, emitter, transform, layers, count);
+	}
+}
+
+fn simulate_particles(mut particles: ResMut<Particles>, time: Res<Time<Fixed>>) {
+	let delta = time.delta_secs();
+	particles.particles.retain_mut(|particle| {
+		particle.age += delta;
+		particle.velocity.z -= particle.gravity * delta;
+		particle.position += particle.velocity * delta;
+		particle.age < particle.lifetime
+	});
+}
+
+fn extract_particles(
+	mut extracted: ResMut<ExtractedParticles>,
+	particles: Res<Particles>,
+	emitters: Query<&InheritedVisibility>,
+	map: Option<Res<TileMap>>,
+) {
+	extracted.0.clear();
+	extracted.0.extend(
+		particles
+			.particles
+			.iter()
+			// particles of despawned emitters, such as one-shots, live on
+			.filter(|particle| {
+				emitters
+					.get(particle.emitter)
+					.ok()
+					.is_none_or(|visibility| visibility.get())
+			})
+			.map(|particle| {
+				let light = map
+					.as_ref()
+					.map_or(1.0, |map| map.light_at(particle.position));
+				ExtractedParticle {
+					position: particle.position,
+					radius: particle.start_size.max(particle.end_size),
+					layers: particle.layers,
+					instance: particle.instance(light),
+				}
+			}),
+	);
+}
+
+fn prepare_particles(
+	ctx: NonSend<GraphicsContext>,
+	mut particle_instances: NonSendMut<ParticleInstances>,
+	extracted: Res<ExtractedParticles>,
+	views: Res<Views>,
+	mut instances: Local<Vec<(f32, SpriteInstance)>>,
+	mut sorted: Local<Vec<SpriteInstance>>,
+) {
+	let particle_instances = &mut *particle_instances;
+	particle_instances.ranges.clear();
+	sorted.clear();
+	for view in &views.0 {
+		instances.clear();
+		for particle in &extracted.0 {
+			if !particle.layers.intersects(view.layers) ||
+				!view
+					.frustum
+					.intersects_sphere(particle.position, particle.radius)
+			{
+				continue;
+			}
+			let depth = view.view.transform_point3(particle.position).z;
+			instances.push((depth, particle.instance));
+		}
+		// view space looks down -z, so the farthest particles have the lowest
+		// depth and are drawn first
+		instances.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+		let first = sorted.len() as u32;
+		sorted.extend(instances.iter().map(|&(_, instance)| instance));
+		particle_instances.ranges.push(first .. sorted.len() as u32);
+	}
+
+	if sorted.is_empty() {
+		return;
+	}
+	let bytes = bytemuck::cast_slice::<SpriteInstance, u8>(&sorted);
+	if (particle_instances.buffer.size() as usize) < bytes.len() {
+		particle_instances.buffer = create_instances_buffer(&ctx, sorted.len().next_power_of_two());
+	}
+	ctx.write_buffer(&particle_instances.buffer, 0, bytes);
+}
+
+pub(super) fn draw_particles(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	particle_instances: NonSend<ParticleInstances>,
+	textures: NonSend<SpriteTextures>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+) {
+	let Some(range) = particle_instances
+		.ranges
+		.get(view.index())
+		.filter(|range| !range.is_empty())
+	else {
+		return;
+	};
+
+	let view = view.get();
+	let mut pass = frame.begin_view_pass("particles", &mut profiler, view);
+	pass.set_pipeline(&pipelines.transparent_pipeline);
+	ctx.record_pipeline_switch();
+	pass.set_bind_group(0, &pipelines.uniforms_group, &[view.uniforms_offset]);
+	pass.set_bind_group(1, &textures.group, &[]);
+	pass.set_vertex_buffer(0, particle_instances.buffer.slice(..));
+	pass.draw(0 .. 4, range.clone());
+	ctx.record_draw(range.len() as _);
+	drop(pass);
+	profiler.end_pass();
+}
@@ -0,0 +1,243 @@
+//! Finding what is under a point on the canvas, for editors and debug tools. A
+//! canvas pixel becomes a world-space ray through the camera drawn there, which
+//! is tested against the quads of visible sprites and against the faces of the
+//! map's solid tiles. Walls and doors spawned from the map are picked as tiles
+//! rather than as sprites.
+
+use crate::{
+	gfx::{
+		Sprite,
+		SpriteMode,
+		camera::{RenderTarget, View, Views},
+		visibility::{InheritedVisibility, RenderLayers},
+	},
+	map::{MapGeometry, TileMap},
+	prelude::*,
+	transform::Transform,
+};
+
+/// Furthest a tile is picked from, matching the far plane.
+const MAX_TILE_DISTANCE: f32 = 1000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PickTarget {
+	Sprite(Entity),
+	/// The face of the solid tile at `cell` pointing along `normal`.
+	Tile {
+		cell: IVec2,
+		normal: IVec2,
+	},
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickHit {
+	pub target: PickTarget,
+	pub position: Vec3,
+	/// Distance along the ray to `position`.
+	pub distance: f32,
+}
+
+type PickableSprite = (
+	Entity,
+	&'static Transform,
+	&'static Sprite,
+	&'static InheritedVisibility,
+	Option<&'static RenderLayers>,
+);
+
+/// Picks sprites and tiles with rays from the views of the last frame.
+#[derive(SystemParam)]
+pub struct Picking<'w, 's> {
+	views: Res<'w, Views>,
+	map: Option<Res<'w, TileMap>>,
+	sprites: Query<'w, 's, PickableSprite, Without<MapGeometry>>,
+}
+
+impl Picking<'_, '_> {
+	/// The view drawn last at `position` in canvas pixels, which is the one on
+	/// top.
+	pub fn view_at(&self, position: Vec2) -> Option<&View> {
+		self.views
+			.0
+			.iter()
+			.rev()
+			.filter(|view| view.target == RenderTarget::Canvas)
+			.find(|view| view.viewport.contains(position))
+	}
+
+	/// The world-space ray through `position` in canvas pixels.
+	pub fn ray(&self, position: Vec2) -> Option<Ray3d> {
+		self.view_at(position)?.ray(position)
+	}
+
+	/// The nearest sprite or tile face at `position` in canvas pixels.
+	pub fn pick(&self, position: Vec2) -> Option<PickHit> {
+		let view = self.view_at(position)?;
+		self.cast(view, view.ray(position)?)
+	}
+
+	/// The nearest sprite or tile face along `ray`, with sprites shaped and
+	/// filtered as `view` draws them.
+	pub fn cast(&self, view: &View, ray: Ray3d) -> Option<PickHit> {
+		let tile = self.map.as_ref().and_then(|map| cast_tiles(map, ray));
+		let sprites = self
+			.sprites
+			.iter()
+			.filter(|(_, _, _, visibility, layers)| {
+				visibility.get() && layers.copied().unwrap_or_default().intersects(view.layers)
+			})
+			.filter_map(|(entity, transform, sprite, ..)| {
+				let distance = cast_sprite(view, transform, sprite, ray)?;
+				Some(PickHit {
+					target: PickTarget::Sprite(entity),
+					position: ray.get_point(distance),
+					distance,
+				})
+			});
+		tile.into_iter()
+			.chain(sprites)
+			.min_by(|a, b| a.distance.total_cmp(&b.distance))
+	}
+}
+
+/// Distance along `ray` to the sprite's quad, if it is hit from its visible
+/// side.
+fn cast_sprite(view: &View, transform: &Transform, sprite: &Sprite, ray: Ray3d) -> Option<f32> {
+	// the quad's horizontal and vertical axes, with the same orientation as in
+	// `quad.wgsl`
+	let (right, up) = match sprite.mode {
+		SpriteMode::Billboard => (-view.view.row(0).truncate(), view.view.row(1).truncate()),
+		SpriteMode::Fixed => {
+			let model = transform.as_model_matrix();
+			let normal = model.y_axis.truncate();
+			// fixed quads are culled from behind
+			if ray.direction.dot(normal) >= 0.0 {
+				return None;
+			}
+			(model.x_axis.truncate(), model.z_axis.truncate())
+		},
+	};
+	let normal = Dir3::new(right.cross(up)).ok()?;
+	let center = transform.translation;
+	let distance = ray.intersect_plane(center, InfinitePlane3d { normal })?;
+	let offset = ray.get_point(distance) - center;
+	let local = Vec2::new(offset.dot(right), offset.dot(up));
+	(local.abs().cmple(sprite.size / 2.0).all()).then_some(distance)
+}
+
+/// The first solid tile face along `ray` between the floor and the ceiling.
+fn cast_tiles(map: &TileMap, ray: Ray3d) -> Option<PickHit> {
+	let horizontal = ray.direction.truncate();
+	let scale = horizontal.length();
+	if scale < f32::EPSILON {
+		return None;
+	}
+	let hit = map.cast_ray(
+		ray.origin.truncate(),
+		horizontal / scale,
+		MAX_TILE_DISTANCE * scale,
+	)?;
+	let distance = hit.distance / scale;
+	let position = ray.get_point(distance);
+	(0.0 ..= 1.0).contains(&position.z).then_some(PickHit {
+		target: PickTarget::Tile {
+			cell: hit.cell,
+			normal: hit.normal,
+		},
+		position,
+		distance,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gfx::{areas::AreaVisibility, camera::ClearMode, culling::Frustum};
+
+	/// A view from `transform`, for facing billboards.
+	fn view(transform: &Transform) -> View {
+		View {
+			entity: Entity::PLACEHOLDER,
+			target: RenderTarget::Canvas,
+			target_size: Vec2::ONE,
+			viewport: Rect::new(0.0, 0.0, 1.0, 1.0),
+			clear: ClearMode::None,
+			layers: RenderLayers::DEFAULT,
+			eye: transform.translation,
+			projection: Mat4::IDENTITY,
+			view: transform.as_view_matrix(),
+			frustum: Frustum {
+				planes: [Vec4::ZERO; 6],
+			},
+			areas: AreaVisibility::ALL,
+			uniforms_offset: 0,
+			sprite_batches: Vec::new(),
+		}
+	}
+
+	fn ray(origin: Vec3, direction: Vec3) -> Ray3d {
+		Ray3d::new(origin, Dir3::new(direction).unwrap())
+	}
+
+	#[test]
+	fn fixed_sprite() {
+		let view = view(&Transform::default());
+		let transform = Transform::from_translation(Vec3::new(0.0, 0.0, 0.5));
+		let sprite = Sprite {
+			mode: SpriteMode::Fixed,
+			..default()
+		};
+		let cast =
+			|origin, direction| cast_sprite(&view, &transform, &sprite, ray(origin, direction));
+
+		// fixed quads face forward, along +y
+		assert_eq!(cast(Vec3::new(0.2, 3.0, 0.6), Vec3::NEG_Y), Some(3.0));
+		assert_eq!(cast(Vec3::new(0.2, -3.0, 0.6), Vec3::Y), None);
+		assert_eq!(cast(Vec3::new(0.7, 3.0, 0.5), Vec3::NEG_Y), None);
+		assert_eq!(cast(Vec3::new(0.0, 3.0, 1.1), Vec3::NEG_Y), None);
+	}
+
+	#[test]
+	fn billboard_sprite() {
+		let camera = Transform::from_translation(Vec3::new(0.0, -3.0, 0.5));
+		let view = view(&camera);
+		// turned away from the camera, which billboards ignore
+		let transform = Transform {
+			translation: Vec3::new(0.0, 0.0, 0.5),
+			rotation: Quat::from_rotation_z(std::f32::consts::PI),
+		};
+		let sprite = Sprite {
+			mode: SpriteMode::Billboard,
+			size: Vec2::new(1.0, 2.0),
+			..default()
+		};
+		let cast =
+			|origin, direction| cast_sprite(&view, &transform, &sprite, ray(origin, direction));
+
+		assert_eq!(cast(Vec3::new(0.4, -3.0, 1.4), Vec3::Y), Some(3.0));
+		assert_eq!(cast(Vec3::new(0.6, -3.0, 0.5), Vec3::Y), None);
+		assert_eq!(cast(Vec3::new(0.0, -3.0, 1.6), Vec3::Y), None);
+	}
+
+	#[test]
+	fn tiles() {
+		let map = TileMap::parse("11111\n1...1\n11111\n").unwrap();
+		let origin = Vec3::new(1.5, 1.5, 0.5);
+
+		let direction = Vec3::new(1.0, 0.0, 0.1);
+		let hit = cast_tiles(&map, ray(origin, direction)).unwrap();
+		assert_eq!(hit.target, PickTarget::Tile {
+			cell: IVec2::new(4, 1),
+			normal: IVec2::NEG_X,
+		});
+		assert!(hit.position.abs_diff_eq(Vec3::new(4.0, 1.5, 0.75), 1e-5));
+		assert!((hit.distance - origin.distance(hit.position)).abs() < 1e-5);
+
+		// the wall is hit above the ceiling
+		assert_eq!(
+			cast_tiles(&map, ray(origin, Vec3::new(1.0, 0.0, 0.3))),
+			None
+		);
+		assert_eq!(cast_tiles(&map, ray(origin, Vec3::Z)), None);
+	}
+}
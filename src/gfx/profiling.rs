@@ -0,0 +1,402 @@
+use std::{
+	collections::VecDeque,
+	sync::{
+		Arc,
+		atomic::{AtomicU8, Ordering},
+	},
+	time::Duration,
+};
+
+use bevy_platform::time::Instant;
+use wgpu::{BufferUsages, util::DrawIndirectArgs};
+
+use crate::{gfx::GraphicsContext, prelude::*};
+
+/// Upper bound on timed passes per frame; passes beyond this are timed on the
+/// CPU instead.
+const MAX_PASSES: u32 = 16;
+/// Number of frames that may be in flight waiting for their timestamps.
+const READBACK_SLOTS: usize = 3;
+/// Number of samples averaged per pass.
+const HISTORY: usize = 60;
+
+const SLOT_FREE: u8 = 0;
+const SLOT_PENDING: u8 = 1;
+const SLOT_MAPPED: u8 = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimingSource {
+	Gpu,
+	#[default]
+	Cpu,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PassTiming {
+	pub label: &'static str,
+	pub average: Duration,
+	pub max: Duration,
+}
+
+/// Rolling per-pass timings, measured on the GPU when timestamp queries are
+/// available and as CPU encoding time otherwise.
+#[derive(Resource, Default)]
+pub struct PassTimings {
+	pub source: TimingSource,
+	passes: Vec<(&'static str, VecDeque<Duration>)>,
+}
+
+impl PassTimings {
+	pub fn iter(&self) -> impl Iterator<Item = PassTiming> + '_ {
+		self.passes.iter().map(|(label, samples)| {
+			let sum: Duration = samples.iter().sum();
+			PassTiming {
+				label,
+				average: sum / samples.len().max(1) as u32,
+				max: samples.iter().copied().max().unwrap_or_default(),
+			}
+		})
+	}
+
+	pub fn total(&self) -> Duration {
+		self.iter().map(|timing| timing.average).sum()
+	}
+
+	fn record(&mut self, frame: &[(&'static str, Duration)]) {
+		// passes may run more than once per frame, so sum before recording
+		let mut totals: Vec<(&'static str, Duration)> = Vec::with_capacity(frame.len());
+		for &(label, duration) in frame {
+			match totals.iter_mut().find(|(other, _)| *other == label) {
+				Some((_, total)) => *total += duration,
+				None => totals.push((label, duration)),
+			}
+		}
+
+		for (label, duration) in totals {
+			let samples = match self.passes.iter().position(|(other, _)| *other == label) {
+				Some(index) => &mut self.passes[index].1,
+				None => {
+					self.passes.push((label, VecDeque::with_capacity(HISTORY)));
+					&mut self.passes.last_mut().unwrap().1
+				},
+			};
+			if samples.len() == HISTORY {
+				samples.pop_front();
+			}
+			samples.push_back(duration);
+		}
+	}
+}
+
+/// Counts of the work done to render the last frame, to catch regressions
+/// like needless uploads.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+	pub draw_calls: u32,
+	/// Instances passed to draw calls. Indirect draws count the instances left
+	/// after GPU culling, as read back a few frames late.
+	pub instances_drawn: u32,
+	/// Sprite instances left out of views by area and frustum culling, GPU
+	/// culling included as for `instances_drawn`.
+	pub instances_culled: u32,
+	pub pipeline_switches: u32,
+	/// Bytes written to buffers and textures.
+	pub bytes_uploaded: u64,
+	/// Memory held by textures, kept as a running total.
+	pub texture_bytes: u64,
+}
+
+/// Indirect draws whose instance counts are read back after the frame.
+#[derive(Clone, Debug)]
+struct CulledDraws {
+	/// Holds `count` [`DrawIndirectArgs`] from its start.
+	args: wgpu::Buffer,
+	count: u32,
+	/// Instances given to the draws before culling.
+	submitted: u32,
+}
+
+struct ReadbackSlot {
+	/// The frame's timestamps, followed by the arguments of its culled draws.
+	buffer: Option<wgpu::Buffer>,
+	labels: Vec<&'static str>,
+	/// Draws read back after the timestamps, as `(count, submitted)`.
+	draws: Option<(u32, u32)>,
+	state: Arc<AtomicU8>,
+}
+
+impl ReadbackSlot {
+	fn timestamps_size(&self) -> u64 {
+		self.labels.len() as u64 * 2 * wgpu::QUERY_SIZE as u64
+	}
+
+	fn size(&self) -> u64 {
+		let draws = self.draws.map_or(0, |(count, _)| count);
+		self.timestamps_size() + (draws as usize * size_of::<DrawIndirectArgs>()) as u64
+	}
+}
+
+struct TimestampQueries {
+	query_set: wgpu::QuerySet,
+	resolve: wgpu::Buffer,
+	period_ns: f32,
+}
+
+pub struct GpuProfiler {
+	device: wgpu::Device,
+	queries: Option<TimestampQueries>,
+	slots: Vec<ReadbackSlot>,
+	/// Slot written by the frame currently being encoded, if one was free.
+	submitted: Option<usize>,
+	labels: Vec<&'static str>,
+	/// Passes begun but not yet ended, with their start time if CPU-timed.
+	open: Vec<Option<(&'static str, Instant)>>,
+	cpu_timings: Vec<(&'static str, Duration)>,
+	culled_draws: Option<CulledDraws>,
+	/// Instances drawn and culled by the latest culled draws read back.
+	culled_counts: (u32, u32),
+}
+
+impl GpuProfiler {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		let queries = ctx
+			.device
+			.features()
+			.contains(wgpu::Features::TIMESTAMP_QUERY)
+			.then(|| {
+				let size = (MAX_PASSES * 2) as u64 * wgpu::QUERY_SIZE as u64;
+				let query_set = ctx.device.create_query_set(&wgpu::QuerySetDescriptor {
+					label: Some("pass timestamps"),
+					ty: wgpu::QueryType::Timestamp,
+					count: MAX_PASSES * 2,
+				});
+				let resolve = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+					label: Some("timestamp resolve"),
+					size,
+					usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+					mapped_at_creation: false,
+				});
+				TimestampQueries {
+					query_set,
+					resolve,
+					period_ns: ctx.queue.get_timestamp_period(),
+				}
+			});
+		if queries.is_none() {
+			log::info!("timestamp queries unavailable, timing passes on the CPU");
+		}
+
+		Self {
+			device: ctx.device.clone(),
+			queries,
+			slots: (0 .. READBACK_SLOTS)
+				.map(|_| ReadbackSlot {
+					buffer: None,
+					labels: Vec::new(),
+					draws: None,
+					state: Arc::new(AtomicU8::new(SLOT_FREE)),
+				})
+				.collect(),
+			submitted: None,
+			labels: Vec::new(),
+			open: Vec::new(),
+			cpu_timings: Vec::new(),
+			culled_draws: None,
+			culled_counts: (0, 0),
+		}
+	}
+
+	pub fn source(&self) -> TimingSource {
+		if self.queries.is_some() {
+			TimingSource::Gpu
+		} else {
+			TimingSource::Cpu
+		}
+	}
+
+	/// Starts timing a pass, returning timestamp writes to place in its
+	/// descriptor. Must be paired with [`Self::end_pass`] once the pass is
+	/// dropped.
+	pub fn begin_pass(
+		&mut self,
+		label: &'static str,
+	) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+		if let Some(queries) = &self.queries &&
+			(self.labels.len() as u32) < MAX_PASSES
+		{
+			let index = self.labels.len() as u32 * 2;
+			self.labels.push(label);
+			self.open.push(None);
+			return Some(wgpu::RenderPassTimestampWrites {
+				query_set: &queries.query_set,
+				beginning_of_pass_write_index: Some(index),
+				end_of_pass_write_index: Some(index + 1),
+			});
+		}
+
+		self.open.push(Some((label, Instant::now())));
+		None
+	}
+
+	/// Like [`Self::begin_pass`], for compute passes.
+	pub fn begin_compute_pass(
+		&mut self,
+		label: &'static str,
+	) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+		self.begin_pass(label)
+			.map(|writes| wgpu::ComputePassTimestampWrites {
+				query_set: writes.query_set,
+				beginning_of_pass_write_index: writes.beginning_of_pass_write_index,
+				end_of_pass_write_index: writes.end_of_pass_write_index,
+			})
+	}
+
+	pub fn end_pass(&mut self) {
+		if let Some(Some((label, start))) = self.open.pop() {
+			self.cpu_timings.push((label, start.elapsed()));
+		}
+	}
+
+	/// Reads back the instance counts of the first `count` draw arguments in
+	/// `args` once the frame is done, for [`RenderStats`]. `submitted` is the
+	/// number of instances the draws were given before culling.
+	pub fn read_back_draws(&mut self, args: &wgpu::Buffer, count: u32, submitted: u32) {
+		self.culled_draws = Some(CulledDraws {
+			args: args.clone(),
+			count,
+			submitted,
+		});
+	}
+
+	/// Copies this frame's timestamps and culled draws into a readback buffer.
+	/// Call once per frame, after all passes have been encoded.
+	pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+		self.submitted = None;
+		let draws = self.culled_draws.take();
+		if draws.is_none() {
+			self.culled_counts = (0, 0);
+		}
+		if self.labels.is_empty() && draws.is_none() {
+			return;
+		}
+
+		let Some(index) = self
+			.slots
+			.iter()
+			.position(|slot| slot.state.load(Ordering::Acquire) == SLOT_FREE)
+		else {
+			// all readbacks still in flight, drop this frame's results
+			self.labels.clear();
+			return;
+		};
+
+		let slot = &mut self.slots[index];
+		slot.labels.clear();
+		slot.labels.append(&mut self.labels);
+		slot.draws = draws.as_ref().map(|draws| (draws.count, draws.submitted));
+		let size = slot.size();
+		if slot
+			.buffer
+			.as_ref()
+			.is_none_or(|buffer| buffer.size() < size)
+		{
+			slot.buffer = Some(self.device.create_buffer(&wgpu::BufferDescriptor {
+				label: Some("profiling readback"),
+				size: size.next_power_of_two(),
+				usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+				mapped_at_creation: false,
+			}));
+		}
+		let buffer = slot.buffer.as_ref().unwrap();
+
+		let timestamps_size = slot.timestamps_size();
+		if let Some(queries) = &self.queries &&
+			timestamps_size > 0
+		{
+			let count = slot.labels.len() as u32 * 2;
+			encoder.resolve_query_set(&queries.query_set, 0 .. count, &queries.resolve, 0);
+			encoder.copy_buffer_to_buffer(&queries.resolve, 0, buffer, 0, timestamps_size);
+		}
+		if let Some(draws) = draws {
+			let args_size = size - timestamps_size;
+			encoder.copy_buffer_to_buffer(&draws.args, 0, buffer, timestamps_size, args_size);
+		}
+		self.submitted = Some(index);
+	}
+
+	/// Starts mapping the readback written by [`Self::resolve`]. Call after the
+	/// frame's command buffer has been submitted.
+	pub fn after_submit(&mut self) {
+		let Some(index) = self.submitted.take() else {
+			return;
+		};
+
+		let slot = &self.slots[index];
+		let state = slot.state.clone();
+		state.store(SLOT_PENDING, Ordering::Release);
+		slot.buffer
+			.as_ref()
+			.unwrap()
+			.slice(.. slot.size())
+			.map_async(wgpu::MapMode::Read, move |result| {
+				if let Err(err) = result {
+					log::error!("failed to map profiling readback: {err}");
+					state.store(SLOT_FREE, Ordering::Release);
+					return;
+				}
+				state.store(SLOT_MAPPED, Ordering::Release);
+			});
+	}
+
+	/// Moves any timings that have become available into `timings`, and adds
+	/// the latest culled draw counts to `stats`.
+	pub fn collect(&mut self, timings: &mut PassTimings, stats: &mut RenderStats) {
+		timings.source = self.source();
+
+		if !self.cpu_timings.is_empty() {
+			timings.record(&self.cpu_timings);
+			self.cpu_timings.clear();
+		}
+
+		let mut frame = Vec::new();
+		for slot in &mut self.slots {
+			if slot.state.load(Ordering::Acquire) != SLOT_MAPPED {
+				continue;
+			}
+
+			let buffer = slot.buffer.as_ref().unwrap();
+			let timestamps_size = slot.timestamps_size() as usize;
+			{
+				let data = buffer.slice(.. slot.size()).get_mapped_range();
+				let stamps: &[u64] = bytemuck::cast_slice(&data[.. timestamps_size]);
+				let period_ns = self
+					.queries
+					.as_ref()
+					.map_or(0.0, |queries| queries.period_ns);
+				frame.clear();
+				frame.extend(slot.labels.iter().zip(stamps.chunks_exact(2)).map(
+					|(&label, pair)| {
+						let ticks = pair[1].saturating_sub(pair[0]);
+						let nanos = ticks as f64 * period_ns as f64;
+						(label, Duration::from_nanos(nanos as u64))
+					},
+				));
+
+				if let Some((_, submitted)) = slot.draws {
+					let args: &[DrawIndirectArgs] = bytemuck::cast_slice(&data[timestamps_size ..]);
+					let drawn = args.iter().map(|args| args.instance_count).sum::<u32>();
+					self.culled_counts = (drawn, submitted.saturating_sub(drawn));
+				}
+			}
+			buffer.unmap();
+			slot.state.store(SLOT_FREE, Ordering::Release);
+			if !frame.is_empty() {
+				timings.record(&frame);
+			}
+		}
+
+		let (drawn, culled) = self.culled_counts;
+		stats.instances_drawn += drawn;
+		stats.instances_culled += culled;
+	}
+}
@@ -0,0 +1,514 @@
+//! Wolfenstein 3D style column raycaster, rendered on the CPU into a
+//! low-resolution texture that is then stretched over the canvas.
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		ExtractedFrame,
+		GraphicsContext,
+		RenderPre,
+		RenderPreSet,
+		WorldRenderer,
+		camera::{CurrentView, RenderView, ViewSet, Views},
+		prepare_frame,
+		profiling::GpuProfiler,
+		shader,
+		vertical_fov,
+	},
+	map::{Side, Tile, TileMap},
+	prelude::*,
+	transform::Transform,
+};
+
+/// Size of a canvas pixel in raycaster pixels.
+const PIXEL_SCALE: u32 = 4;
+const TEXTURE_SIZE: usize = 64;
+const MAX_DISTANCE: f32 = 128.0;
+const NEAR: f32 = 0.05;
+
+const CEILING: [u8; 4] = [56, 56, 56, 255];
+const FLOOR: [u8; 4] = [112, 112, 112, 255];
+
+type Texel = [u8; 4];
+
+struct WallTextures {
+	walls: Vec<Vec<Texel>>,
+	door: Vec<Texel>,
+}
+
+impl WallTextures {
+	fn new() -> Self {
+		const COLORS: [Texel; 10] = [
+			[96, 96, 96, 255],
+			[112, 112, 120, 255],
+			[40, 56, 140, 255],
+			[120, 72, 40, 255],
+			[64, 112, 64, 255],
+			[140, 120, 72, 255],
+			[120, 40, 40, 255],
+			[80, 80, 40, 255],
+			[100, 64, 120, 255],
+			[48, 96, 112, 255],
+		];
+
+		Self {
+			walls: COLORS.iter().map(|&color| bricks(color)).collect(),
+			door: door(),
+		}
+	}
+
+	fn sample(&self, tile: Tile, u: f32, v: f32) -> Texel {
+		let texture = match tile {
+			Tile::Wall(id) => &self.walls[id as usize % self.walls.len()],
+			Tile::Door { .. } => &self.door,
+			Tile::Empty => return [255, 0, 255, 255],
+		};
+		let x = ((u * TEXTURE_SIZE as f32) as usize).min(TEXTURE_SIZE - 1);
+		let y = ((v * TEXTURE_SIZE as f32) as usize).min(TEXTURE_SIZE - 1);
+		texture[y * TEXTURE_SIZE + x]
+	}
+}
+
+fn shade(texel: Texel, factor: f32) -> Texel {
+	let [r, g, b, a] = texel;
+	let shade = |channel: u8| (channel as f32 * factor) as u8;
+	[shade(r), shade(g), shade(b), a]
+}
+
+fn bricks(color: Texel) -> Vec<Texel> {
+	(0 .. TEXTURE_SIZE * TEXTURE_SIZE)
+		.map(|index| {
+			let (x, y) = (index % TEXTURE_SIZE, index / TEXTURE_SIZE);
+			let row = y / 16;
+			let x = (x + row % 2 * 16) % TEXTURE_SIZE;
+			let mortar = y.is_multiple_of(16) || x.is_multiple_of(32);
+			if mortar {
+				shade(color, 0.5)
+			} else {
+				// cheap per-brick variation so walls don't look flat
+				let brick = (row * 7 + x / 32 * 13) % 5;
+				shade(color, 0.85 + brick as f32 * 0.05)
+			}
+		})
+		.collect()
+}
+
+fn door() -> Vec<Texel> {
+	const WOOD: Texel = [96, 120, 120, 255];
+	(0 .. TEXTURE_SIZE * TEXTURE_SIZE)
+		.map(|index| {
+			let (x, y) = (index % TEXTURE_SIZE, index / TEXTURE_SIZE);
+			if (40 .. 44).contains(&x) && (28 .. 36).contains(&y) {
+				[200, 200, 200, 255]
+			} else if x.is_multiple_of(8) || !(2 .. TEXTURE_SIZE - 2).contains(&y) {
+				shade(WOOD, 0.6)
+			} else {
+				WOOD
+			}
+		})
+		.collect()
+}
+
+pub struct RaycastPipelines {
+	size: UVec2,
+	pixels: Vec<Texel>,
+	/// Perpendicular distance to the wall in each column, for clipping
+	/// sprites.
+	depth: Vec<f32>,
+	textures: WallTextures,
+
+	texture: wgpu::Texture,
+	sampler: wgpu::Sampler,
+	bind_group_layout: wgpu::BindGroupLayout,
+	bind_group: wgpu::BindGroup,
+	pipeline: wgpu::RenderPipeline,
+}
+
+impl RaycastPipelines {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		let bind_group_layout =
+			ctx.device
+				.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+					label: Some("raycast layout"),
+					entries: &[
+						wgpu::BindGroupLayoutEntry {
+							binding: 0,
+							count: None,
+							visibility: wgpu::ShaderStages::FRAGMENT,
+							ty: wgpu::BindingType::Texture {
+								sample_type: wgpu::TextureSampleType::Float { filterable: true },
+								view_dimension: wgpu::TextureViewDimension::D2,
+								multisampled: false,
+							},
+						},
+						wgpu::BindGroupLayoutEntry {
+							binding: 1,
+							count: None,
+							visibility: wgpu::ShaderStages::FRAGMENT,
+							ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+						},
+					],
+				});
+		let sampler = ctx.device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("raycast sampler"),
+			..default()
+		});
+		let size = UVec2::ONE;
+		let texture = create_texture(ctx, size);
+		let bind_group = create_bind_group(ctx, &bind_group_layout, &texture, &sampler);
+
+		let pipeline = create_pipeline(
+			ctx,
+			&bind_group_layout,
+			&shader::create_shader_module(ctx, "raycast.wgsl", &[]),
+		);
+
+		Self {
+			size,
+			pixels: Vec::new(),
+			depth: Vec::new(),
+			textures: WallTextures::new(),
+
+			texture,
+			sampler,
+			bind_group_layout,
+			bind_group,
+			pipeline,
+		}
+	}
+
+	fn resize(&mut self, ctx: &GraphicsContext, size: UVec2) {
+		self.size = size;
+		self.pixels = vec![[0; 4]; (size.x * size.y) as usize];
+		self.depth = vec![f32::INFINITY; size.x as usize];
+		ctx.destroy_texture(&self.texture);
+		self.texture = create_texture(ctx, size);
+		self.bind_group =
+			create_bind_group(ctx, &self.bind_group_layout, &self.texture, &self.sampler);
+	}
+
+	/// Draws the walls of `map` and billboards at the given positions and
+	/// sizes.
+	fn render(&mut self, map: &TileMap, camera: &Transform, sprites: &[(Vec3, Vec2)]) {
+		let UVec2 {
+			x: width,
+			y: height,
+		} = self.size;
+		let (width, height) = (width as usize, height as usize);
+		let aspect = width as f32 / height as f32;
+		let tan_y = (vertical_fov(aspect) / 2.0).tan();
+		let tan_x = tan_y * aspect;
+
+		// pitch and roll are ignored, as in the original
+		let eye = camera.translation;
+		let forward = camera.forward().truncate().normalize_or(Vec2::Y);
+		let right = Vec2::new(forward.y, -forward.x);
+		let row_of = |z: f32, distance: f32| {
+			let ndc = (z - eye.z) / (distance * tan_y);
+			(1.0 - ndc) / 2.0 * height as f32
+		};
+
+		for (y, row) in self.pixels.chunks_exact_mut(width).enumerate() {
+			row.fill(if y < height / 2 { CEILING } else { FLOOR });
+		}
+
+		for x in 0 .. width {
+			let camera_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+			let direction = forward + right * camera_x * tan_x;
+			let Some(hit) = map.cast_ray(eye.truncate(), direction, MAX_DISTANCE) else {
+				self.depth[x] = f32::INFINITY;
+				continue;
+			};
+			// the ray parameter is already the perpendicular distance, as the
+			// direction's forward component is 1
+			let distance = hit.distance.max(NEAR);
+			self.depth[x] = distance;
+
+			let top = row_of(1.0, distance);
+			let bottom = row_of(0.0, distance);
+			let u = if hit.normal.x < 0 || hit.normal.y > 0 {
+				1.0 - hit.face_u
+			} else {
+				hit.face_u
+			};
+			// as in Wolf3D, faces along one axis are darker to give corners
+			// some definition
+			let light = match hit.side {
+				Side::X => 1.0,
+				Side::Y => 0.7,
+			};
+
+			let start = top.max(0.0) as usize;
+			let end = (bottom.ceil().max(0.0) as usize).min(height);
+			for y in start .. end {
+				let v = (y as f32 + 0.5 - top) / (bottom - top);
+				let texel = self.textures.sample(hit.tile, u, v);
+				self.pixels[y * width + x] = shade(texel, light);
+			}
+		}
+
+		// sprites are always billboards here, drawn back to front and clipped
+		// against the walls per column
+		let mut projected: Vec<_> = sprites
+			.iter()
+			.filter_map(|&(position, size)| {
+				let relative = position.truncate() - eye.truncate();
+				let depth = relative.dot(forward);
+				(depth > NEAR).then(|| (depth, relative.dot(right), position, size))
+			})
+			.collect();
+		projected.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+		for (depth, lateral, position, size) in projected {
+			let center_x = (lateral / (depth * tan_x) + 1.0) / 2.0 * width as f32;
+			let half_width = size.x / 2.0 / (depth * tan_x) * width as f32 / 2.0;
+			let left = center_x - half_width;
+			let z = position.z;
+			let top = row_of(z + size.y / 2.0, depth);
+			let bottom = row_of(z - size.y / 2.0, depth);
+
+			let columns = (left.max(0.0) as usize) ..
+				((center_x + half_width).ceil().max(0.0) as usize).min(width);
+			let rows = (top.max(0.0) as usize) .. (bottom.ceil().max(0.0) as usize).min(height);
+			for x in columns {
+				if depth >= self.depth[x] {
+					continue;
+				}
+				let u = (x as f32 + 0.5 - left) / (half_width * 2.0);
+				for y in rows.clone() {
+					let v = (y as f32 + 0.5 - top) / (bottom - top);
+					// sprite textures only live on the GPU, so this matches the
+					// quad shader's untextured UV colouring instead
+					self.pixels[y * width + x] =
+						[(u * 255.0) as u8, ((1.0 - v) * 255.0) as u8, 128, 255];
+				}
+			}
+		}
+	}
+}
+
+fn create_pipeline(
+	ctx: &GraphicsContext,
+	bind_group_layout: &wgpu::BindGroupLayout,
+	shader_module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("raycast render layout"),
+			bind_group_layouts: &[bind_group_layout],
+			push_constant_ranges: &[],
+		});
+	ctx.device
+		.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("raycast render pipeline"),
+			layout: Some(&pipeline_layout),
+			depth_stencil: Some(wgpu::DepthStencilState {
+				format: wgpu::TextureFormat::Depth24Plus,
+				depth_write_enabled: false,
+				depth_compare: wgpu::CompareFunction::Always,
+				stencil: default(),
+				bias: default(),
+			}),
+			multisample: wgpu::MultisampleState::default(),
+			multiview: None,
+			cache: None,
+			primitive: wgpu::PrimitiveState::default(),
+			vertex: wgpu::VertexState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: None,
+				buffers: &[],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: None,
+				targets: &[Some(ctx.surface_format().into())],
+			}),
+		})
+}
+
+#[cfg(debug_assertions)]
+fn reload_pipeline(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	raycast: NonSend<RaycastPipelines>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	if changes
+		.read()
+		.any(|change| change.affects("raycast.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"raycast.wgsl",
+			&[],
+			|shader_module| create_pipeline(&ctx, &raycast.bind_group_layout, shader_module),
+			|world, pipeline| world.non_send_resource_mut::<RaycastPipelines>().pipeline = pipeline,
+		);
+	}
+}
+
+fn create_texture(ctx: &GraphicsContext, size: UVec2) -> wgpu::Texture {
+	ctx.create_texture(&wgpu::TextureDescriptor {
+		label: Some("raycast texture"),
+		size: wgpu::Extent3d {
+			width: size.x,
+			height: size.y,
+			depth_or_array_layers: 1,
+		},
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: wgpu::TextureFormat::Rgba8Unorm,
+		usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+		view_formats: &[],
+	})
+}
+
+fn create_bind_group(
+	ctx: &GraphicsContext,
+	layout: &wgpu::BindGroupLayout,
+	texture: &wgpu::Texture,
+	sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+	ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("raycast group"),
+		layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::TextureView(
+					&texture.create_view(&wgpu::TextureViewDescriptor::default()),
+				),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: wgpu::BindingResource::Sampler(sampler),
+			},
+		],
+	})
+}
+
+/// The map as of its last change.
+#[derive(Resource, Default)]
+struct ExtractedMap(Option<TileMap>);
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<ExtractedMap>();
+	app.add_systems(
+		RenderPre,
+		(
+			extract_map.in_set(RenderPreSet::Extract),
+			raycast_frame
+				.in_set(RenderPreSet::Prepare)
+				.after(prepare_frame),
+		)
+			.run_if(resource_equals(WorldRenderer::Raycast)),
+	);
+	app.add_systems(
+		RenderView,
+		draw_raycast
+			.in_set(ViewSet::World)
+			.run_if(resource_equals(WorldRenderer::Raycast)),
+	);
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, (toggle_renderer, reload_pipeline));
+}
+
+fn extract_map(mut extracted: ResMut<ExtractedMap>, map: Option<Res<TileMap>>) {
+	match map {
+		Some(map) if map.is_changed() => extracted.0 = Some(map.clone()),
+		Some(_) => {},
+		None => extracted.0 = None,
+	}
+}
+
+fn raycast_frame(
+	ctx: NonSend<GraphicsContext>,
+	mut raycast: NonSendMut<RaycastPipelines>,
+	map: Res<ExtractedMap>,
+	views: Res<Views>,
+	extracted: Res<ExtractedFrame>,
+) {
+	// only the main view is raycast, there being a single output texture
+	let Some(view) = views.main() else {
+		return;
+	};
+	let size = (view.viewport.size().as_uvec2() / PIXEL_SCALE).max(UVec2::ONE);
+	if raycast.size != size || raycast.pixels.is_empty() {
+		raycast.resize(&ctx, size);
+	}
+
+	let (Some(map), Some(camera)) = (&map.0, extracted.camera(view.entity)) else {
+		return;
+	};
+	// walls come from the map itself, and like the original only billboards
+	// are drawn on top, so fixed sprites such as the map's wall panels are left
+	// out
+	let sprites: Vec<_> = extracted
+		.sprites
+		.iter()
+		.filter(|sprite| sprite.instance.billboard != 0 && sprite.layers.intersects(view.layers))
+		.map(|sprite| {
+			let instance = &sprite.instance;
+			(instance.model.w_axis.truncate(), instance.size)
+		})
+		.collect();
+	raycast.render(map, &camera.transform, &sprites);
+
+	ctx.write_texture(
+		wgpu::TexelCopyTextureInfo {
+			texture: &raycast.texture,
+			mip_level: 0,
+			origin: wgpu::Origin3d::ZERO,
+			aspect: wgpu::TextureAspect::All,
+		},
+		bytemuck::cast_slice(&raycast.pixels),
+		wgpu::TexelCopyBufferLayout {
+			offset: 0,
+			bytes_per_row: Some(size.x * 4),
+			rows_per_image: None,
+		},
+		wgpu::Extent3d {
+			width: size.x,
+			height: size.y,
+			depth_or_array_layers: 1,
+		},
+	);
+}
+
+fn draw_raycast(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	raycast: NonSend<RaycastPipelines>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+) {
+	if !view.is_main() {
+		return;
+	}
+
+	let mut pass = frame.begin_view_pass("raycast", &mut profiler, view.get());
+	pass.set_pipeline(&raycast.pipeline);
+	ctx.record_pipeline_switch();
+	pass.set_bind_group(0, &raycast.bind_group, &[]);
+	pass.draw(0 .. 3, 0 .. 1);
+	ctx.record_draw(1);
+	drop(pass);
+	profiler.end_pass();
+}
+
+#[cfg(debug_assertions)]
+fn toggle_renderer(input: Res<ButtonInput<KeyCode>>, mut renderer: ResMut<WorldRenderer>) {
+	if input.just_pressed(KeyCode::F2) {
+		*renderer = match *renderer {
+			WorldRenderer::Polygonal => WorldRenderer::Raycast,
+			WorldRenderer::Raycast => WorldRenderer::Polygonal,
+		};
+		log::info!("switched to {:?} world renderer", *renderer);
+	}
+}
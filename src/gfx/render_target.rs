@@ -0,0 +1,299 @@
+//! Offscreen textures that cameras with a [`RenderTarget::Texture`] draw
+//! into. Each view is rendered at its target's size into its own colour and
+//! depth textures, then scaled into its layer of the sprite textures so
+//! sprites can show it like any other texture.
+
+use bevy_platform::collections::{HashMap, HashSet};
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		FrameTarget,
+		GraphicsContext,
+		RenderPre,
+		camera::{CurrentView, RenderTarget, RenderView, ViewSet, Views},
+		prepare_frame,
+		profiling::GpuProfiler,
+		shader,
+		textures::{SpriteTextures, TextureId},
+	},
+	prelude::*,
+};
+
+struct OffscreenTexture {
+	size: UVec2,
+	color: wgpu::TextureView,
+	depth: wgpu::TextureView,
+	/// The layer of the sprite textures the view is copied into.
+	layer: wgpu::TextureView,
+	/// Binds `color` for the copy.
+	group: wgpu::BindGroup,
+}
+
+pub struct RenderTargets {
+	targets: HashMap<TextureId, OffscreenTexture>,
+	/// Targets that failed to prepare, so each is only reported once.
+	rejected: HashSet<RenderTarget>,
+	layout: wgpu::BindGroupLayout,
+	sampler: wgpu::Sampler,
+	pipeline: wgpu::RenderPipeline,
+}
+
+impl RenderTargets {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		let layout = ctx
+			.device
+			.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+				label: Some("blit layout"),
+				entries: &[
+					wgpu::BindGroupLayoutEntry {
+						binding: 0,
+						count: None,
+						visibility: wgpu::ShaderStages::FRAGMENT,
+						ty: wgpu::BindingType::Texture {
+							sample_type: wgpu::TextureSampleType::Float { filterable: true },
+							view_dimension: wgpu::TextureViewDimension::D2,
+							multisampled: false,
+						},
+					},
+					wgpu::BindGroupLayoutEntry {
+						binding: 1,
+						count: None,
+						visibility: wgpu::ShaderStages::FRAGMENT,
+						ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+					},
+				],
+			});
+		let sampler = ctx.device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("blit sampler"),
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..default()
+		});
+		let pipeline = create_pipeline(
+			ctx,
+			&layout,
+			&shader::create_shader_module(ctx, "blit.wgsl", &[]),
+		);
+
+		Self {
+			targets: default(),
+			rejected: default(),
+			layout,
+			sampler,
+			pipeline,
+		}
+	}
+
+	/// Attachments for drawing a view of `size` pixels into `texture`, if it
+	/// has been prepared at that size.
+	pub fn frame_target(&self, texture: TextureId, size: UVec2) -> Option<FrameTarget> {
+		let target = self.get(texture, size)?;
+		Some(FrameTarget::new(target.color.clone(), target.depth.clone()))
+	}
+
+	fn get(&self, texture: TextureId, size: UVec2) -> Option<&OffscreenTexture> {
+		self.targets
+			.get(&texture)
+			.filter(|target| target.size == size)
+	}
+
+	fn prepare(
+		&mut self,
+		ctx: &GraphicsContext,
+		textures: &SpriteTextures,
+		texture: TextureId,
+		size: UVec2,
+	) -> Result<(), String> {
+		if !textures.is_render_target(texture) {
+			return Err(format!(
+				"{texture:?} was not added with SpriteTextures::add_render_target"
+			));
+		}
+		let max = ctx.device.limits().max_texture_dimension_2d;
+		if size.min_element() == 0 || size.max_element() > max {
+			return Err(format!(
+				"render target size {size} is outside 1 ..= {max} pixels"
+			));
+		}
+		if self.get(texture, size).is_some() {
+			return Ok(());
+		}
+
+		let create_texture = |label, format, usage| {
+			ctx.create_texture(&wgpu::TextureDescriptor {
+				label: Some(label),
+				size: wgpu::Extent3d {
+					width: size.x,
+					height: size.y,
+					depth_or_array_layers: 1,
+				},
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: wgpu::TextureDimension::D2,
+				format,
+				usage,
+				view_formats: &[],
+			})
+			.create_view(&default())
+		};
+		let color = create_texture(
+			"offscreen color",
+			ctx.surface_format(),
+			wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+		);
+		let depth = create_texture(
+			"offscreen depth",
+			wgpu::TextureFormat::Depth24Plus,
+			wgpu::TextureUsages::RENDER_ATTACHMENT,
+		);
+		let group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("blit group"),
+			layout: &self.layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(&color),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::Sampler(&self.sampler),
+				},
+			],
+		});
+
+		// replaces the textures of an earlier size
+		self.targets.insert(texture, OffscreenTexture {
+			size,
+			color,
+			depth,
+			layer: textures.layer_view(texture),
+			group,
+		});
+		Ok(())
+	}
+}
+
+fn create_pipeline(
+	ctx: &GraphicsContext,
+	layout: &wgpu::BindGroupLayout,
+	shader_module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("blit render layout"),
+			bind_group_layouts: &[layout],
+			push_constant_ranges: &[],
+		});
+	ctx.device
+		.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("blit pipeline"),
+			layout: Some(&pipeline_layout),
+			depth_stencil: None,
+			multisample: wgpu::MultisampleState::default(),
+			multiview: None,
+			cache: None,
+			primitive: wgpu::PrimitiveState::default(),
+			vertex: wgpu::VertexState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: None,
+				buffers: &[],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: None,
+				targets: &[Some(wgpu::TextureFormat::Rgba8Unorm.into())],
+			}),
+		})
+}
+
+#[cfg(debug_assertions)]
+fn reload_pipeline(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	targets: NonSend<RenderTargets>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	if changes
+		.read()
+		.any(|change| change.affects("blit.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"blit.wgsl",
+			&[],
+			|shader_module| create_pipeline(&ctx, &targets.layout, shader_module),
+			|world, pipeline| world.non_send_resource_mut::<RenderTargets>().pipeline = pipeline,
+		);
+	}
+}
+
+pub(super) fn setup(app: &mut App) {
+	app.add_systems(RenderPre, prepare_targets.after(prepare_frame));
+	app.add_systems(RenderView, resolve_target.in_set(ViewSet::Resolve));
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_pipeline);
+}
+
+fn prepare_targets(
+	ctx: NonSend<GraphicsContext>,
+	mut targets: NonSendMut<RenderTargets>,
+	textures: NonSend<SpriteTextures>,
+	views: Res<Views>,
+) {
+	for view in &views.0 {
+		let RenderTarget::Texture { texture, size } = view.target else {
+			continue;
+		};
+		if let Err(error) = targets.prepare(&ctx, &textures, texture, size) &&
+			targets.rejected.insert(view.target)
+		{
+			log::error!("camera {} is not drawn: {error}", view.entity);
+		}
+	}
+}
+
+fn resolve_target(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	targets: NonSend<RenderTargets>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+) {
+	let RenderTarget::Texture { texture, size } = view.get().target else {
+		return;
+	};
+	let Some(target) = targets.get(texture, size) else {
+		return;
+	};
+
+	let mut pass = frame
+		.encoder
+		.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some("resolve target"),
+			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+				view: &target.layer,
+				depth_slice: None,
+				resolve_target: None,
+				ops: wgpu::Operations {
+					load: wgpu::LoadOp::Load,
+					store: wgpu::StoreOp::Store,
+				},
+			})],
+			depth_stencil_attachment: None,
+			timestamp_writes: profiler.begin_pass("resolve target"),
+			..default()
+		});
+	pass.set_pipeline(&targets.pipeline);
+	pass.set_bind_group(0, &target.group, &[]);
+	pass.draw(0 .. 3, 0 .. 1);
+	ctx.record_pipeline_switch();
+	ctx.record_draw(1);
+	drop(pass);
+	profiler.end_pass();
+}
@@ -0,0 +1,697 @@
+//! The WGSL sources under `shaders/`, run through a small preprocessor so they
+//! can share code and be built in variants. Directives take a line each:
+//!
+//! - `#include "path"` pastes in another source, by its path under `shaders/`.
+//!   Each source is included at most once, however many times it is asked for.
+//! - `#define NAME` defines `NAME` for the rest of the shader.
+//! - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop the lines
+//!   between them, depending on whether `NAME` is defined.
+//!
+//! In debug builds the sources are watched for changes, which replace the
+//! embedded ones and send a [`ShaderChanged`] for the pipelines built on them
+//! to be rebuilt with [`ShaderReloads`].
+
+use std::borrow::Cow;
+#[cfg(debug_assertions)]
+use std::{cell::RefCell, rc::Rc};
+
+#[cfg(debug_assertions)]
+use bevy_platform::collections::HashMap;
+use bevy_platform::collections::HashSet;
+
+#[cfg(debug_assertions)]
+use crate::hot_reload::{self, FileChanged};
+use crate::{gfx::GraphicsContext, prelude::*};
+
+/// Every source, by its path under `shaders/`.
+const SOURCES: &[(&str, &str)] = &[
+	("automap.wgsl", include_str!("shaders/automap.wgsl")),
+	("blit.wgsl", include_str!("shaders/blit.wgsl")),
+	("clear.wgsl", include_str!("shaders/clear.wgsl")),
+	(
+		"common/lighting.wgsl",
+		include_str!("shaders/common/lighting.wgsl"),
+	),
+	(
+		"common/sprite_instance.wgsl",
+		include_str!("shaders/common/sprite_instance.wgsl"),
+	),
+	(
+		"common/sprite_textures.wgsl",
+		include_str!("shaders/common/sprite_textures.wgsl"),
+	),
+	("common/view.wgsl", include_str!("shaders/common/view.wgsl")),
+	("cull.wgsl", include_str!("shaders/cull.wgsl")),
+	("gizmo.wgsl", include_str!("shaders/gizmo.wgsl")),
+	(
+		"materials/dissolve.wgsl",
+		include_str!("shaders/materials/dissolve.wgsl"),
+	),
+	(
+		"materials/lava.wgsl",
+		include_str!("shaders/materials/lava.wgsl"),
+	),
+	(
+		"materials/water.wgsl",
+		include_str!("shaders/materials/water.wgsl"),
+	),
+	("mesh.wgsl", include_str!("shaders/mesh.wgsl")),
+	("quad.wgsl", include_str!("shaders/quad.wgsl")),
+	("raycast.wgsl", include_str!("shaders/raycast.wgsl")),
+	("sky.wgsl", include_str!("shaders/sky.wgsl")),
+	("static.wgsl", include_str!("shaders/static.wgsl")),
+];
+
+#[cfg(debug_assertions)]
+thread_local! {
+	/// Sources that have changed on disk since the app was built.
+	static RELOADED: RefCell<HashMap<String, String>> = RefCell::default();
+}
+
+/// The unprocessed source at `path` under `shaders/`.
+pub fn source(path: &str) -> Option<Cow<'static, str>> {
+	#[cfg(debug_assertions)]
+	if let Some(source) = RELOADED.with_borrow(|reloaded| reloaded.get(path).cloned()) {
+		return Some(Cow::Owned(source));
+	}
+	SOURCES
+		.iter()
+		.find(|&&(source_path, _)| source_path == path)
+		.map(|&(_, source)| Cow::Borrowed(source))
+}
+
+/// Expands the directives in `source`, with `defines` defined.
+pub fn preprocess(source: &str, defines: &[&str]) -> Result<String, String> {
+	let mut preprocessor = Preprocessor::new(defines);
+	preprocessor.process("<source>", source)?;
+	Ok(preprocessor.output)
+}
+
+/// Preprocesses the source at `path`.
+fn load(path: &str, defines: &[&str]) -> Result<String, String> {
+	let source = source(path).ok_or_else(|| format!("no such shader {path:?}"))?;
+	preprocess(&source, defines)
+}
+
+/// Preprocesses the source at `path` and compiles it.
+pub(crate) fn create_shader_module(
+	ctx: &GraphicsContext,
+	path: &str,
+	defines: &[&str],
+) -> wgpu::ShaderModule {
+	let source =
+		load(path, defines).unwrap_or_else(|error| panic!("couldn't load shader {path}: {error}"));
+	compile(ctx, path, source)
+}
+
+fn compile(ctx: &GraphicsContext, label: &str, source: String) -> wgpu::ShaderModule {
+	ctx.device
+		.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some(label),
+			source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+		})
+}
+
+/// A source under `shaders/` that changed on disk.
+#[cfg(debug_assertions)]
+#[derive(Event, Clone, Debug)]
+pub struct ShaderChanged {
+	pub path: String,
+}
+
+#[cfg(debug_assertions)]
+impl ShaderChanged {
+	/// Whether the shader at `path`, built with `defines`, is or includes the
+	/// changed source.
+	pub fn affects(&self, path: &str, defines: &[&str]) -> bool {
+		path == self.path ||
+			source(path).is_some_and(|source| self.affects_source(&source, defines))
+	}
+
+	/// Whether `source`, built with `defines`, includes the changed source.
+	pub fn affects_source(&self, source: &str, defines: &[&str]) -> bool {
+		let mut preprocessor = Preprocessor::new(defines);
+		// whatever was included before any error still counts
+		let _ = preprocessor.process("<source>", source);
+		preprocessor.included.contains(&self.path)
+	}
+}
+
+#[cfg(debug_assertions)]
+type Reload = Result<Box<dyn FnOnce(&mut World)>, String>;
+
+/// Pipelines rebuilt after a shader changed, held back until the GPU has
+/// reported whether they compiled so that a broken shader leaves the old ones
+/// in place.
+#[cfg(debug_assertions)]
+#[derive(Default)]
+pub(crate) struct ShaderReloads {
+	finished: Rc<RefCell<Vec<Reload>>>,
+}
+
+#[cfg(debug_assertions)]
+impl ShaderReloads {
+	/// Compiles the shader at `path` and passes it to `build`, then once it is
+	/// known to be valid passes what was built to `apply`.
+	pub(crate) fn rebuild<T: 'static>(
+		&self,
+		ctx: &GraphicsContext,
+		path: &str,
+		defines: &[&str],
+		build: impl FnOnce(&wgpu::ShaderModule) -> T,
+		apply: impl FnOnce(&mut World, T) + 'static,
+	) {
+		self.rebuild_variants(ctx, path, &[defines], |modules| build(&modules[0]), apply);
+	}
+
+	/// Like [`rebuild`](Self::rebuild), compiling the shader once for each set
+	/// of defines and passing the modules to `build` in the same order.
+	pub(crate) fn rebuild_variants<T: 'static>(
+		&self,
+		ctx: &GraphicsContext,
+		path: &str,
+		variants: &[&[&str]],
+		build: impl FnOnce(&[wgpu::ShaderModule]) -> T,
+		apply: impl FnOnce(&mut World, T) + 'static,
+	) {
+		let sources = variants.iter().map(|defines| load(path, defines)).collect();
+		self.rebuild_sources(ctx, path, sources, build, apply);
+	}
+
+	/// Like [`rebuild`](Self::rebuild), from an already preprocessed source.
+	pub(crate) fn rebuild_source<T: 'static>(
+		&self,
+		ctx: &GraphicsContext,
+		label: &str,
+		source: Result<String, String>,
+		build: impl FnOnce(&wgpu::ShaderModule) -> T,
+		apply: impl FnOnce(&mut World, T) + 'static,
+	) {
+		let sources = source.map(|source| vec![source]);
+		self.rebuild_sources(ctx, label, sources, |modules| build(&modules[0]), apply);
+	}
+
+	fn rebuild_sources<T: 'static>(
+		&self,
+		ctx: &GraphicsContext,
+		label: &str,
+		sources: Result<Vec<String>, String>,
+		build: impl FnOnce(&[wgpu::ShaderModule]) -> T,
+		apply: impl FnOnce(&mut World, T) + 'static,
+	) {
+		let sources = match sources {
+			Ok(sources) => sources,
+			Err(error) => {
+				self.finished
+					.borrow_mut()
+					.push(Err(format!("{label}: {error}")));
+				return;
+			},
+		};
+		ctx.device.push_error_scope(wgpu::ErrorFilter::Validation);
+		let modules: Vec<_> = sources
+			.into_iter()
+			.map(|source| compile(ctx, label, source))
+			.collect();
+		let built = build(&modules);
+		let error = ctx.device.pop_error_scope();
+
+		let finished = self.finished.clone();
+		let label = label.to_owned();
+		wasm_bindgen_futures::spawn_local(async move {
+			let reload: Reload = match error.await {
+				None => Ok(Box::new(move |world| apply(world, built))),
+				Some(error) => Err(format!("{label}: {error}")),
+			};
+			finished.borrow_mut().push(reload);
+		});
+	}
+}
+
+#[cfg(debug_assertions)]
+pub(super) fn setup(app: &mut App) {
+	for &(path, _) in SOURCES {
+		hot_reload::watch(app, &format!("gfx/shaders/{path}"));
+	}
+	app.add_event::<ShaderChanged>();
+	app.init_non_send_resource::<ShaderReloads>();
+	app.add_systems(PreUpdate, (update_sources, apply_reloads));
+}
+
+#[cfg(debug_assertions)]
+fn update_sources(mut files: EventReader<FileChanged>, mut changes: EventWriter<ShaderChanged>) {
+	for file in files.read() {
+		let Some(path) = file.path.strip_prefix("gfx/shaders/") else {
+			continue;
+		};
+		match file.text() {
+			Ok(text) => {
+				RELOADED
+					.with_borrow_mut(|reloaded| reloaded.insert(path.to_owned(), text.to_owned()));
+				changes.write(ShaderChanged {
+					path: path.to_owned(),
+				});
+			},
+			Err(error) => log::error!("couldn't reload shader: {error}"),
+		}
+	}
+}
+
+#[cfg(debug_assertions)]
+fn apply_reloads(world: &mut World) {
+	let finished = world.non_send_resource::<ShaderReloads>().finished.take();
+	for reload in finished {
+		match reload {
+			Ok(apply) => apply(world),
+			Err(error) => log::error!("keeping the old pipeline, shader failed to build: {error}"),
+		}
+	}
+}
+
+struct Preprocessor {
+	defines: HashSet<String>,
+	included: HashSet<String>,
+	output: String,
+}
+
+struct Condition {
+	/// Whether the lines under the condition are kept, ignoring any enclosing
+	/// conditions.
+	active: bool,
+	in_else: bool,
+}
+
+impl Preprocessor {
+	fn new(defines: &[&str]) -> Self {
+		Self {
+			defines: defines.iter().map(|&define| define.to_owned()).collect(),
+			included: HashSet::default(),
+			output: String::new(),
+		}
+	}
+
+	fn process(&mut self, path: &str, text: &str) -> Result<(), String> {
+		let mut conditions: Vec<Condition> = Vec::new();
+		for (index, line) in text.lines().enumerate() {
+			let error = |message: &str| format!("{path}:{}: {message}", index + 1);
+			let active = conditions.iter().all(|condition| condition.active);
+			let Some(directive) = line.trim().strip_prefix('#') else {
+				if active {
+					self.output.push_str(line);
+					self.output.push('\n');
+				}
+				continue;
+			};
+			let (name, argument) = directive
+				.split_once(char::is_whitespace)
+				.map_or((directive, ""), |(name, argument)| (name, argument.trim()));
+
+			match name {
+				"ifdef" | "ifndef" => {
+					if argument.is_empty() {
+						return Err(error("expected a name"));
+					}
+					let defined = self.defines.contains(argument);
+					conditions.push(Condition {
+						active: defined == (name == "ifdef"),
+						in_else: false,
+					});
+				},
+				"else" => {
+					let condition = conditions
+						.last_mut()
+						.filter(|condition| !condition.in_else)
+						.ok_or_else(|| error("#else without #ifdef"))?;
+					condition.active = !condition.active;
+					condition.in_else = true;
+				},
+				"endif" => {
+					conditions
+						.pop()
+						.ok_or_else(|| error("#endif without #ifdef"))?;
+				},
+				_ if !active => {},
+				"define" => {
+					if argument.is_empty() {
+						return Err(error("expected a name"));
+					}
+					self.defines.insert(argument.to_owned());
+				},
+				"include" => {
+					let include = argument
+						.strip_prefix('"')
+						.and_then(|argument| argument.strip_suffix('"'))
+						.ok_or_else(|| error("expected a quoted path"))?;
+					if self.included.insert(include.to_owned()) {
+						let text = source(include)
+							.ok_or_else(|| error(&format!("no such shader {include:?}")))?;
+						self.process(include, &text)?;
+					}
+				},
+				_ => return Err(error(&format!("unknown directive #{name}"))),
+			}
+		}
+
+		if conditions.is_empty() {
+			Ok(())
+		} else {
+			Err(format!("{path}: #ifdef without #endif"))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::mem::offset_of;
+
+	use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+	use super::*;
+	use crate::gfx::{
+		SPRITE_INSTANCE_ATTRIBUTES,
+		SpriteInstance,
+		Uniforms,
+		culling::CullParams,
+		lighting::{MAX_POINT_LIGHTS, PointLightData, PointLightUniforms},
+		material::DissolveUniforms,
+		mesh::{MESH_INSTANCE_ATTRIBUTES, MESH_VERTEX_ATTRIBUTES, MeshInstance, MeshVertex},
+		palette::LIGHT_LEVELS,
+		sky::SkyParams,
+		static_batch::{STATIC_VERTEX_ATTRIBUTES, StaticVertex},
+	};
+
+	/// Sets of defines shaders are built with, besides none at all.
+	const VARIANTS: &[(&str, &[&str])] =
+		&[("quad.wgsl", &["TRANSPARENT"]), ("sky.wgsl", &["CUBEMAP"])];
+
+	/// Size and member offsets of a struct.
+	type Layout = (u32, Vec<(String, u32)>);
+
+	/// The layout of a Rust struct, leaving out padding fields.
+	macro_rules! layout {
+		($type:ty { $($field:ident),* $(,)? }) => {
+			(
+				size_of::<$type>() as u32,
+				vec![$((stringify!($field).to_owned(), offset_of!($type, $field) as u32)),*],
+			)
+		};
+	}
+
+	/// Every shader that is built on its own, with each of its sets of defines.
+	fn permutations() -> impl Iterator<Item = (&'static str, &'static [&'static str])> {
+		SOURCES
+			.iter()
+			.map(|&(path, _)| path)
+			.filter(|path| !path.starts_with("common/"))
+			.flat_map(|path| {
+				let variants = VARIANTS
+					.iter()
+					.filter(move |&&(variant_path, _)| variant_path == path)
+					.map(|&(_, defines)| defines);
+				[(path, &[][..])]
+					.into_iter()
+					.chain(variants.map(move |defines| (path, defines)))
+			})
+	}
+
+	fn parse(path: &str, defines: &[&str]) -> (String, naga::Module) {
+		let source = preprocess(&source(path).unwrap(), defines)
+			.unwrap_or_else(|error| panic!("{path} {defines:?}: {error}"));
+		let module = naga::front::wgsl::parse_str(&source).unwrap_or_else(|error| {
+			panic!("{path} {defines:?}: {}", error.emit_to_string(&source))
+		});
+		(source, module)
+	}
+
+	fn struct_layout(module: &naga::Module, name: &str) -> Option<Layout> {
+		module.types.iter().find_map(|(_, ty)| match &ty.inner {
+			naga::TypeInner::Struct { members, span } if ty.name.as_deref() == Some(name) => {
+				let members = members
+					.iter()
+					.map(|member| (member.name.clone().unwrap_or_default(), member.offset))
+					.collect();
+				Some((*span, members))
+			},
+			_ => None,
+		})
+	}
+
+	/// The `@location`s and scalar kinds of the inputs to the vertex entry
+	/// point `entry`, by member name.
+	fn vertex_locations(
+		module: &naga::Module,
+		entry: &str,
+	) -> Vec<(String, u32, naga::ScalarKind)> {
+		let entry_point = module
+			.entry_points
+			.iter()
+			.find(|entry_point| {
+				entry_point.stage == naga::ShaderStage::Vertex && entry_point.name == entry
+			})
+			.unwrap_or_else(|| panic!("no vertex entry point {entry}"));
+		let kind = |ty: naga::Handle<naga::Type>| {
+			module.types[ty]
+				.inner
+				.scalar_kind()
+				.expect("vertex inputs are scalars or vectors")
+		};
+		let mut locations = Vec::new();
+		for argument in &entry_point.function.arguments {
+			if let Some(naga::Binding::Location { location, .. }) = argument.binding {
+				let name = argument.name.clone().unwrap_or_default();
+				locations.push((name, location, kind(argument.ty)));
+			} else if let naga::TypeInner::Struct { members, .. } = &module.types[argument.ty].inner
+			{
+				for member in members {
+					if let Some(naga::Binding::Location { location, .. }) = member.binding {
+						let name = member.name.clone().unwrap_or_default();
+						locations.push((name, location, kind(member.ty)));
+					}
+				}
+			}
+		}
+		locations
+	}
+
+	fn constant(module: &naga::Module, name: &str) -> Option<u32> {
+		module
+			.constants
+			.iter()
+			.find(|(_, constant)| constant.name.as_deref() == Some(name))
+			.and_then(
+				|(_, constant)| match module.global_expressions[constant.init] {
+					naga::Expression::Literal(naga::Literal::U32(value)) => Some(value),
+					_ => None,
+				},
+			)
+	}
+
+	#[test]
+	fn shaders_validate() {
+		for (path, defines) in permutations() {
+			let (source, module) = parse(path, defines);
+			Validator::new(ValidationFlags::all(), Capabilities::default())
+				.validate(&module)
+				.unwrap_or_else(|error| {
+					panic!("{path} {defines:?}: {}", error.emit_to_string(&source))
+				});
+		}
+	}
+
+	#[test]
+	fn layouts_match() {
+		let structs: [(&str, Layout); 7] = [
+			(
+				"Uniforms",
+				layout!(Uniforms {
+					projection,
+					view,
+					time,
+					fog_mode,
+					fog_start,
+					fog_end,
+					fog_color,
+					fog_density,
+					color_mode,
+				}),
+			),
+			(
+				"PointLight",
+				layout!(PointLightData {
+					position,
+					radius,
+					color
+				}),
+			),
+			("PointLights", layout!(PointLightUniforms { count, lights })),
+			(
+				"Instance",
+				layout!(SpriteInstance {
+					model,
+					size,
+					billboard,
+					texture,
+					tint,
+					uv_rect,
+					light,
+					flags,
+				}),
+			),
+			(
+				"CullParams",
+				layout!(CullParams {
+					planes,
+					first,
+					count,
+					slot
+				}),
+			),
+			("Dissolve", layout!(DissolveUniforms { edge_color, amount })),
+			(
+				"SkyParams",
+				layout!(SkyParams {
+					ceiling,
+					floor,
+					repeat,
+					drift,
+					height
+				}),
+			),
+		];
+		let constants = [
+			("LIGHT_LEVELS", LIGHT_LEVELS as u32),
+			("MAX_POINT_LIGHTS", MAX_POINT_LIGHTS as u32),
+			("FLIP_X", SpriteInstance::FLIP_X),
+			("FLIP_Y", SpriteInstance::FLIP_Y),
+			("FULLBRIGHT", SpriteInstance::FULLBRIGHT),
+			("INDEXED", SpriteInstance::INDEXED),
+		];
+
+		let mut seen = HashSet::<&str>::default();
+		for (path, defines) in permutations() {
+			let (_, module) = parse(path, defines);
+			for (name, expected) in &structs {
+				if let Some(layout) = struct_layout(&module, name) {
+					assert_eq!(&layout, expected, "layout of {name} in {path}");
+					seen.insert(name);
+				}
+			}
+			for &(name, expected) in &constants {
+				if let Some(value) = constant(&module, name) {
+					assert_eq!(value, expected, "value of {name} in {path}");
+					seen.insert(name);
+				}
+			}
+		}
+		for name in structs
+			.iter()
+			.map(|(name, _)| name)
+			.chain(constants.iter().map(|(name, _)| name))
+		{
+			assert!(seen.contains(name), "no shader defines {name}");
+		}
+	}
+
+	#[test]
+	fn vertex_inputs_match() {
+		// each vertex buffer's attributes, with the offset each input reads
+		type Buffer<'a> = (&'a [wgpu::VertexAttribute], Vec<(&'a str, usize)>);
+		let model = offset_of!(SpriteInstance, model);
+		let sprite_instance: Buffer = (&SPRITE_INSTANCE_ATTRIBUTES, vec![
+			("model_0", model),
+			("model_1", model + size_of::<Vec4>()),
+			("model_2", model + size_of::<[Vec4; 2]>()),
+			("model_3", model + size_of::<[Vec4; 3]>()),
+			("size", offset_of!(SpriteInstance, size)),
+			("billboard", offset_of!(SpriteInstance, billboard)),
+			("texture", offset_of!(SpriteInstance, texture)),
+			("tint", offset_of!(SpriteInstance, tint)),
+			("uv_rect", offset_of!(SpriteInstance, uv_rect)),
+			("light", offset_of!(SpriteInstance, light)),
+			("flags", offset_of!(SpriteInstance, flags)),
+		]);
+		let model = offset_of!(MeshInstance, model);
+		let mesh_vertex: Buffer = (&MESH_VERTEX_ATTRIBUTES, vec![
+			("position", offset_of!(MeshVertex, position)),
+			("normal", offset_of!(MeshVertex, normal)),
+			("uv", offset_of!(MeshVertex, uv)),
+		]);
+		let mesh_instance: Buffer = (&MESH_INSTANCE_ATTRIBUTES, vec![
+			("model_0", model),
+			("model_1", model + size_of::<Vec4>()),
+			("model_2", model + size_of::<[Vec4; 2]>()),
+			("model_3", model + size_of::<[Vec4; 3]>()),
+			("tint", offset_of!(MeshInstance, tint)),
+			("light", offset_of!(MeshInstance, light)),
+			("texture", offset_of!(MeshInstance, texture)),
+		]);
+		let static_vertex: Buffer = (&STATIC_VERTEX_ATTRIBUTES, vec![
+			("position", offset_of!(StaticVertex, position)),
+			("uv", offset_of!(StaticVertex, uv)),
+			("light", offset_of!(StaticVertex, light)),
+			("texture", offset_of!(StaticVertex, texture)),
+			("flags", offset_of!(StaticVertex, flags)),
+			("tint", offset_of!(StaticVertex, tint)),
+			("uv_rect", offset_of!(StaticVertex, uv_rect)),
+		]);
+		let shaders = [
+			("quad.wgsl", "vertex_main", vec![sprite_instance]),
+			("mesh.wgsl", "vertex_main", vec![mesh_vertex, mesh_instance]),
+			("static.wgsl", "static_main", vec![static_vertex]),
+		];
+
+		for (path, entry, buffers) in &shaders {
+			let (_, module) = parse(path, &[]);
+			let locations = vertex_locations(&module, entry);
+			for (attributes, inputs) in buffers {
+				for &(input, offset) in inputs {
+					let &(_, location, kind) = locations
+						.iter()
+						.find(|(name, ..)| name == input)
+						.unwrap_or_else(|| panic!("{path} has no vertex input {input}"));
+					let attribute = attributes
+						.iter()
+						.find(|attribute| attribute.shader_location == location)
+						.unwrap_or_else(|| panic!("nothing feeds {input} at {location} in {path}"));
+					assert_eq!(
+						attribute.offset, offset as u64,
+						"offset of {input} at location {location} in {path}"
+					);
+					// integers passed as floats come through as different
+					// numbers, or none at all
+					let fed_kind = match attribute.format {
+						wgpu::VertexFormat::Uint32 |
+						wgpu::VertexFormat::Uint32x2 |
+						wgpu::VertexFormat::Uint32x3 |
+						wgpu::VertexFormat::Uint32x4 => naga::ScalarKind::Uint,
+						_ => naga::ScalarKind::Float,
+					};
+					assert_eq!(fed_kind, kind, "type of {input} in {path}");
+				}
+			}
+			let fed = buffers
+				.iter()
+				.map(|(_, inputs)| inputs.len())
+				.sum::<usize>();
+			assert_eq!(locations.len(), fed, "vertex inputs of {path}");
+		}
+	}
+
+	#[test]
+	fn directives() {
+		let text = "a\n#ifdef A\nb\n#else\nc\n#endif\n#define B\n#ifndef B\nd\n#endif\n";
+		assert_eq!(preprocess(text, &["A"]).unwrap(), "a\nb\n");
+		assert_eq!(preprocess(text, &[]).unwrap(), "a\nc\n");
+
+		let twice = "#include \"common/view.wgsl\"\n#include \"common/view.wgsl\"\n";
+		assert_eq!(
+			preprocess(twice, &[]).unwrap(),
+			preprocess(&source("common/view.wgsl").unwrap(), &[]).unwrap(),
+		);
+
+		assert!(preprocess("#ifdef A\n", &[]).is_err());
+		assert!(preprocess("#endif\n", &[]).is_err());
+		assert!(preprocess("#include \"missing.wgsl\"\n", &[]).is_err());
+		assert!(preprocess("#pragma\n", &[]).is_err());
+	}
+}
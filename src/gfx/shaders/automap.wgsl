@@ -0,0 +1,29 @@
+struct VIn {
+	@location(0)
+	position: vec2f,
+
+	@location(1)
+	color: vec4f,
+}
+
+struct VOut {
+	@builtin(position)
+	position: vec4f,
+
+	@location(0)
+	color: vec4f,
+}
+
+// positions are already in clip space
+@vertex
+fn vertex_main(in: VIn) -> VOut {
+	return VOut(
+		vec4f(in.position, 0.0, 1.0),
+		in.color,
+	);
+}
+
+@fragment
+fn fragment_main(in: VOut) -> @location(0) vec4f {
+	return in.color;
+}
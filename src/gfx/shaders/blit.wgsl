@@ -0,0 +1,27 @@
+@group(0)
+@binding(0)
+var source: texture_2d<f32>;
+@group(0)
+@binding(1)
+var source_sampler: sampler;
+
+struct VOut {
+	@builtin(position)
+	position: vec4f,
+
+	@location(0)
+	uv: vec2f,
+}
+
+// single triangle covering the whole target
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex: u32) -> VOut {
+	let uv = vec2f(f32((vertex << 1u) & 2u), f32(vertex & 2u));
+	return VOut(vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0), uv);
+}
+
+// the source is scaled to fit the target, whatever its size
+@fragment
+fn fragment_main(in: VOut) -> @location(0) vec4f {
+	return textureSampleLevel(source, source_sampler, in.uv, 0.0);
+}
@@ -0,0 +1,12 @@
+// single triangle covering the whole viewport, at the far plane
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex: u32) -> @builtin(position) vec4f {
+	let uv = vec2f(f32((vertex << 1u) & 2u), f32(vertex & 2u));
+	return vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 1.0, 1.0);
+}
+
+// the actual colour is the blend constant
+@fragment
+fn fragment_main() -> @location(0) vec4f {
+	return vec4f(1.0);
+}
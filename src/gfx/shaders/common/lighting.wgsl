@@ -0,0 +1,46 @@
+#include "common/view.wgsl"
+
+// must match `MAX_POINT_LIGHTS` in `lighting.rs`
+const MAX_POINT_LIGHTS: u32 = 16u;
+
+struct PointLight {
+	position: vec3f,
+	radius: f32,
+	color: vec3f,
+}
+
+// must match `PointLightUniforms`
+struct PointLights {
+	count: u32,
+	lights: array<PointLight, MAX_POINT_LIGHTS>,
+}
+
+@group(0)
+@binding(1)
+var<uniform> point_lights: PointLights;
+
+fn point_lighting(position: vec3f) -> vec3f {
+	var total = vec3f(0.0);
+	for (var i = 0u; i < min(point_lights.count, MAX_POINT_LIGHTS); i++) {
+		let light = point_lights.lights[i];
+		let falloff = clamp(1.0 - distance(position, light.position) / light.radius, 0.0, 1.0);
+		total += light.color * falloff * falloff;
+	}
+	return total;
+}
+
+fn apply_fog(color: vec3f, distance: f32) -> vec3f {
+	var amount = 0.0;
+	switch uniforms.fog_mode {
+		case 1u {
+			// a zero-length ramp is a hard edge at `fog_start`
+			let ramp = max(uniforms.fog_end - uniforms.fog_start, 1e-4);
+			amount = (distance - uniforms.fog_start) / ramp;
+		}
+		case 2u {
+			amount = 1.0 - exp(-uniforms.fog_density * distance);
+		}
+		default {}
+	}
+	return mix(color, uniforms.fog_color.rgb, clamp(amount, 0.0, 1.0) * uniforms.fog_color.a);
+}
@@ -0,0 +1,17 @@
+// must match the `SpriteInstance` flags
+const FLIP_X: u32 = 1u;
+const FLIP_Y: u32 = 2u;
+const FULLBRIGHT: u32 = 4u;
+const INDEXED: u32 = 8u;
+
+// must match `SpriteInstance`
+struct Instance {
+	model: mat4x4f,
+	size: vec2f,
+	billboard: u32,
+	texture: u32,
+	tint: vec4f,
+	uv_rect: vec4f,
+	light: f32,
+	flags: u32,
+}
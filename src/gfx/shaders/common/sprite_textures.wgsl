@@ -0,0 +1,20 @@
+// the bind group of `SpriteTextures`
+
+// must match `LIGHT_LEVELS` in `palette.rs`
+const LIGHT_LEVELS: u32 = 32u;
+
+@group(1)
+@binding(0)
+var sprite_textures: texture_2d_array<f32>;
+
+@group(1)
+@binding(1)
+var sprite_sampler: sampler;
+
+@group(1)
+@binding(2)
+var palette: texture_2d<f32>;
+
+@group(1)
+@binding(3)
+var colormap: texture_2d<u32>;
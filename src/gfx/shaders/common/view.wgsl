@@ -0,0 +1,16 @@
+// per-view values, must match `Uniforms` in `gfx/mod.rs`
+struct Uniforms {
+	projection: mat4x4f,
+	view: mat4x4f,
+	time: f32,
+	fog_mode: u32,
+	fog_start: f32,
+	fog_end: f32,
+	fog_color: vec4f,
+	fog_density: f32,
+	color_mode: u32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> uniforms: Uniforms;
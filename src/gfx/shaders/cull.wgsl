@@ -0,0 +1,52 @@
+#include "common/sprite_instance.wgsl"
+
+struct CullParams {
+	planes: array<vec4f, 6>,
+	first: u32,
+	count: u32,
+	slot: u32,
+}
+
+struct DrawArgs {
+	vertex_count: u32,
+	instance_count: atomic<u32>,
+	first_vertex: u32,
+	first_instance: u32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> params: CullParams;
+
+@group(0)
+@binding(1)
+var<storage, read> instances: array<Instance>;
+
+@group(0)
+@binding(2)
+var<storage, read_write> visible: array<Instance>;
+
+@group(0)
+@binding(3)
+var<storage, read_write> args: array<DrawArgs>;
+
+@compute
+@workgroup_size(64)
+fn cull_main(@builtin(global_invocation_id) id: vec3u) {
+	if id.x >= params.count {
+		return;
+	}
+
+	let instance = instances[params.first + id.x];
+	let center = instance.model[3].xyz;
+	let radius = length(instance.size) / 2.0;
+	for (var i = 0u; i < 6u; i++) {
+		let plane = params.planes[i];
+		if dot(plane.xyz, center) + plane.w < -radius {
+			return;
+		}
+	}
+
+	let index = atomicAdd(&args[params.slot].instance_count, 1u);
+	visible[params.first + index] = instance;
+}
@@ -0,0 +1,30 @@
+#include "common/view.wgsl"
+
+struct VIn {
+	@location(0)
+	position: vec3f,
+
+	@location(1)
+	color: vec4f,
+}
+
+struct VOut {
+	@builtin(position)
+	position: vec4f,
+
+	@location(0)
+	color: vec4f,
+}
+
+@vertex
+fn vertex_main(in: VIn) -> VOut {
+	return VOut(
+		uniforms.projection * uniforms.view * vec4f(in.position, 1.0),
+		in.color,
+	);
+}
+
+@fragment
+fn fragment_main(in: VOut) -> @location(0) vec4f {
+	return in.color;
+}
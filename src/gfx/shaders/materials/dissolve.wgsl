@@ -0,0 +1,30 @@
+#include "quad.wgsl"
+
+struct Dissolve {
+	edge_color: vec4f,
+	amount: f32,
+}
+
+@group(2)
+@binding(0)
+var<uniform> dissolve: Dissolve;
+
+fn hash(p: vec2f) -> f32 {
+	return fract(sin(dot(p, vec2f(12.9898, 78.233))) * 43758.5453);
+}
+
+@fragment
+fn material_main(in: VOut) -> @location(0) vec4f {
+	let noise = hash(floor(in.uv * 32.0));
+	if noise < dissolve.amount {
+		discard;
+	}
+	let color = shade(in);
+	if color.a < 0.5 {
+		discard;
+	}
+	if noise < dissolve.amount + 0.05 {
+		return vec4f(dissolve.edge_color.rgb, 1.0);
+	}
+	return vec4f(color.rgb, 1.0);
+}
@@ -0,0 +1,12 @@
+#include "quad.wgsl"
+
+@fragment
+fn material_main(in: VOut) -> @location(0) vec4f {
+	let t = uniforms.time * 0.3;
+	let churn = sin(in.uv.x * 9.0 + t * 2.0) + sin(in.uv.y * 7.0 - t * 3.0) +
+		sin((in.uv.x + in.uv.y) * 5.0 + t);
+	let heat = churn / 6.0 + 0.5;
+	let color = mix(vec3f(0.5, 0.05, 0.0), vec3f(1.0, 0.8, 0.2), heat);
+	// glows, so only fog applies
+	return vec4f(apply_fog(color, in.distance), 1.0);
+}
@@ -0,0 +1,13 @@
+#include "quad.wgsl"
+
+@fragment
+fn material_main(in: VOut) -> @location(0) vec4f {
+	var rippled = in;
+	let phase = uniforms.time * 2.0;
+	rippled.uv += vec2f(
+		sin(in.uv.y * 12.0 + phase) * 0.02,
+		cos(in.uv.x * 10.0 + phase * 0.8) * 0.02,
+	);
+	let color = shade(rippled);
+	return vec4f(color.rgb * vec3f(0.6, 0.8, 1.0), color.a * 0.7);
+}
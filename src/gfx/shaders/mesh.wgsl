@@ -0,0 +1,96 @@
+#include "common/lighting.wgsl"
+#include "common/sprite_textures.wgsl"
+
+struct VIn {
+	@location(0)
+	position: vec3f,
+
+	@location(1)
+	normal: vec3f,
+
+	@location(2)
+	uv: vec2f,
+
+	@location(3)
+	model_0: vec4f,
+
+	@location(4)
+	model_1: vec4f,
+
+	@location(5)
+	model_2: vec4f,
+
+	@location(6)
+	model_3: vec4f,
+
+	@location(7)
+	tint: vec4f,
+
+	@location(8)
+	light: f32,
+
+	@location(9)
+	texture: u32,
+}
+
+struct VOut {
+	@builtin(position)
+	position: vec4f,
+
+	@location(0)
+	uv: vec2f,
+
+	@location(1)
+	light: f32,
+
+	@location(2)
+	distance: f32,
+
+	@location(3)
+	world_position: vec3f,
+
+	@location(4)
+	normal: vec3f,
+
+	@location(5)
+	@interpolate(flat)
+	texture: u32,
+
+	@location(6)
+	tint: vec4f,
+}
+
+@vertex
+fn vertex_main(in: VIn) -> VOut {
+	let model = mat4x4f(in.model_0, in.model_1, in.model_2, in.model_3);
+	let world_position = model * vec4f(in.position, 1.0);
+	let view_position = uniforms.view * world_position;
+	return VOut(
+		uniforms.projection * view_position,
+		in.uv,
+		in.light,
+		length(view_position.xyz),
+		world_position.xyz,
+		// models are only rotated, never scaled
+		(model * vec4f(in.normal, 0.0)).xyz,
+		in.texture,
+		in.tint,
+	);
+}
+
+@fragment
+fn fragment_main(in: VOut) -> @location(0) vec4f {
+	var color = in.tint;
+	if in.texture != 0u {
+		// the sprite sampler clamps, so repeat by hand
+		color *= textureSampleLevel(sprite_textures, sprite_sampler, fract(in.uv), in.texture - 1u, 0.0);
+	}
+	// cut-out transparency only, like opaque sprites
+	if color.a < 0.5 {
+		discard;
+	}
+	// a little light from above, so the faces of a prop stay distinguishable
+	let facing = 0.75 + 0.25 * normalize(in.normal).z;
+	let light = in.light * facing + point_lighting(in.world_position);
+	return vec4f(apply_fog(color.rgb * light, in.distance), 1.0);
+}
@@ -0,0 +1,178 @@
+#include "common/lighting.wgsl"
+#include "common/sprite_instance.wgsl"
+#include "common/sprite_textures.wgsl"
+
+const PI: f32 = 3.14159265358979323846264338327950288;
+
+struct VIn {
+	@builtin(vertex_index)
+	vertex: u32,
+
+	@location(0)
+	model_0: vec4f,
+
+	@location(1)
+	model_1: vec4f,
+
+	@location(2)
+	model_2: vec4f,
+
+	@location(3)
+	model_3: vec4f,
+
+	@location(4)
+	size: vec2f,
+
+	@location(5)
+	billboard: u32,
+
+	@location(6)
+	texture: u32,
+
+	@location(7)
+	tint: vec4f,
+
+	@location(8)
+	uv_rect: vec4f,
+
+	@location(9)
+	light: f32,
+
+	@location(10)
+	flags: u32,
+}
+
+struct VOut {
+	@builtin(position)
+	position: vec4f,
+
+	@location(0)
+	uv: vec2f,
+
+	@location(1)
+	light: f32,
+
+	@location(2)
+	distance: f32,
+
+	@location(3)
+	world_position: vec3f,
+
+	@location(4)
+	@interpolate(flat)
+	texture: u32,
+
+	@location(5)
+	tint: vec4f,
+
+	@location(6)
+	@interpolate(flat)
+	flags: u32,
+
+	@location(7)
+	@interpolate(flat)
+	uv_rect: vec4f,
+}
+
+@vertex
+fn vertex_main(in: VIn) -> VOut {
+	var vertex: vec4f;
+	var uv: vec2f;
+	switch in.vertex {
+		case 0 {
+			vertex = vec4f(-0.5, 0.0, 0.5, 1.0);
+			uv = vec2f(0.0, 1.0);
+		}
+		case 1 {
+			vertex = vec4f(0.5, 0.0, 0.5, 1.0);
+			uv = vec2f(1.0, 1.0);
+		}
+		case 2 {
+			vertex = vec4f(-0.5, 0.0, -0.5, 1.0);
+			uv = vec2f(0.0, 0.0);
+		}
+		case 3 {
+			vertex = vec4f(0.5, 0.0, -0.5, 1.0);
+			uv = vec2f(1.0, 0.0);
+		}
+		default {
+			vertex = vec4f(0.0, 0.0, 0.0, 1.0);
+			uv = vec2f(0.5, 0.5);
+		}
+	}
+	vertex = vertex * vec4f(in.size.x, 1.0, in.size.y, 1.0);
+	let model = mat4x4f(in.model_0, in.model_1, in.model_2, in.model_3);
+	var world_position = model * vertex;
+	if in.billboard != 0u {
+		// face the camera, keeping only the model's translation. seen from the
+		// front a quad's +x is on the viewer's left, hence the negated right.
+		let right = vec3f(uniforms.view[0].x, uniforms.view[1].x, uniforms.view[2].x);
+		let up = vec3f(uniforms.view[0].y, uniforms.view[1].y, uniforms.view[2].y);
+		world_position = vec4f(model[3].xyz - right * vertex.x + up * vertex.z, 1.0);
+	}
+	let view_position = uniforms.view * world_position;
+	return VOut(
+		uniforms.projection * view_position,
+		uv,
+		in.light,
+		length(view_position.xyz),
+		world_position.xyz,
+		in.texture,
+		in.tint,
+		in.flags,
+		in.uv_rect,
+	);
+}
+
+fn shade(in: VOut) -> vec4f {
+	var color: vec4f;
+	if in.texture == 0u {
+		color = vec4f(
+			in.uv,
+			cos(uniforms.time * 2.0 * PI),
+			1.0,
+		);
+	} else {
+		// uv runs right to left and bottom to top as seen from the front
+		var uv = vec2f(1.0 - in.uv.x, 1.0 - in.uv.y);
+		if (in.flags & FLIP_X) != 0u {
+			uv.x = 1.0 - uv.x;
+		}
+		if (in.flags & FLIP_Y) != 0u {
+			uv.y = 1.0 - uv.y;
+		}
+		uv = mix(in.uv_rect.xy, in.uv_rect.zw, uv);
+		color = textureSampleLevel(sprite_textures, sprite_sampler, uv, in.texture - 1u, 0.0);
+	}
+	var light = vec3f(1.0);
+	if (in.flags & FULLBRIGHT) == 0u {
+		light = in.light + point_lighting(in.world_position);
+	}
+	if (in.flags & INDEXED) != 0u {
+		var index = u32(round(color.r * 255.0));
+		if uniforms.color_mode == 1u {
+			// light by picking a darker palette colour, as the originals did
+			let brightness = clamp(max(light.r, max(light.g, light.b)), 0.0, 1.0);
+			let level = u32(round((1.0 - brightness) * f32(LIGHT_LEVELS - 1u)));
+			index = textureLoad(colormap, vec2u(index, level), 0).r;
+			light = vec3f(1.0);
+		}
+		color = vec4f(textureLoad(palette, vec2u(index, 0u), 0).rgb, color.a);
+	}
+	color *= in.tint;
+	return vec4f(apply_fog(color.rgb * light, in.distance), color.a);
+}
+
+@fragment
+fn fragment_main(in: VOut) -> @location(0) vec4f {
+	let color = shade(in);
+#ifdef TRANSPARENT
+	return color;
+#else
+	// opaque sprites only have cut-out transparency
+	if color.a < 0.5 {
+		discard;
+	}
+	return vec4f(color.rgb, 1.0);
+#endif
+}
@@ -0,0 +1,30 @@
+@group(0)
+@binding(0)
+var frame_texture: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var frame_sampler: sampler;
+
+struct VOut {
+	@builtin(position)
+	position: vec4f,
+
+	@location(0)
+	uv: vec2f,
+}
+
+// single triangle covering the whole viewport
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex: u32) -> VOut {
+	let uv = vec2f(f32((vertex << 1u) & 2u), f32(vertex & 2u));
+	return VOut(
+		vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0),
+		uv,
+	);
+}
+
+@fragment
+fn fragment_main(in: VOut) -> @location(0) vec4f {
+	return textureSample(frame_texture, frame_sampler, in.uv);
+}
@@ -0,0 +1,78 @@
+#include "common/view.wgsl"
+
+const TAU: f32 = 6.2831855;
+
+// must match `SkyParams` in `gfx/sky.rs`
+struct SkyParams {
+	ceiling: vec4f,
+	floor: vec4f,
+	repeat: f32,
+	drift: f32,
+	height: f32,
+}
+
+@group(1)
+@binding(0)
+var<uniform> params: SkyParams;
+
+#ifdef CUBEMAP
+@group(2)
+@binding(0)
+var sky_texture: texture_cube<f32>;
+#else
+@group(2)
+@binding(0)
+var sky_texture: texture_2d<f32>;
+#endif
+
+@group(2)
+@binding(1)
+var sky_sampler: sampler;
+
+struct VOut {
+	@builtin(position) position: vec4f,
+	@location(0) ndc: vec2f,
+}
+
+// single triangle covering the whole viewport, at the far plane
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex: u32) -> VOut {
+	let uv = vec2f(f32((vertex << 1u) & 2u), f32(vertex & 2u));
+	let ndc = vec2f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+	return VOut(vec4f(ndc, 1.0, 1.0), ndc);
+}
+
+// world-space direction seen through a point of the viewport
+fn view_direction(ndc: vec2f) -> vec3f {
+	let eye = vec3f(ndc.x / uniforms.projection[0][0], ndc.y / uniforms.projection[1][1], -1.0);
+	// the view matrix only rotates and translates, so the transpose of its
+	// rotation undoes it
+	let rotation = mat3x3f(uniforms.view[0].xyz, uniforms.view[1].xyz, uniforms.view[2].xyz);
+	return normalize(transpose(rotation) * eye);
+}
+
+#ifdef CUBEMAP
+@fragment
+fn cubemap_main(in: VOut) -> @location(0) vec4f {
+	let direction = view_direction(in.ndc);
+	// cube textures are looked up with y up
+	return textureSample(sky_texture, sky_sampler, direction.xzy);
+}
+#else
+@fragment
+fn flat_main(in: VOut) -> @location(0) vec4f {
+	return select(params.floor, params.ceiling, view_direction(in.ndc).z > 0.0);
+}
+
+@fragment
+fn panorama_main(in: VOut) -> @location(0) vec4f {
+	let direction = view_direction(in.ndc);
+	// turns clockwise from north, as seen from above
+	let turns = atan2(direction.x, direction.y) / TAU + uniforms.time * params.drift;
+	// height on a cylinder of radius 1 around the eye, with the bottom of the
+	// texture on the horizon
+	let height = direction.z / max(length(direction.xy), 0.0001);
+	let uv = vec2f(turns * params.repeat, 1.0 - height / params.height);
+	return textureSample(sky_texture, sky_sampler, uv);
+}
+#endif
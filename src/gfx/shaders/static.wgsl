@@ -0,0 +1,41 @@
+// baked `Fixed` sprites, shaded the same as their instanced counterparts
+#include "quad.wgsl"
+
+struct StaticIn {
+	@location(0)
+	position: vec3f,
+
+	@location(1)
+	uv: vec2f,
+
+	@location(2)
+	light: f32,
+
+	@location(3)
+	texture: u32,
+
+	@location(4)
+	flags: u32,
+
+	@location(5)
+	tint: vec4f,
+
+	@location(6)
+	uv_rect: vec4f,
+}
+
+@vertex
+fn static_main(in: StaticIn) -> VOut {
+	let view_position = uniforms.view * vec4f(in.position, 1.0);
+	return VOut(
+		uniforms.projection * view_position,
+		in.uv,
+		in.light,
+		length(view_position.xyz),
+		in.position,
+		in.texture,
+		in.tint,
+		in.flags,
+		in.uv_rect,
+	);
+}
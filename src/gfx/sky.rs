@@ -0,0 +1,713 @@
+//! The backdrop behind the world, drawn right after each view is cleared so
+//! that open-air areas show more than the clear colour. Levels pick a sky from
+//! [`Skies`] by name, falling back to [`Sky::FLAT`].
+
+use std::{f32::consts::TAU, num::NonZero};
+
+use bevy_platform::collections::HashMap;
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		Color,
+		GraphicsContext,
+		Pipelines,
+		RenderPre,
+		RenderPreSet,
+		WorldRenderer,
+		camera::{ClearMode, CurrentView, RenderView, ViewSet, clear_view},
+		profiling::GpuProfiler,
+		shader,
+		textures::Texel,
+	},
+	prelude::*,
+};
+
+/// Most layers of a [`Sky::Panorama`] drawn. Further layers are skipped.
+pub const MAX_SKY_LAYERS: usize = 4;
+
+/// Name of the sky levels get when they don't name one.
+const FALLBACK_SKY: &str = "flat";
+
+/// What is drawn behind the world in views cleared to a colour.
+#[derive(Resource, Clone, Debug, Default, PartialEq)]
+pub enum Sky {
+	/// Only the camera's clear colour.
+	#[default]
+	None,
+	/// One colour above the horizon and another below it, standing in for an
+	/// untextured ceiling and floor.
+	Flat {
+		ceiling: Color,
+		floor: Color,
+	},
+	/// Layers wrapped around the horizon on a cylinder, drawn over each other
+	/// from the first.
+	Panorama(Vec<PanoramaLayer>),
+	Cubemap(CubemapId),
+}
+
+impl Sky {
+	/// The ceiling and floor colours of the raycaster.
+	pub const FLAT: Self = Self::Flat {
+		ceiling: Color::rgb(0.22, 0.22, 0.22),
+		floor: Color::rgb(0.44, 0.44, 0.44),
+	};
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PanoramaLayer {
+	pub texture: PanoramaId,
+	/// Times the texture wraps around the horizon. Layers repeated more often
+	/// scroll past faster as the camera turns, so they seem nearer.
+	pub repeat: f32,
+	/// Turns around the horizon per second, for drifting clouds.
+	pub drift: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PanoramaId(u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CubemapId(u32);
+
+/// The skies levels can pick by name.
+#[derive(Resource, Clone, Debug)]
+pub struct Skies(pub HashMap<String, Sky>);
+
+impl Skies {
+	pub(super) fn new(ctx: &GraphicsContext, sky_pipelines: &mut SkyPipelines) -> Self {
+		let skies = [
+			("none", Sky::None),
+			("flat", Sky::FLAT),
+			("dusk", dusk(ctx, sky_pipelines)),
+			("night", night(ctx, sky_pipelines)),
+		];
+		Self(
+			skies
+				.into_iter()
+				.map(|(name, sky)| (name.to_owned(), sky))
+				.collect(),
+		)
+	}
+}
+
+/// Name in [`Skies`] of the sky the current level is under, or `None` for
+/// the fallback.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LevelSky(pub Option<String>);
+
+/// Must match `SkyParams` in `sky.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(super) struct SkyParams {
+	pub(super) ceiling: Color,
+	pub(super) floor: Color,
+	pub(super) repeat: f32,
+	pub(super) drift: f32,
+	/// Height of a panorama layer above the horizon, in radii of its cylinder.
+	pub(super) height: f32,
+	_padding: f32,
+}
+
+/// Sky pipelines, and the textures skies are drawn with.
+pub struct SkyPipelines {
+	flat: wgpu::RenderPipeline,
+	panorama: wgpu::RenderPipeline,
+	cubemap: wgpu::RenderPipeline,
+	/// [`SkyParams`] for each layer of the current sky.
+	params: wgpu::Buffer,
+	params_stride: u32,
+	params_group: wgpu::BindGroup,
+	params_layout: wgpu::BindGroupLayout,
+	panorama_layout: wgpu::BindGroupLayout,
+	cubemap_layout: wgpu::BindGroupLayout,
+	panorama_sampler: wgpu::Sampler,
+	cubemap_sampler: wgpu::Sampler,
+	/// Group and size in texels of each panorama.
+	panoramas: Vec<(wgpu::BindGroup, UVec2)>,
+	cubemaps: Vec<wgpu::BindGroup>,
+}
+
+impl SkyPipelines {
+	pub fn new(ctx: &GraphicsContext, pipelines: &Pipelines) -> Self {
+		let params_stride = (size_of::<SkyParams>() as u32)
+			.next_multiple_of(ctx.device.limits().min_uniform_buffer_offset_alignment);
+		let params = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("sky params"),
+			size: (params_stride as usize * MAX_SKY_LAYERS) as _,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+		let params_layout = ctx
+			.device
+			.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+				label: Some("sky params layout"),
+				entries: &[wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					count: None,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: true,
+						min_binding_size: NonZero::new(size_of::<SkyParams>() as _),
+					},
+				}],
+			});
+		let params_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("sky params group"),
+			layout: &params_layout,
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+					buffer: &params,
+					offset: 0,
+					size: NonZero::new(size_of::<SkyParams>() as _),
+				}),
+			}],
+		});
+
+		let texture_layout = |label, view_dimension| {
+			ctx.device
+				.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+					label: Some(label),
+					entries: &[
+						wgpu::BindGroupLayoutEntry {
+							binding: 0,
+							count: None,
+							visibility: wgpu::ShaderStages::FRAGMENT,
+							ty: wgpu::BindingType::Texture {
+								sample_type: wgpu::TextureSampleType::Float { filterable: true },
+								view_dimension,
+								multisampled: false,
+							},
+						},
+						wgpu::BindGroupLayoutEntry {
+							binding: 1,
+							count: None,
+							visibility: wgpu::ShaderStages::FRAGMENT,
+							ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+						},
+					],
+				})
+		};
+		let panorama_layout = texture_layout("panorama layout", wgpu::TextureViewDimension::D2);
+		let cubemap_layout = texture_layout("cubemap layout", wgpu::TextureViewDimension::Cube);
+
+		let (flat, panorama, cubemap) = create_pipelines(
+			ctx,
+			&pipelines.uniforms_layout,
+			&params_layout,
+			&panorama_layout,
+			&cubemap_layout,
+			&shader::create_shader_module(ctx, "sky.wgsl", &[]),
+			&shader::create_shader_module(ctx, "sky.wgsl", &["CUBEMAP"]),
+		);
+
+		Self {
+			flat,
+			panorama,
+			cubemap,
+			params,
+			params_stride,
+			params_group,
+			params_layout,
+			panorama_layout,
+			cubemap_layout,
+			// panoramas wrap around the horizon, but not over the top
+			panorama_sampler: ctx.device.create_sampler(&wgpu::SamplerDescriptor {
+				label: Some("panorama sampler"),
+				address_mode_u: wgpu::AddressMode::Repeat,
+				..default()
+			}),
+			cubemap_sampler: ctx.device.create_sampler(&wgpu::SamplerDescriptor {
+				label: Some("cubemap sampler"),
+				..default()
+			}),
+			panoramas: Vec::new(),
+			cubemaps: Vec::new(),
+		}
+	}
+
+	/// Uploads a panorama of `size` texels, in rows from the top. The bottom
+	/// row lies on the horizon, and the texels are kept square however many
+	/// times a layer repeats it, with the top row stretched to the zenith.
+	pub fn add_panorama(
+		&mut self,
+		ctx: &GraphicsContext,
+		size: UVec2,
+		texels: &[Texel],
+	) -> PanoramaId {
+		assert_eq!(
+			texels.len(),
+			(size.x * size.y) as usize,
+			"panorama texels don't match its size"
+		);
+		let texture = create_texture(ctx, "panorama", size, 1, &[texels]);
+		let group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("panorama group"),
+			layout: &self.panorama_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(&texture.create_view(&default())),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::Sampler(&self.panorama_sampler),
+				},
+			],
+		});
+		self.panoramas.push((group, size));
+		PanoramaId(self.panoramas.len() as u32 - 1)
+	}
+
+	/// Uploads the faces of a cube `size` texels across, in rows from the top.
+	/// The faces are in the order east, west, north, south, up and down, each
+	/// as seen turning to it from facing north.
+	pub fn add_cubemap(
+		&mut self,
+		ctx: &GraphicsContext,
+		size: u32,
+		faces: [&[Texel]; 6],
+	) -> CubemapId {
+		for face in faces {
+			assert_eq!(
+				face.len(),
+				(size * size) as usize,
+				"cubemap texels don't match its size"
+			);
+		}
+		// cube layers go along the y-up axes the shader looks them up with
+		let [east, west, north, south, up, down] = faces;
+		let texture = create_texture(ctx, "cubemap", UVec2::splat(size), 6, &[
+			east, west, up, down, north, south,
+		]);
+		let view = texture.create_view(&wgpu::TextureViewDescriptor {
+			dimension: Some(wgpu::TextureViewDimension::Cube),
+			..default()
+		});
+		let group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("cubemap group"),
+			layout: &self.cubemap_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(&view),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::Sampler(&self.cubemap_sampler),
+				},
+			],
+		});
+		self.cubemaps.push(group);
+		CubemapId(self.cubemaps.len() as u32 - 1)
+	}
+}
+
+fn create_texture(
+	ctx: &GraphicsContext,
+	label: &str,
+	size: UVec2,
+	layers: u32,
+	texels: &[&[Texel]],
+) -> wgpu::Texture {
+	let texture = ctx.create_texture(&wgpu::TextureDescriptor {
+		label: Some(label),
+		size: wgpu::Extent3d {
+			width: size.x,
+			height: size.y,
+			depth_or_array_layers: layers,
+		},
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: wgpu::TextureFormat::Rgba8Unorm,
+		usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+		view_formats: &[],
+	});
+	for (layer, texels) in texels.iter().enumerate() {
+		ctx.write_texture(
+			wgpu::TexelCopyTextureInfo {
+				texture: &texture,
+				mip_level: 0,
+				origin: wgpu::Origin3d {
+					x: 0,
+					y: 0,
+					z: layer as u32,
+				},
+				aspect: wgpu::TextureAspect::All,
+			},
+			bytemuck::cast_slice(texels),
+			wgpu::TexelCopyBufferLayout {
+				offset: 0,
+				bytes_per_row: Some(size.x * 4),
+				rows_per_image: None,
+			},
+			wgpu::Extent3d {
+				width: size.x,
+				height: size.y,
+				depth_or_array_layers: 1,
+			},
+		);
+	}
+	texture
+}
+
+/// The flat, panorama and cubemap pipelines.
+fn create_pipelines(
+	ctx: &GraphicsContext,
+	uniforms_layout: &wgpu::BindGroupLayout,
+	params_layout: &wgpu::BindGroupLayout,
+	panorama_layout: &wgpu::BindGroupLayout,
+	cubemap_layout: &wgpu::BindGroupLayout,
+	shader_module: &wgpu::ShaderModule,
+	cubemap_shader_module: &wgpu::ShaderModule,
+) -> (
+	wgpu::RenderPipeline,
+	wgpu::RenderPipeline,
+	wgpu::RenderPipeline,
+) {
+	let create_pipeline = |label,
+	                       bind_group_layouts: &[&wgpu::BindGroupLayout],
+	                       shader_module,
+	                       entry_point,
+	                       blend| {
+		let pipeline_layout = ctx
+			.device
+			.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: Some("sky render layout"),
+				bind_group_layouts,
+				push_constant_ranges: &[],
+			});
+		ctx.device
+			.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+				label: Some(label),
+				layout: Some(&pipeline_layout),
+				// behind everything, so it neither tests nor writes depth
+				depth_stencil: Some(wgpu::DepthStencilState {
+					format: wgpu::TextureFormat::Depth24Plus,
+					depth_write_enabled: false,
+					depth_compare: wgpu::CompareFunction::Always,
+					stencil: default(),
+					bias: default(),
+				}),
+				multisample: wgpu::MultisampleState::default(),
+				multiview: None,
+				cache: None,
+				primitive: wgpu::PrimitiveState::default(),
+				vertex: wgpu::VertexState {
+					module: shader_module,
+					compilation_options: wgpu::PipelineCompilationOptions::default(),
+					entry_point: Some("vertex_main"),
+					buffers: &[],
+				},
+				fragment: Some(wgpu::FragmentState {
+					module: shader_module,
+					compilation_options: wgpu::PipelineCompilationOptions::default(),
+					entry_point: Some(entry_point),
+					targets: &[Some(wgpu::ColorTargetState {
+						format: ctx.surface_format(),
+						blend,
+						write_mask: wgpu::ColorWrites::ALL,
+					})],
+				}),
+			})
+	};
+	(
+		create_pipeline(
+			"flat sky pipeline",
+			&[uniforms_layout, params_layout],
+			shader_module,
+			"flat_main",
+			None,
+		),
+		create_pipeline(
+			"panorama sky pipeline",
+			&[uniforms_layout, params_layout, panorama_layout],
+			shader_module,
+			"panorama_main",
+			Some(wgpu::BlendState::ALPHA_BLENDING),
+		),
+		create_pipeline(
+			"cubemap sky pipeline",
+			&[uniforms_layout, params_layout, cubemap_layout],
+			cubemap_shader_module,
+			"cubemap_main",
+			None,
+		),
+	)
+}
+
+#[cfg(debug_assertions)]
+fn reload_pipelines(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	sky_pipelines: NonSend<SkyPipelines>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	let variants: [&[&str]; 2] = [&[], &["CUBEMAP"]];
+	if !changes.read().any(|change| {
+		variants
+			.iter()
+			.any(|defines| change.affects("sky.wgsl", defines))
+	}) {
+		return;
+	}
+	reloads.rebuild_variants(
+		&ctx,
+		"sky.wgsl",
+		&variants,
+		|shader_modules| {
+			create_pipelines(
+				&ctx,
+				&pipelines.uniforms_layout,
+				&sky_pipelines.params_layout,
+				&sky_pipelines.panorama_layout,
+				&sky_pipelines.cubemap_layout,
+				&shader_modules[0],
+				&shader_modules[1],
+			)
+		},
+		|world, (flat, panorama, cubemap)| {
+			let mut sky_pipelines = world.non_send_resource_mut::<SkyPipelines>();
+			sky_pipelines.flat = flat;
+			sky_pipelines.panorama = panorama;
+			sky_pipelines.cubemap = cubemap;
+		},
+	);
+}
+
+/// The [`Sky`] of the frame being rendered.
+#[derive(Resource, Default)]
+struct ExtractedSky(Sky);
+
+pub(super) fn setup(app: &mut App) {
+	app.init_resource::<Sky>();
+	app.init_resource::<LevelSky>();
+	app.init_resource::<ExtractedSky>();
+	app.add_systems(Update, select_sky);
+	app.add_systems(
+		RenderPre,
+		(
+			extract_sky.in_set(RenderPreSet::Extract),
+			prepare_sky.in_set(RenderPreSet::Prepare),
+		),
+	);
+	app.add_systems(
+		RenderView,
+		draw_sky
+			.in_set(ViewSet::Clear)
+			.after(clear_view)
+			.run_if(resource_equals(WorldRenderer::Polygonal)),
+	);
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_pipelines);
+}
+
+/// Switches to the sky the level names.
+fn select_sky(level: Res<LevelSky>, skies: Res<Skies>, mut sky: ResMut<Sky>) {
+	if !level.is_changed() && !skies.is_changed() {
+		return;
+	}
+	let name = level.0.as_deref().unwrap_or(FALLBACK_SKY);
+	let selected = skies.0.get(name).cloned().unwrap_or_else(|| {
+		log::warn!("no sky named {name:?}, using {FALLBACK_SKY:?}");
+		skies.0.get(FALLBACK_SKY).cloned().unwrap_or(Sky::FLAT)
+	});
+	sky.set_if_neq(selected);
+}
+
+fn extract_sky(mut extracted: ResMut<ExtractedSky>, sky: Res<Sky>) {
+	if sky.is_changed() {
+		extracted.0 = sky.clone();
+	}
+}
+
+fn prepare_sky(
+	ctx: NonSend<GraphicsContext>,
+	sky_pipelines: NonSend<SkyPipelines>,
+	sky: Res<ExtractedSky>,
+) {
+	if !sky.is_changed() {
+		return;
+	}
+	let params = |ceiling, floor, repeat, drift, height| SkyParams {
+		ceiling,
+		floor,
+		repeat,
+		drift,
+		height,
+		_padding: 0.0,
+	};
+	let layers = match &sky.0 {
+		Sky::None | Sky::Cubemap(_) => Vec::new(),
+		&Sky::Flat { ceiling, floor } => vec![params(ceiling, floor, 0.0, 0.0, 0.0)],
+		Sky::Panorama(layers) => layers
+			.iter()
+			.take(MAX_SKY_LAYERS)
+			.map(|layer| {
+				let size = sky_pipelines.panoramas[layer.texture.0 as usize]
+					.1
+					.as_vec2();
+				// square texels on the cylinder
+				let height = TAU / layer.repeat * size.y / size.x;
+				params(Color::NONE, Color::NONE, layer.repeat, layer.drift, height)
+			})
+			.collect(),
+	};
+	for (index, layer) in layers.iter().enumerate() {
+		ctx.write_buffer(
+			&sky_pipelines.params,
+			(index as u32 * sky_pipelines.params_stride) as _,
+			bytemuck::bytes_of(layer),
+		);
+	}
+}
+
+fn draw_sky(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	sky_pipelines: NonSend<SkyPipelines>,
+	pipelines: NonSend<Pipelines>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	sky: Res<ExtractedSky>,
+	view: CurrentView,
+) {
+	let view = view.get();
+	// overlay cameras keep what is behind them
+	if sky.0 == Sky::None || !matches!(view.clear, ClearMode::Color(_)) {
+		return;
+	}
+
+	let mut pass = frame.begin_view_pass("sky", &mut profiler, view);
+	pass.set_bind_group(0, &pipelines.uniforms_group, &[view.uniforms_offset]);
+	match &sky.0 {
+		Sky::None => {},
+		Sky::Flat { .. } => {
+			pass.set_pipeline(&sky_pipelines.flat);
+			ctx.record_pipeline_switch();
+			pass.set_bind_group(1, &sky_pipelines.params_group, &[0]);
+			pass.draw(0 .. 3, 0 .. 1);
+			ctx.record_draw(1);
+		},
+		Sky::Panorama(layers) => {
+			pass.set_pipeline(&sky_pipelines.panorama);
+			ctx.record_pipeline_switch();
+			for (index, layer) in layers.iter().take(MAX_SKY_LAYERS).enumerate() {
+				let offset = index as u32 * sky_pipelines.params_stride;
+				pass.set_bind_group(1, &sky_pipelines.params_group, &[offset]);
+				let (group, _) = &sky_pipelines.panoramas[layer.texture.0 as usize];
+				pass.set_bind_group(2, group, &[]);
+				pass.draw(0 .. 3, 0 .. 1);
+				ctx.record_draw(1);
+			}
+		},
+		Sky::Cubemap(cubemap) => {
+			pass.set_pipeline(&sky_pipelines.cubemap);
+			ctx.record_pipeline_switch();
+			pass.set_bind_group(1, &sky_pipelines.params_group, &[0]);
+			pass.set_bind_group(2, &sky_pipelines.cubemaps[cubemap.0 as usize], &[]);
+			pass.draw(0 .. 3, 0 .. 1);
+			ctx.record_draw(1);
+		},
+	}
+	drop(pass);
+	profiler.end_pass();
+}
+
+/// Texels of a `size` texture, with `texel` given the position of each in
+/// `0.0 ..= 1.0` from the top left.
+fn generate(size: UVec2, texel: impl Fn(Vec2) -> Vec4) -> Vec<Texel> {
+	(0 .. size.x * size.y)
+		.map(|index| {
+			let position = UVec2::new(index % size.x, index / size.x).as_vec2() + 0.5;
+			(texel(position / size.as_vec2()).clamp(Vec4::ZERO, Vec4::ONE) * 255.0)
+				.round()
+				.to_array()
+				.map(|channel| channel as u8)
+		})
+		.collect()
+}
+
+/// A ridge line around `base` that wraps around seamlessly, from sines with
+/// whole numbers of periods given with their amplitude and phase.
+fn ridge(u: f32, base: f32, waves: &[(f32, f32, f32)]) -> f32 {
+	base + waves
+		.iter()
+		.map(|&(periods, amplitude, phase)| (u * periods * TAU + phase).sin() * amplitude)
+		.sum::<f32>()
+}
+
+/// A glowing evening sky behind two ranges of hills.
+fn dusk(ctx: &GraphicsContext, sky_pipelines: &mut SkyPipelines) -> Sky {
+	let glow = Vec4::new(1.0, 0.55, 0.25, 1.0);
+	let zenith = Vec4::new(0.12, 0.08, 0.25, 1.0);
+	let size = UVec2::new(256, 64);
+	let backdrop = generate(size, |position| {
+		// brightest towards the sun, low in the east
+		let sun = (1.0 - ((position.x - 0.25).abs() * 4.0).min(1.0)) * (1.0 - position.y);
+		zenith.lerp(glow, (1.0 - position.y).powi(2) * 0.7 + sun * 0.3)
+	});
+	let hills = |color: Vec4, base, waves: &[(f32, f32, f32)]| {
+		generate(size, |position| {
+			let top = 1.0 - ridge(position.x, base, waves);
+			if position.y > top { color } else { Vec4::ZERO }
+		})
+	};
+	let far = hills(Vec4::new(0.3, 0.18, 0.35, 1.0), 0.35, &[
+		(3.0, 0.15, 0.0),
+		(7.0, 0.08, 1.0),
+		(13.0, 0.03, 2.0),
+	]);
+	let near = hills(Vec4::new(0.12, 0.08, 0.16, 1.0), 0.3, &[
+		(2.0, 0.1, 0.5),
+		(5.0, 0.06, 3.0),
+	]);
+
+	let mut layer = |texels: &[Texel], repeat| PanoramaLayer {
+		texture: sky_pipelines.add_panorama(ctx, size, texels),
+		repeat,
+		drift: 0.0,
+	};
+	Sky::Panorama(vec![
+		layer(&backdrop, 1.0),
+		layer(&far, 2.0),
+		layer(&near, 3.0),
+	])
+}
+
+/// Stars above a dark blue horizon.
+fn night(ctx: &GraphicsContext, sky_pipelines: &mut SkyPipelines) -> Sky {
+	const SIZE: u32 = 128;
+	let horizon = Vec4::new(0.05, 0.08, 0.2, 1.0);
+	let zenith = Vec4::new(0.0, 0.0, 0.03, 1.0);
+	// directions through each face, matching `add_cubemap`
+	let faces: [fn(Vec2) -> Vec3; 6] = [
+		|uv| Vec3::new(1.0, -uv.x, -uv.y),
+		|uv| Vec3::new(-1.0, uv.x, -uv.y),
+		|uv| Vec3::new(uv.x, 1.0, -uv.y),
+		|uv| Vec3::new(-uv.x, -1.0, -uv.y),
+		|uv| Vec3::new(uv.x, uv.y, 1.0),
+		|uv| Vec3::new(uv.x, -uv.y, -1.0),
+	];
+	let faces = faces.map(|face| {
+		generate(UVec2::splat(SIZE), |position| {
+			let direction = face(position * 2.0 - 1.0).normalize();
+			// hash the direction rather than the texel, so stars don't line
+			// up along the edges of faces
+			let cell = (direction * 200.0).floor();
+			let hash = (cell.dot(Vec3::new(12.9898, 78.233, 37.719)).sin() * 43758.547).fract();
+			if direction.z > 0.0 && hash.abs() > 0.997 {
+				Vec4::ONE
+			} else {
+				horizon.lerp(zenith, direction.z.max(0.0).sqrt())
+			}
+		})
+	});
+	Sky::Cubemap(sky_pipelines.add_cubemap(ctx, SIZE, faces.each_ref().map(Vec::as_slice)))
+}
@@ -0,0 +1,439 @@
+//! Baking of static sprites into per-chunk vertex and index buffers, so large
+//! amounts of level geometry cost one draw call per chunk instead of being
+//! re-uploaded as instances every frame.
+
+use bevy_platform::collections::HashMap;
+use wgpu::BufferUsages;
+
+#[cfg(debug_assertions)]
+use crate::gfx::shader::{ShaderChanged, ShaderReloads};
+use crate::{
+	gfx::{
+		ActiveFrame,
+		Color,
+		GraphicsContext,
+		Pipelines,
+		RenderPre,
+		RenderPreSet,
+		Sprite,
+		SpriteMode,
+		WorldRenderer,
+		areas::Areas,
+		camera::{CurrentView, RenderView, ViewSet},
+		profiling::GpuProfiler,
+		shader,
+		textures::{SpriteTextures, TextureId},
+		visibility::{InheritedVisibility, RenderLayers},
+	},
+	map::TileMap,
+	prelude::*,
+	transform::Transform,
+};
+
+/// Width and depth of a chunk in world units.
+pub const CHUNK_SIZE: f32 = 16.0;
+
+/// Marks a sprite as never moving, so it can be baked into its chunk's
+/// geometry. Only `Fixed` sprites without a material are baked; billboards
+/// and material sprites are still drawn as instances. Changing a static
+/// sprite's `Transform`, `Sprite`, visibility or render layers rebakes its
+/// chunk.
+#[derive(Clone, Copy, Debug, Default, Component)]
+pub struct Static;
+
+pub fn chunk_of(position: Vec3) -> IVec2 {
+	(position.truncate() / CHUNK_SIZE).floor().as_ivec2()
+}
+
+/// A corner of a baked quad, carrying what `quad.wgsl` shades an instance
+/// with.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct StaticVertex {
+	pub position: Vec3,
+	pub uv: Vec2,
+	pub light: f32,
+	/// Layer of the sprite texture array plus one, or `0` if untextured.
+	pub texture: u32,
+	pub flags: u32,
+	pub tint: Color,
+	pub uv_rect: Vec4,
+}
+
+/// Vertex layout of [`StaticVertex`] for `static.wgsl`.
+pub(crate) const STATIC_VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+	0 => Float32x3,
+	1 => Float32x2,
+	2 => Float32,
+	3 => Uint32,
+	4 => Uint32,
+	5 => Float32x4,
+	6 => Float32x4,
+];
+
+struct ChunkBuffers {
+	vertices: wgpu::Buffer,
+	indices: wgpu::Buffer,
+	index_count: u32,
+}
+
+/// Chunk coordinates, plus the render layers and area shared by everything in
+/// the chunk so whole chunks can be skipped per camera.
+type ChunkKey = (IVec2, RenderLayers, Option<u32>);
+
+/// What baking needs of a static sprite, copied out when it changes.
+#[derive(Clone, Copy)]
+struct StaticQuad {
+	model: Mat4,
+	size: Vec2,
+	light: f32,
+	/// Where the tile light level is sampled.
+	light_position: Vec3,
+	texture: u32,
+	flags: u32,
+	tint: Color,
+	uv_rect: Vec4,
+}
+
+impl StaticQuad {
+	fn new(transform: &Transform, sprite: &Sprite) -> Self {
+		Self {
+			model: transform.as_model_matrix(),
+			size: sprite.size,
+			light: sprite.light,
+			// the cell in front of the face, as walls sit on cell boundaries
+			light_position: transform.translation + transform.forward() * 0.01,
+			texture: TextureId::instance_index(sprite.texture),
+			flags: sprite.instance_flags(),
+			tint: sprite.tint,
+			uv_rect: sprite.instance_uv_rect(),
+		}
+	}
+}
+
+#[derive(Default)]
+struct Chunk {
+	entities: HashMap<Entity, StaticQuad>,
+	dirty: bool,
+	buffers: Option<ChunkBuffers>,
+}
+
+pub struct StaticBatches {
+	chunks: HashMap<ChunkKey, Chunk>,
+	entity_chunks: HashMap<Entity, ChunkKey>,
+	pipeline: wgpu::RenderPipeline,
+}
+
+impl StaticBatches {
+	pub fn new(ctx: &GraphicsContext, pipelines: &Pipelines, textures: &SpriteTextures) -> Self {
+		let pipeline = create_pipeline(
+			ctx,
+			&pipelines.uniforms_layout,
+			textures,
+			&shader::create_shader_module(ctx, "static.wgsl", &[]),
+		);
+
+		Self {
+			chunks: default(),
+			entity_chunks: default(),
+			pipeline,
+		}
+	}
+
+	fn insert(&mut self, entity: Entity, chunk: ChunkKey, quad: StaticQuad) {
+		if let Some(previous) = self.entity_chunks.insert(entity, chunk) &&
+			previous != chunk
+		{
+			self.remove_from(entity, previous);
+		}
+		let chunk = self.chunks.entry(chunk).or_default();
+		chunk.entities.insert(entity, quad);
+		chunk.dirty = true;
+	}
+
+	fn remove(&mut self, entity: Entity) {
+		if let Some(chunk) = self.entity_chunks.remove(&entity) {
+			self.remove_from(entity, chunk);
+		}
+	}
+
+	fn remove_from(&mut self, entity: Entity, chunk: ChunkKey) {
+		if let Some(chunk) = self.chunks.get_mut(&chunk) {
+			chunk.entities.remove(&entity);
+			chunk.dirty = true;
+		}
+	}
+}
+
+fn create_pipeline(
+	ctx: &GraphicsContext,
+	uniforms_layout: &wgpu::BindGroupLayout,
+	textures: &SpriteTextures,
+	shader_module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+	let pipeline_layout = ctx
+		.device
+		.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("static render layout"),
+			bind_group_layouts: &[uniforms_layout, &textures.layout],
+			push_constant_ranges: &[],
+		});
+	ctx.device
+		.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("static render pipeline"),
+			layout: Some(&pipeline_layout),
+			depth_stencil: Some(wgpu::DepthStencilState {
+				format: wgpu::TextureFormat::Depth24Plus,
+				depth_write_enabled: true,
+				depth_compare: wgpu::CompareFunction::LessEqual,
+				stencil: default(),
+				bias: default(),
+			}),
+			multisample: wgpu::MultisampleState::default(),
+			multiview: None,
+			cache: None,
+			primitive: wgpu::PrimitiveState {
+				topology: wgpu::PrimitiveTopology::TriangleList,
+				cull_mode: Some(wgpu::Face::Back),
+				..default()
+			},
+			vertex: wgpu::VertexState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: Some("static_main"),
+				buffers: &[wgpu::VertexBufferLayout {
+					step_mode: wgpu::VertexStepMode::Vertex,
+					array_stride: size_of::<StaticVertex>() as _,
+					attributes: &STATIC_VERTEX_ATTRIBUTES,
+				}],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: shader_module,
+				compilation_options: wgpu::PipelineCompilationOptions::default(),
+				entry_point: Some("fragment_main"),
+				targets: &[Some(ctx.surface_format().into())],
+			}),
+		})
+}
+
+#[cfg(debug_assertions)]
+fn reload_pipeline(
+	mut changes: EventReader<ShaderChanged>,
+	ctx: NonSend<GraphicsContext>,
+	pipelines: NonSend<Pipelines>,
+	textures: NonSend<SpriteTextures>,
+	reloads: NonSend<ShaderReloads>,
+) {
+	if changes
+		.read()
+		.any(|change| change.affects("static.wgsl", &[]))
+	{
+		reloads.rebuild(
+			&ctx,
+			"static.wgsl",
+			&[],
+			|shader_module| {
+				create_pipeline(&ctx, &pipelines.uniforms_layout, &textures, shader_module)
+			},
+			|world, pipeline| world.non_send_resource_mut::<StaticBatches>().pipeline = pipeline,
+		);
+	}
+}
+
+/// The four corners of a `Fixed` sprite's quad, in triangle strip order.
+fn quad_vertices(quad: &StaticQuad, light: f32) -> [StaticVertex; 4] {
+	let half = quad.size / 2.0;
+	[
+		(Vec3::new(-half.x, 0.0, half.y), Vec2::new(0.0, 1.0)),
+		(Vec3::new(half.x, 0.0, half.y), Vec2::new(1.0, 1.0)),
+		(Vec3::new(-half.x, 0.0, -half.y), Vec2::new(0.0, 0.0)),
+		(Vec3::new(half.x, 0.0, -half.y), Vec2::new(1.0, 0.0)),
+	]
+	.map(|(position, uv)| StaticVertex {
+		position: quad.model.transform_point3(position),
+		uv,
+		light,
+		texture: quad.texture,
+		flags: quad.flags,
+		tint: quad.tint,
+		uv_rect: quad.uv_rect,
+	})
+}
+
+pub(super) fn setup(app: &mut App) {
+	app.add_systems(
+		RenderPre,
+		(
+			track_statics.in_set(RenderPreSet::Extract),
+			bake_statics.in_set(RenderPreSet::Prepare),
+		),
+	);
+	app.add_systems(
+		RenderView,
+		draw_statics
+			.in_set(ViewSet::World)
+			.run_if(resource_equals(WorldRenderer::Polygonal)),
+	);
+	#[cfg(debug_assertions)]
+	app.add_systems(Update, reload_pipeline);
+}
+
+type StaticItem<'a> = (
+	Entity,
+	&'a Transform,
+	&'a Sprite,
+	&'a InheritedVisibility,
+	Option<&'a RenderLayers>,
+);
+
+/// Statics whose chunk may have changed.
+type StaticChanged = (
+	With<Static>,
+	Or<(
+		Added<Static>,
+		Changed<Transform>,
+		Changed<Sprite>,
+		Changed<InheritedVisibility>,
+		Changed<RenderLayers>,
+	)>,
+);
+
+/// Copies statics that changed into their chunks, marking those for baking.
+fn track_statics(
+	mut batches: NonSendMut<StaticBatches>,
+	changed: Query<StaticItem, StaticChanged>,
+	statics: Query<StaticItem, With<Static>>,
+	areas: Res<Areas>,
+	mut removed_statics: RemovedComponents<Static>,
+	mut removed_sprites: RemovedComponents<Sprite>,
+	mut removed_layers: RemovedComponents<RenderLayers>,
+) {
+	for entity in removed_statics.read().chain(removed_sprites.read()) {
+		batches.remove(entity);
+	}
+
+	let mut track = |(entity, transform, sprite, visibility, layers): StaticItem| match sprite.mode
+	{
+		SpriteMode::Fixed if visibility.get() && sprite.material.is_none() => {
+			let layers = layers.copied().unwrap_or_default();
+			let area = areas.sprite_area(transform, sprite);
+			let chunk = (chunk_of(transform.translation), layers, area);
+			batches.insert(entity, chunk, StaticQuad::new(transform, sprite));
+		},
+		_ => batches.remove(entity),
+	};
+	// back on the default layers
+	for entity in removed_layers.read() {
+		if let Ok(item) = statics.get(entity) {
+			track(item);
+		}
+	}
+	// rebuilt areas can move anything into another area
+	if areas.is_changed() {
+		statics.iter().for_each(&mut track);
+	} else {
+		changed.iter().for_each(&mut track);
+	}
+}
+
+fn bake_statics(
+	ctx: NonSend<GraphicsContext>,
+	mut batches: NonSendMut<StaticBatches>,
+	map: Option<Res<TileMap>>,
+	mut vertices: Local<Vec<StaticVertex>>,
+	mut indices: Local<Vec<u32>>,
+) {
+	if let Some(map) = &map &&
+		map.is_changed()
+	{
+		// light levels are baked in
+		for chunk in batches.chunks.values_mut() {
+			chunk.dirty = true;
+		}
+	}
+
+	let mut emptied = Vec::new();
+	for (&key, chunk) in batches.chunks.iter_mut().filter(|(_, chunk)| chunk.dirty) {
+		chunk.dirty = false;
+
+		vertices.clear();
+		indices.clear();
+		for quad in chunk.entities.values() {
+			let light = quad.light *
+				map.as_ref()
+					.map_or(1.0, |map| map.light_at(quad.light_position));
+			let base = vertices.len() as u32;
+			vertices.extend(quad_vertices(quad, light));
+			indices.extend([0, 1, 2, 2, 1, 3].map(|index| base + index));
+		}
+		if indices.is_empty() {
+			emptied.push(key);
+			continue;
+		}
+
+		log::trace!(
+			"baked static chunk {} with {} quads",
+			key.0,
+			chunk.entities.len()
+		);
+		let create_buffer = |label, usage, contents: &[u8]| {
+			let buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+				label: Some(label),
+				size: contents.len() as _,
+				usage: usage | BufferUsages::COPY_DST,
+				mapped_at_creation: false,
+			});
+			ctx.write_buffer(&buffer, 0, contents);
+			buffer
+		};
+		chunk.buffers = Some(ChunkBuffers {
+			vertices: create_buffer(
+				"static chunk vertices",
+				BufferUsages::VERTEX,
+				bytemuck::cast_slice(&vertices),
+			),
+			indices: create_buffer(
+				"static chunk indices",
+				BufferUsages::INDEX,
+				bytemuck::cast_slice(&indices),
+			),
+			index_count: indices.len() as _,
+		});
+	}
+
+	for key in emptied {
+		batches.chunks.remove(&key);
+	}
+}
+
+fn draw_statics(
+	mut frame: NonSendMut<ActiveFrame>,
+	ctx: NonSend<GraphicsContext>,
+	batches: NonSend<StaticBatches>,
+	pipelines: NonSend<Pipelines>,
+	textures: NonSend<SpriteTextures>,
+	mut profiler: NonSendMut<GpuProfiler>,
+	view: CurrentView,
+) {
+	let view = view.get();
+	let mut pass = frame.begin_view_pass("statics", &mut profiler, view);
+	pass.set_pipeline(&batches.pipeline);
+	ctx.record_pipeline_switch();
+	pass.set_bind_group(0, &pipelines.uniforms_group, &[view.uniforms_offset]);
+	pass.set_bind_group(1, &textures.group, &[]);
+	for buffers in batches
+		.chunks
+		.iter()
+		.filter(|((_, layers, area), _)| {
+			layers.intersects(view.layers) && view.areas.contains(*area)
+		})
+		.filter_map(|(_, chunk)| chunk.buffers.as_ref())
+	{
+		pass.set_vertex_buffer(0, buffers.vertices.slice(..));
+		pass.set_index_buffer(buffers.indices.slice(..), wgpu::IndexFormat::Uint32);
+		pass.draw_indexed(0 .. buffers.index_count, 0, 0 .. 1);
+		ctx.record_draw(1);
+	}
+	drop(pass);
+	profiler.end_pass();
+}
@@ -0,0 +1,335 @@
+//! Sprite textures, stored as the layers of a single texture array so that
+//! sprites with different textures can still be drawn in one instanced call.
+
+use bevy_platform::collections::HashSet;
+
+use crate::{
+	gfx::{
+		GraphicsContext,
+		SpriteInstance,
+		palette::{LIGHT_LEVELS, PALETTE_SIZE},
+	},
+	prelude::*,
+};
+
+/// Width and height of every sprite texture.
+pub const SPRITE_TEXTURE_SIZE: u32 = 64;
+/// Layers in the texture array, the WebGL2 minimum.
+pub const MAX_SPRITE_TEXTURES: u32 = 256;
+
+pub type Texel = [u8; 4];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureId {
+	layer: u32,
+	/// Holds palette indices rather than colours, see
+	/// [`SpriteTextures::add_indexed`].
+	indexed: bool,
+}
+
+impl TextureId {
+	/// The texture `index` layers after this one, for animation frames added
+	/// with [`SpriteTextures::add_frames`].
+	pub fn frame(self, index: u32) -> Self {
+		Self {
+			layer: self.layer + index,
+			..self
+		}
+	}
+
+	pub fn is_indexed(self) -> bool {
+		self.indexed
+	}
+
+	/// Value of `SpriteInstance::texture`, where `0` means untextured.
+	pub(crate) fn instance_index(texture: Option<Self>) -> u32 {
+		texture.map_or(0, |texture| texture.layer + 1)
+	}
+
+	/// Bits of `SpriteInstance::flags` describing the texture.
+	pub(crate) fn instance_flags(texture: Option<Self>) -> u32 {
+		match texture {
+			Some(texture) if texture.indexed => SpriteInstance::INDEXED,
+			_ => 0,
+		}
+	}
+}
+
+pub struct SpriteTextures {
+	texture: wgpu::Texture,
+	/// [`PALETTE_SIZE`] colours in a single row, written by the palette module.
+	pub(super) palette: wgpu::Texture,
+	/// [`LIGHT_LEVELS`] rows of [`PALETTE_SIZE`] palette indices.
+	pub(super) colormap: wgpu::Texture,
+	pub layout: wgpu::BindGroupLayout,
+	pub group: wgpu::BindGroup,
+	len: u32,
+	/// Textures added with [`Self::add_render_target`].
+	render_targets: HashSet<TextureId>,
+}
+
+impl SpriteTextures {
+	pub fn new(ctx: &GraphicsContext) -> Self {
+		let texture = ctx.create_texture(&wgpu::TextureDescriptor {
+			label: Some("sprite textures"),
+			size: wgpu::Extent3d {
+				width: SPRITE_TEXTURE_SIZE,
+				height: SPRITE_TEXTURE_SIZE,
+				depth_or_array_layers: MAX_SPRITE_TEXTURES,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: wgpu::TextureFormat::Rgba8Unorm,
+			// render targets are copied into their layers by a render pass
+			usage: wgpu::TextureUsages::TEXTURE_BINDING |
+				wgpu::TextureUsages::COPY_DST |
+				wgpu::TextureUsages::RENDER_ATTACHMENT,
+			view_formats: &[],
+		});
+		let sampler = ctx.device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("sprite sampler"),
+			..default()
+		});
+		let palette = ctx.create_texture(&wgpu::TextureDescriptor {
+			label: Some("palette"),
+			size: wgpu::Extent3d {
+				width: PALETTE_SIZE as _,
+				height: 1,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: wgpu::TextureFormat::Rgba8Unorm,
+			usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+			view_formats: &[],
+		});
+		let colormap = ctx.create_texture(&wgpu::TextureDescriptor {
+			label: Some("colormap"),
+			size: wgpu::Extent3d {
+				width: PALETTE_SIZE as _,
+				height: LIGHT_LEVELS as _,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: wgpu::TextureFormat::R8Uint,
+			usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+			view_formats: &[],
+		});
+
+		let layout = ctx
+			.device
+			.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+				label: Some("sprite textures layout"),
+				entries: &[
+					wgpu::BindGroupLayoutEntry {
+						binding: 0,
+						count: None,
+						visibility: wgpu::ShaderStages::FRAGMENT,
+						ty: wgpu::BindingType::Texture {
+							sample_type: wgpu::TextureSampleType::Float { filterable: true },
+							view_dimension: wgpu::TextureViewDimension::D2Array,
+							multisampled: false,
+						},
+					},
+					wgpu::BindGroupLayoutEntry {
+						binding: 1,
+						count: None,
+						visibility: wgpu::ShaderStages::FRAGMENT,
+						ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+					},
+					wgpu::BindGroupLayoutEntry {
+						binding: 2,
+						count: None,
+						visibility: wgpu::ShaderStages::FRAGMENT,
+						ty: wgpu::BindingType::Texture {
+							sample_type: wgpu::TextureSampleType::Float { filterable: false },
+							view_dimension: wgpu::TextureViewDimension::D2,
+							multisampled: false,
+						},
+					},
+					wgpu::BindGroupLayoutEntry {
+						binding: 3,
+						count: None,
+						visibility: wgpu::ShaderStages::FRAGMENT,
+						ty: wgpu::BindingType::Texture {
+							sample_type: wgpu::TextureSampleType::Uint,
+							view_dimension: wgpu::TextureViewDimension::D2,
+							multisampled: false,
+						},
+					},
+				],
+			});
+		let group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("sprite textures group"),
+			layout: &layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(&texture.create_view(
+						&wgpu::TextureViewDescriptor {
+							dimension: Some(wgpu::TextureViewDimension::D2Array),
+							..default()
+						},
+					)),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::Sampler(&sampler),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: wgpu::BindingResource::TextureView(&palette.create_view(&default())),
+				},
+				wgpu::BindGroupEntry {
+					binding: 3,
+					resource: wgpu::BindingResource::TextureView(&colormap.create_view(&default())),
+				},
+			],
+		});
+
+		Self {
+			texture,
+			palette,
+			colormap,
+			layout,
+			group,
+			len: 0,
+			render_targets: default(),
+		}
+	}
+
+	/// Uploads a texture of [`SPRITE_TEXTURE_SIZE`] squared texels, in rows
+	/// from the top. Fails on other sizes, or once all
+	/// [`MAX_SPRITE_TEXTURES`] layers are used.
+	pub fn add(&mut self, ctx: &GraphicsContext, texels: &[Texel]) -> Result<TextureId, String> {
+		check_size(texels)?;
+		self.reserve(1)?;
+
+		ctx.write_texture(
+			wgpu::TexelCopyTextureInfo {
+				texture: &self.texture,
+				mip_level: 0,
+				origin: wgpu::Origin3d {
+					x: 0,
+					y: 0,
+					z: self.len,
+				},
+				aspect: wgpu::TextureAspect::All,
+			},
+			bytemuck::cast_slice(texels),
+			wgpu::TexelCopyBufferLayout {
+				offset: 0,
+				bytes_per_row: Some(SPRITE_TEXTURE_SIZE * 4),
+				rows_per_image: None,
+			},
+			wgpu::Extent3d {
+				width: SPRITE_TEXTURE_SIZE,
+				height: SPRITE_TEXTURE_SIZE,
+				depth_or_array_layers: 1,
+			},
+		);
+
+		self.len += 1;
+		Ok(TextureId {
+			layer: self.len - 1,
+			indexed: false,
+		})
+	}
+
+	/// Checks that `count` more layers are free.
+	fn reserve(&self, count: usize) -> Result<(), String> {
+		if self.len as usize + count > MAX_SPRITE_TEXTURES as usize {
+			return Err(format!(
+				"out of sprite texture layers, all {MAX_SPRITE_TEXTURES} are used"
+			));
+		}
+		Ok(())
+	}
+
+	/// Uploads a texture of [`SPRITE_TEXTURE_SIZE`] squared palette indices, in
+	/// rows from the top. Its colours come from the
+	/// [`Palette`](crate::gfx::palette::Palette) when drawn, with texels of the
+	/// `transparent` index left out.
+	pub fn add_indexed(
+		&mut self,
+		ctx: &GraphicsContext,
+		indices: &[u8],
+		transparent: Option<u8>,
+	) -> Result<TextureId, String> {
+		let texels: Vec<Texel> = indices
+			.iter()
+			.map(|&index| {
+				let alpha = if Some(index) == transparent { 0 } else { 255 };
+				[index, 0, 0, alpha]
+			})
+			.collect();
+		Ok(TextureId {
+			indexed: true,
+			..self.add(ctx, &texels)?
+		})
+	}
+
+	/// Reserves a blank texture for a camera with a
+	/// [`RenderTarget::Texture`](crate::gfx::camera::RenderTarget::Texture)
+	/// to draw into.
+	pub fn add_render_target(&mut self, ctx: &GraphicsContext) -> Result<TextureId, String> {
+		let size = (SPRITE_TEXTURE_SIZE * SPRITE_TEXTURE_SIZE) as usize;
+		let texture = self.add(ctx, &vec![[0; 4]; size])?;
+		self.render_targets.insert(texture);
+		Ok(texture)
+	}
+
+	pub fn is_render_target(&self, texture: TextureId) -> bool {
+		self.render_targets.contains(&texture)
+	}
+
+	/// View of the single layer holding `texture`.
+	pub(super) fn layer_view(&self, texture: TextureId) -> wgpu::TextureView {
+		self.texture.create_view(&wgpu::TextureViewDescriptor {
+			label: Some("sprite texture layer"),
+			dimension: Some(wgpu::TextureViewDimension::D2),
+			base_array_layer: texture.layer,
+			array_layer_count: Some(1),
+			..default()
+		})
+	}
+
+	/// Uploads consecutive animation frames, returning the first. Later frames
+	/// are reached with [`TextureId::frame`].
+	pub fn add_frames<'a>(
+		&mut self,
+		ctx: &GraphicsContext,
+		frames: impl IntoIterator<Item = &'a [Texel]>,
+	) -> Result<TextureId, String> {
+		// all or nothing, keeping the frames consecutive
+		let frames: Vec<_> = frames.into_iter().collect();
+		if frames.is_empty() {
+			return Err("no animation frames given".to_owned());
+		}
+		for (index, texels) in frames.iter().enumerate() {
+			check_size(texels).map_err(|error| format!("frame {index}: {error}"))?;
+		}
+		self.reserve(frames.len())?;
+		let first = TextureId {
+			layer: self.len,
+			indexed: false,
+		};
+		for texels in frames {
+			self.add(ctx, texels)?;
+		}
+		Ok(first)
+	}
+}
+
+fn check_size(texels: &[Texel]) -> Result<(), String> {
+	if texels.len() != (SPRITE_TEXTURE_SIZE * SPRITE_TEXTURE_SIZE) as usize {
+		return Err(format!(
+			"sprite textures must be {SPRITE_TEXTURE_SIZE}x{SPRITE_TEXTURE_SIZE}"
+		));
+	}
+	Ok(())
+}
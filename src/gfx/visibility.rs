@@ -0,0 +1,95 @@
+//! Hiding entities without despawning them, and keeping them out of cameras
+//! that don't share a render layer with them.
+
+use crate::{gfx::RenderPre, prelude::*};
+
+/// Whether an entity is drawn. `Inherited` follows the [`ChildOf`] parent, and
+/// is visible for entities without one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Component)]
+#[require(InheritedVisibility)]
+pub enum Visibility {
+	#[default]
+	Inherited,
+	Hidden,
+	Visible,
+}
+
+/// An entity's [`Visibility`] after resolving inheritance, updated at the start
+/// of [`RenderPre`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Component)]
+pub struct InheritedVisibility(bool);
+
+impl Default for InheritedVisibility {
+	fn default() -> Self {
+		Self(true)
+	}
+}
+
+impl InheritedVisibility {
+	pub fn get(self) -> bool {
+		self.0
+	}
+}
+
+/// Bitmask of the layers an entity is drawn on, or of the layers a camera
+/// draws. Entities and cameras without one are on [`RenderLayers::DEFAULT`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Component)]
+pub struct RenderLayers(pub u32);
+
+impl Default for RenderLayers {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+impl RenderLayers {
+	pub const ALL: Self = Self(u32::MAX);
+	pub const DEFAULT: Self = Self::layer(0);
+	pub const NONE: Self = Self(0);
+
+	pub const fn layer(layer: u32) -> Self {
+		Self(1 << layer)
+	}
+
+	pub const fn with(self, layer: u32) -> Self {
+		Self(self.0 | 1 << layer)
+	}
+
+	pub const fn without(self, layer: u32) -> Self {
+		Self(self.0 & !(1 << layer))
+	}
+
+	pub const fn intersects(self, other: Self) -> bool {
+		self.0 & other.0 != 0
+	}
+}
+
+pub(super) fn setup(app: &mut App) {
+	app.add_systems(RenderPre, propagate_visibility.in_set(VisibilitySystems));
+}
+
+/// Systems resolving [`InheritedVisibility`], which anything reading it in
+/// [`RenderPre`] runs after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, SystemSet)]
+pub struct VisibilitySystems;
+
+fn propagate_visibility(
+	mut entities: Query<(Entity, &mut InheritedVisibility)>,
+	hierarchy: Query<(Option<&Visibility>, Option<&ChildOf>)>,
+) {
+	for (entity, mut inherited) in entities.iter_mut() {
+		let mut current = entity;
+		let visible = loop {
+			let Ok((visibility, parent)) = hierarchy.get(current) else {
+				break true;
+			};
+			match (visibility.copied().unwrap_or_default(), parent) {
+				(Visibility::Hidden, _) => break false,
+				(Visibility::Visible, _) | (Visibility::Inherited, None) => break true,
+				(Visibility::Inherited, Some(parent)) => current = parent.parent(),
+			}
+		};
+		// only write on change, so static batches see real changes
+		inherited.set_if_neq(InheritedVisibility(visible));
+	}
+}
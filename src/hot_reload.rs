@@ -0,0 +1,139 @@
+//! Reloading of shaders and assets while the app runs, in debug builds. Watched
+//! files are fetched from the dev server every [`POLL_INTERVAL`], from the
+//! `src/` directory that `make build` links into `dist/`, and a [`FileChanged`]
+//! is sent whenever one differs from the previous fetch.
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use bevy_platform::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::{DomElements, prelude::*};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// New contents of a watched file.
+#[derive(Event, Clone, Debug)]
+pub struct FileChanged {
+	/// Path under `src/`, as passed to [`watch`].
+	pub path: String,
+	pub contents: Vec<u8>,
+}
+
+impl FileChanged {
+	pub fn text(&self) -> Result<&str, String> {
+		str::from_utf8(&self.contents).map_err(|error| format!("{}: {error}", self.path))
+	}
+}
+
+type Fetched = Vec<(String, JsResult<Vec<u8>>)>;
+
+struct HotReload {
+	/// Contents of each watched file as last fetched, or `None` before the
+	/// first fetch.
+	files: HashMap<String, Option<Vec<u8>>>,
+	timer: Timer,
+	/// Results of the fetches in flight, filled in once all have finished.
+	pending: Option<Rc<RefCell<Option<Fetched>>>>,
+}
+
+impl Default for HotReload {
+	fn default() -> Self {
+		Self {
+			files: default(),
+			timer: Timer::new(POLL_INTERVAL, TimerMode::Repeating),
+			pending: None,
+		}
+	}
+}
+
+/// Sends [`FileChanged`] whenever the file at `path` under `src/` changes.
+pub fn watch(app: &mut App, path: &str) {
+	app.add_event::<FileChanged>();
+	app.init_non_send_resource::<HotReload>();
+	app.world_mut()
+		.non_send_resource_mut::<HotReload>()
+		.files
+		.insert(path.to_owned(), None);
+}
+
+app_setup_fn!("hot_reload", setup, before: ["gfx", "map"]);
+fn setup(app: &mut App) -> JsResult {
+	app.add_event::<FileChanged>();
+	app.init_non_send_resource::<HotReload>();
+	app.add_systems(First, poll_files);
+
+	Ok(())
+}
+
+fn poll_files(
+	mut hot_reload: NonSendMut<HotReload>,
+	mut changes: EventWriter<FileChanged>,
+	time: Res<Time<Real>>,
+	dom: NonSend<DomElements>,
+) {
+	let hot_reload = &mut *hot_reload;
+	if let Some(pending) = &hot_reload.pending {
+		let Some(fetched) = pending.take() else {
+			return;
+		};
+		hot_reload.pending = None;
+		for (path, result) in fetched {
+			let Some(previous) = hot_reload.files.get_mut(&path) else {
+				continue;
+			};
+			match result {
+				Ok(contents) => {
+					if previous
+						.as_ref()
+						.is_some_and(|previous| *previous != contents)
+					{
+						log::info!("{path} changed, reloading");
+						changes.write(FileChanged {
+							path,
+							contents: contents.clone(),
+						});
+					}
+					*previous = Some(contents);
+				},
+				// later failures are likely an editor halfway through saving
+				Err(error) if previous.is_none() => {
+					log::warn!("not watching {path}: {error:?}");
+					hot_reload.files.remove(&path);
+				},
+				Err(_) => {},
+			}
+		}
+	}
+
+	if !hot_reload.timer.tick(time.delta()).just_finished() || hot_reload.files.is_empty() {
+		return;
+	}
+	let pending = Rc::new(RefCell::new(None));
+	hot_reload.pending = Some(pending.clone());
+	let paths: Vec<_> = hot_reload.files.keys().cloned().collect();
+	let window = dom.window.clone();
+	wasm_bindgen_futures::spawn_local(async move {
+		let mut fetched = Vec::with_capacity(paths.len());
+		for path in paths {
+			let result = fetch(&window, &path).await;
+			fetched.push((path, result));
+		}
+		*pending.borrow_mut() = Some(fetched);
+	});
+}
+
+async fn fetch(window: &web_sys::Window, path: &str) -> JsResult<Vec<u8>> {
+	let init = web_sys::RequestInit::new();
+	init.set_cache(web_sys::RequestCache::NoStore);
+	let response: web_sys::Response =
+		JsFuture::from(window.fetch_with_str_and_init(&format!("src/{path}"), &init))
+			.await?
+			.dyn_into()?;
+	if !response.ok() {
+		return Err(format!("HTTP {}", response.status()).into());
+	}
+	let buffer = JsFuture::from(response.array_buffer()?).await?;
+	Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
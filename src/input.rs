@@ -30,7 +30,7 @@ fn get_keycode_map() -> &'static BTreeMap<String, KeyCode> {
 			.unwrap_or_else(|err| unreachable!("KeyCode should be an enum ({err:?})"));
 		typeinfo
 			.variant_names()
-			.into_iter()
+			.iter()
 			.map(|s| s.to_string())
 			.filter_map(|name| {
 				if name == "Unidentified" {
@@ -48,7 +48,7 @@ fn get_keycode_map() -> &'static BTreeMap<String, KeyCode> {
 	})
 }
 
-app_setup_fn!(setup);
+app_setup_fn!("input", setup);
 fn setup(app: &mut App) -> JsResult {
 	app.add_systems(PreUpdate, process_inputs);
 
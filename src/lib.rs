@@ -10,7 +10,10 @@ pub mod entities;
 #[cfg(debug_assertions)]
 pub mod fps_counter;
 pub mod gfx;
+#[cfg(debug_assertions)]
+pub mod hot_reload;
 pub mod input;
+pub mod map;
 pub mod transform;
 
 pub mod prelude {
@@ -22,12 +25,25 @@ pub mod prelude {
 
 	pub use crate::JsResult;
 
+	/// Registers a setup function under a unique name, optionally run
+	/// `after` or `before` other named setup functions:
+	/// `app_setup_fn!("player", setup, after: ["gfx", "map"])`.
 	macro_rules! app_setup_fn {
-		(async $f:ident) => {
-			inventory::submit!($crate::SetupFn::Async($f));
+		($name:literal, async $f:ident $(, after: [$($after:literal),*])? $(, before: [$($before:literal),*])?) => {
+			inventory::submit!($crate::SetupFn {
+				name: $name,
+				after: &[$($($after),*)?],
+				before: &[$($($before),*)?],
+				run: $crate::SetupRun::Async($f),
+			});
 		};
-		($f:ident) => {
-			inventory::submit!($crate::SetupFn::Sync($f));
+		($name:literal, $f:ident $(, after: [$($after:literal),*])? $(, before: [$($before:literal),*])?) => {
+			inventory::submit!($crate::SetupFn {
+				name: $name,
+				after: &[$($($after),*)?],
+				before: &[$($($before),*)?],
+				run: $crate::SetupRun::Sync($f),
+			});
 		};
 	}
 	pub(crate) use app_setup_fn;
@@ -49,11 +65,87 @@ pub type JsResult<T = ()> = Result<T, JsValue>;
 
 pub type AsyncSetupResult<'a> = futures_util::future::LocalBoxFuture<'a, JsResult>;
 
-pub enum SetupFn {
+pub struct SetupFn {
+	pub name: &'static str,
+	/// Setup functions that have to run before this one.
+	pub after: &'static [&'static str],
+	/// Setup functions that have to run after this one.
+	pub before: &'static [&'static str],
+	pub run: SetupRun,
+}
+inventory::collect!(SetupFn);
+
+pub enum SetupRun {
 	Sync(fn(&mut App) -> JsResult),
 	Async(for<'a> fn(&'a mut App) -> AsyncSetupResult<'a>),
 }
-inventory::collect!(SetupFn);
+
+/// Orders setup functions so each runs after those it depends on, otherwise
+/// keeping the order they are given in.
+fn sorted_setup_fns<'a>(fns: &[&'a SetupFn]) -> Result<Vec<&'a SetupFn>, String> {
+	let index = |name: &str, by: &str| {
+		fns.iter()
+			.position(|f| f.name == name)
+			.ok_or_else(|| format!("setup function {by:?} is ordered against unknown {name:?}"))
+	};
+
+	// `dependencies[i]` holds the setup functions that have to run before `i`
+	let mut dependencies = vec![Vec::new(); fns.len()];
+	for (i, f) in fns.iter().enumerate() {
+		if fns[.. i].iter().any(|other| other.name == f.name) {
+			return Err(format!("setup function {:?} is registered twice", f.name));
+		}
+		for name in f.after {
+			dependencies[i].push(index(name, f.name)?);
+		}
+		for name in f.before {
+			dependencies[index(name, f.name)?].push(i);
+		}
+	}
+
+	let mut done = vec![false; fns.len()];
+	let mut sorted = Vec::with_capacity(fns.len());
+	while sorted.len() < fns.len() {
+		let Some(next) = (0 .. fns.len())
+			.find(|&i| !done[i] && dependencies[i].iter().all(|&dependency| done[dependency]))
+		else {
+			let cycle: Vec<_> = find_cycle(&dependencies, &done)
+				.into_iter()
+				.map(|i| format!("{:?}", fns[i].name))
+				.collect();
+			return Err(format!(
+				"setup functions can't be ordered, as their dependencies form a cycle: {}",
+				cycle.join(" after "),
+			));
+		};
+		done[next] = true;
+		sorted.push(fns[next]);
+	}
+	Ok(sorted)
+}
+
+/// Follows unfinished dependencies from the first unfinished setup function
+/// until one repeats, when none can run because every one is waiting on
+/// another. Returns the cycle from the repeated function back to itself.
+fn find_cycle(dependencies: &[Vec<usize>], done: &[bool]) -> Vec<usize> {
+	let mut path = Vec::new();
+	let mut current = done
+		.iter()
+		.position(|&done| !done)
+		.unwrap_or_else(|| unreachable!());
+	loop {
+		if let Some(start) = path.iter().position(|&i| i == current) {
+			path.drain(.. start);
+			path.push(current);
+			return path;
+		}
+		path.push(current);
+		current = *dependencies[current]
+			.iter()
+			.find(|&&dependency| !done[dependency])
+			.unwrap_or_else(|| unreachable!());
+	}
+}
 
 pub struct DomElements {
 	pub window: web_sys::Window,
@@ -114,10 +206,12 @@ async fn start() -> JsResult {
 		},
 	);
 
-	for f in inventory::iter::<SetupFn> {
-		match f {
-			SetupFn::Sync(f) => f(&mut app)?,
-			SetupFn::Async(f) => f(&mut app).await?,
+	let setup_fns: Vec<_> = inventory::iter::<SetupFn>.into_iter().collect();
+	for f in sorted_setup_fns(&setup_fns)? {
+		log::debug!("running setup {:?}", f.name);
+		match f.run {
+			SetupRun::Sync(run) => run(&mut app)?,
+			SetupRun::Async(run) => run(&mut app).await?,
 		}
 	}
 
@@ -173,3 +267,76 @@ async fn start() -> JsResult {
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn noop(_: &mut App) -> JsResult {
+		Ok(())
+	}
+
+	fn setup_fn(
+		name: &'static str,
+		after: &'static [&'static str],
+		before: &'static [&'static str],
+	) -> SetupFn {
+		SetupFn {
+			name,
+			after,
+			before,
+			run: SetupRun::Sync(noop),
+		}
+	}
+
+	fn sorted(fns: &[SetupFn]) -> Result<Vec<&'static str>, String> {
+		let fns: Vec<_> = fns.iter().collect();
+		Ok(sorted_setup_fns(&fns)?.iter().map(|f| f.name).collect())
+	}
+
+	#[test]
+	fn ordering() {
+		let fns = [
+			setup_fn("player", &["map"], &[]),
+			setup_fn("input", &[], &[]),
+			setup_fn("map", &["gfx"], &[]),
+			setup_fn("gfx", &[], &[]),
+			setup_fn("fps", &[], &["gfx"]),
+		];
+		assert_eq!(sorted(&fns).unwrap(), [
+			"input", "fps", "gfx", "map", "player"
+		]);
+	}
+
+	#[test]
+	fn unknown_name() {
+		let after = [setup_fn("a", &["missing"], &[])];
+		assert!(sorted(&after).unwrap_err().contains("\"missing\""));
+		let before = [setup_fn("a", &[], &["missing"])];
+		assert!(sorted(&before).unwrap_err().contains("\"missing\""));
+	}
+
+	#[test]
+	fn duplicate_name() {
+		let fns = [setup_fn("a", &[], &[]), setup_fn("a", &[], &[])];
+		assert!(sorted(&fns).unwrap_err().contains("registered twice"));
+	}
+
+	#[test]
+	fn cycle() {
+		let fns = [
+			setup_fn("waiting", &["b"], &[]),
+			setup_fn("a", &["c"], &[]),
+			setup_fn("b", &["a"], &["c"]),
+			setup_fn("c", &[], &[]),
+		];
+		let error = sorted(&fns).unwrap_err();
+		assert!(
+			error.ends_with(r#"cycle: "b" after "a" after "c" after "b""#),
+			"{error}"
+		);
+
+		let itself = [setup_fn("a", &["a"], &[])];
+		assert!(sorted(&itself).unwrap_err().ends_with(r#""a" after "a""#));
+	}
+}
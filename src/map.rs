@@ -0,0 +1,517 @@
+#[cfg(debug_assertions)]
+use crate::hot_reload::{self, FileChanged};
+use crate::{
+	gfx::{
+		Color,
+		Sprite,
+		SpriteBundle,
+		SpriteMode,
+		lighting::PointLight,
+		sky::LevelSky,
+		static_batch::Static,
+		visibility::Visibility,
+	},
+	prelude::*,
+	transform::Transform,
+};
+
+const NEIGHBORS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+/// Contents of a single map cell. Each tile covers one world unit, with walls
+/// spanning from the floor at `z = 0` to the ceiling at `z = 1`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tile {
+	#[default]
+	Empty,
+	/// Solid wall, drawn with the given wall texture.
+	Wall(u8),
+	Door {
+		open: bool,
+	},
+}
+
+impl Tile {
+	/// Whether rays and movement are blocked by this tile.
+	pub fn is_solid(self) -> bool {
+		match self {
+			Self::Empty => false,
+			Self::Wall(_) => true,
+			Self::Door { open } => !open,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+	/// The ray crossed a vertical grid line, hitting an east or west face.
+	X,
+	/// The ray crossed a horizontal grid line, hitting a north or south face.
+	Y,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+	pub cell: IVec2,
+	pub tile: Tile,
+	pub side: Side,
+	/// Outward normal of the face that was hit.
+	pub normal: IVec2,
+	/// Ray parameter of the hit, in multiples of the ray direction. With a
+	/// normalized direction this is the euclidean distance; with a camera
+	/// plane ray it is the perpendicular distance to the camera plane.
+	pub distance: f32,
+	/// Horizontal position of the hit across the face, in `0.0 .. 1.0`.
+	pub face_u: f32,
+}
+
+/// Grid of tiles making up the level. Cell `(x, y)` covers world coordinates
+/// `x .. x + 1` and `y .. y + 1`.
+#[derive(Resource, Clone, Debug)]
+pub struct TileMap {
+	size: UVec2,
+	tiles: Vec<Tile>,
+	/// Brightness of each cell, from `0.0` (black) to `1.0` (fully lit).
+	lights: Vec<f32>,
+}
+
+impl TileMap {
+	pub fn new(size: UVec2) -> Self {
+		let len = (size.x * size.y) as usize;
+		Self {
+			size,
+			tiles: vec![Tile::Empty; len],
+			lights: vec![1.0; len],
+		}
+	}
+
+	/// Parses a map drawn in text, with the first line being the northernmost
+	/// row. `.` or space is an empty tile, `1` to `9` are walls with that
+	/// texture, and `D`/`d` are closed/open doors.
+	pub fn parse(src: &str) -> Result<Self, String> {
+		let rows: Vec<&str> = src.lines().filter(|line| !line.is_empty()).collect();
+		let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+		let mut map = Self::new(UVec2::new(width as u32, rows.len() as u32));
+
+		for (row, line) in rows.iter().enumerate() {
+			let y = (rows.len() - 1 - row) as i32;
+			for (x, char) in line.chars().enumerate() {
+				let tile = match char {
+					'.' | ' ' => Tile::Empty,
+					'1' ..= '9' => Tile::Wall(char as u8 - b'0'),
+					'D' => Tile::Door { open: false },
+					'd' => Tile::Door { open: true },
+					_ => return Err(format!("unknown tile {char:?} at {x}, {row}")),
+				};
+				map.set(IVec2::new(x as i32, y), tile);
+			}
+		}
+
+		Ok(map)
+	}
+
+	pub fn size(&self) -> UVec2 {
+		self.size
+	}
+
+	pub fn contains(&self, cell: IVec2) -> bool {
+		cell.cmpge(IVec2::ZERO).all() && cell.as_uvec2().cmplt(self.size).all()
+	}
+
+	pub fn get(&self, cell: IVec2) -> Option<Tile> {
+		self.contains(cell).then(|| self.tiles[self.index(cell)])
+	}
+
+	pub fn set(&mut self, cell: IVec2, tile: Tile) {
+		if self.contains(cell) {
+			let index = self.index(cell);
+			self.tiles[index] = tile;
+		}
+	}
+
+	/// Light level of `cell`, fully lit outside the map.
+	pub fn light(&self, cell: IVec2) -> f32 {
+		if self.contains(cell) {
+			self.lights[self.index(cell)]
+		} else {
+			1.0
+		}
+	}
+
+	pub fn set_light(&mut self, cell: IVec2, light: f32) {
+		if self.contains(cell) {
+			let index = self.index(cell);
+			self.lights[index] = light;
+		}
+	}
+
+	/// Light level of the cell containing a world position.
+	pub fn light_at(&self, position: Vec3) -> f32 {
+		self.light(Self::cell_at(position.truncate()))
+	}
+
+	/// Whether `cell` blocks rays, treating everything outside the map as
+	/// solid.
+	pub fn is_solid(&self, cell: IVec2) -> bool {
+		self.get(cell).is_none_or(Tile::is_solid)
+	}
+
+	pub fn cells(&self) -> impl Iterator<Item = (IVec2, Tile)> + '_ {
+		self.tiles.iter().enumerate().map(|(index, &tile)| {
+			let index = index as u32;
+			let cell = UVec2::new(index % self.size.x, index / self.size.x);
+			(cell.as_ivec2(), tile)
+		})
+	}
+
+	pub fn cell_at(position: Vec2) -> IVec2 {
+		position.floor().as_ivec2()
+	}
+
+	/// Steps `origin + direction * t` through the grid until it enters a solid
+	/// cell or `t` exceeds `max_distance`.
+	pub fn cast_ray(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> Option<RayHit> {
+		let mut cell = Self::cell_at(origin);
+		let step = IVec2::new(
+			if direction.x < 0.0 { -1 } else { 1 },
+			if direction.y < 0.0 { -1 } else { 1 },
+		);
+		// distance along the ray between successive grid lines on each axis
+		let delta = (1.0 / direction).abs();
+		let fract = origin - cell.as_vec2();
+		let mut side_distance = Vec2::new(
+			if direction.x < 0.0 {
+				fract.x
+			} else {
+				1.0 - fract.x
+			} * delta.x,
+			if direction.y < 0.0 {
+				fract.y
+			} else {
+				1.0 - fract.y
+			} * delta.y,
+		);
+
+		loop {
+			let (side, distance) = if side_distance.x < side_distance.y {
+				cell.x += step.x;
+				side_distance.x += delta.x;
+				(Side::X, side_distance.x - delta.x)
+			} else {
+				cell.y += step.y;
+				side_distance.y += delta.y;
+				(Side::Y, side_distance.y - delta.y)
+			};
+			if distance > max_distance || !distance.is_finite() {
+				return None;
+			}
+			if !self.is_solid(cell) {
+				continue;
+			}
+
+			let hit = origin + direction * distance;
+			let (normal, face_u) = match side {
+				Side::X => (IVec2::new(-step.x, 0), hit.y.fract()),
+				Side::Y => (IVec2::new(0, -step.y), hit.x.fract()),
+			};
+			return Some(RayHit {
+				cell,
+				tile: self.get(cell).unwrap_or(Tile::Wall(0)),
+				side,
+				normal,
+				distance,
+				face_u: face_u.rem_euclid(1.0),
+			});
+		}
+	}
+
+	fn index(&self, cell: IVec2) -> usize {
+		(cell.y as u32 * self.size.x + cell.x as u32) as usize
+	}
+}
+
+/// Path of the level under `src/`.
+const LEVEL_PATH: &str = "maps/demo.txt";
+
+app_setup_fn!("map", setup);
+fn setup(app: &mut App) -> JsResult {
+	let (map, sky) = load_level(include_str!("maps/demo.txt"))?;
+	app.insert_resource(map);
+	app.insert_resource(sky);
+	app.add_systems(Startup, (spawn_walls, spawn_lamps));
+	app.add_systems(Update, sync_door_panels);
+	#[cfg(debug_assertions)]
+	{
+		hot_reload::watch(app, LEVEL_PATH);
+		app.add_systems(Update, reload_level);
+	}
+
+	Ok(())
+}
+
+/// Parses a level file: `key: value` settings, then the map as read by
+/// [`TileMap::parse`]. The settings are:
+///
+/// - `sky: name`, naming one of the [`Skies`](crate::gfx::sky::Skies).
+/// - `light: x y width height level`, setting the light level of a rectangle of
+///   cells, with `x, y` its southwest cell. Any number may be given, later ones
+///   overriding earlier ones where they overlap.
+fn load_level(source: &str) -> Result<(TileMap, LevelSky), String> {
+	let mut sky = LevelSky::default();
+	let mut lights = Vec::new();
+	let mut map_start = 0;
+	for line in source.split_inclusive('\n') {
+		let Some((key, value)) = line.split_once(':') else {
+			break;
+		};
+		match key.trim() {
+			"sky" => sky.0 = Some(value.trim().to_owned()),
+			"light" => lights.push(parse_light(value)?),
+			key => return Err(format!("unknown level setting {key:?}")),
+		}
+		map_start += line.len();
+	}
+
+	let mut map = TileMap::parse(&source[map_start ..])?;
+	for (min, size, level) in lights {
+		for y in min.y .. min.y + size.y {
+			for x in min.x .. min.x + size.x {
+				map.set_light(IVec2::new(x, y), level);
+			}
+		}
+	}
+	Ok((map, sky))
+}
+
+/// Parses the value of a `light` setting into its first cell, size and level.
+fn parse_light(value: &str) -> Result<(IVec2, IVec2, f32), String> {
+	let error = || format!("expected `light: x y width height level`, got {value:?}");
+	let fields: Vec<&str> = value.split_whitespace().collect();
+	let [x, y, width, height, level] = fields[..] else {
+		return Err(error());
+	};
+	let int = |field: &str| field.parse::<i32>().map_err(|_| error());
+	let level = level.parse::<f32>().map_err(|_| error())?;
+	Ok((
+		IVec2::new(int(x)?, int(y)?),
+		IVec2::new(int(width)?, int(height)?),
+		level,
+	))
+}
+
+/// Replaces the map and its walls when the level file changes.
+#[cfg(debug_assertions)]
+fn reload_level(
+	mut cmd: Commands,
+	mut files: EventReader<FileChanged>,
+	geometry: Query<Entity, With<MapGeometry>>,
+) {
+	let Some(file) = files.read().filter(|file| file.path == LEVEL_PATH).last() else {
+		return;
+	};
+	match file.text().and_then(load_level) {
+		Ok((map, sky)) => {
+			for entity in &geometry {
+				cmd.entity(entity).despawn();
+			}
+			spawn_geometry(&mut cmd, &map);
+			cmd.insert_resource(map);
+			cmd.insert_resource(sky);
+		},
+		Err(error) => log::error!("keeping the old map, level failed to load: {error}"),
+	}
+}
+
+fn spawn_lamps(mut cmd: Commands) {
+	// a failing lamp in the dim room behind the door
+	cmd.spawn((
+		Transform::from_translation(Vec3::new(17.5, 12.5, 0.9)),
+		PointLight {
+			color: Color::rgb(1.0, 0.8, 0.5),
+			radius: 3.0,
+			intensity: 1.2,
+			flicker: 0.6,
+		},
+	));
+}
+
+/// One side of a door's panel, drawn in the middle of its tile.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct DoorPanel {
+	pub cell: IVec2,
+}
+
+/// Marks the walls and door panels spawned from the map.
+#[derive(Clone, Copy, Debug, Default, Component)]
+pub struct MapGeometry;
+
+fn spawn_walls(mut cmd: Commands, map: Res<TileMap>) {
+	spawn_geometry(&mut cmd, &map);
+}
+
+fn spawn_geometry(cmd: &mut Commands, map: &TileMap) {
+	let wall = |position: Vec3, facing: IVec2| SpriteBundle {
+		sprite: Sprite {
+			mode: SpriteMode::Fixed,
+			..default()
+		},
+		transform: Transform::from_translation(position)
+			.looking_along(facing.as_vec2().extend(0.0)),
+	};
+
+	for (cell, tile) in map.cells() {
+		let center = cell.as_vec2().extend(0.0) + Vec3::new(0.5, 0.5, 0.5);
+		match tile {
+			Tile::Empty => {},
+			Tile::Wall(_) => {
+				for normal in NEIGHBORS {
+					// faces towards other walls can never be seen
+					if matches!(map.get(cell + normal), None | Some(Tile::Wall(_))) {
+						continue;
+					}
+					let position = center + normal.as_vec2().extend(0.0) * 0.5;
+					cmd.spawn((wall(position, normal), Static, MapGeometry));
+				}
+			},
+			Tile::Door { .. } => {
+				// doors span the gap between the walls on either side of them
+				let facing = if map.is_solid(cell + IVec2::X) {
+					IVec2::Y
+				} else {
+					IVec2::X
+				};
+				for facing in [facing, -facing] {
+					cmd.spawn((
+						wall(center, facing),
+						DoorPanel { cell },
+						door_visibility(tile),
+						MapGeometry,
+					));
+				}
+			},
+		}
+	}
+}
+
+/// Hides the panels of open doors.
+fn door_visibility(tile: Tile) -> Visibility {
+	match tile {
+		Tile::Door { open: true } => Visibility::Hidden,
+		_ => Visibility::Inherited,
+	}
+}
+
+/// Shows or hides door panels as their doors are closed or opened.
+fn sync_door_panels(map: Res<TileMap>, mut panels: Query<(&DoorPanel, &mut Visibility)>) {
+	if !map.is_changed() {
+		return;
+	}
+	for (panel, mut visibility) in &mut panels {
+		let tile = map.get(panel.cell).unwrap_or_default();
+		visibility.set_if_neq(door_visibility(tile));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A corridor with a closed door at its east end, and a wall beyond.
+	const CORRIDOR: &str = "111111\n1..D.1\n111111\n";
+
+	#[test]
+	fn parse() {
+		let map = TileMap::parse("12\n\nD.d\n").unwrap();
+		assert_eq!(map.size(), UVec2::new(3, 2));
+		assert_eq!(map.get(IVec2::new(0, 1)), Some(Tile::Wall(1)));
+		assert_eq!(map.get(IVec2::new(1, 1)), Some(Tile::Wall(2)));
+		// short rows are padded with empty tiles
+		assert_eq!(map.get(IVec2::new(2, 1)), Some(Tile::Empty));
+		assert_eq!(map.get(IVec2::new(0, 0)), Some(Tile::Door { open: false }));
+		assert_eq!(map.get(IVec2::new(1, 0)), Some(Tile::Empty));
+		assert_eq!(map.get(IVec2::new(2, 0)), Some(Tile::Door { open: true }));
+		assert_eq!(map.get(IVec2::new(3, 0)), None);
+
+		let error = TileMap::parse("11\n1x\n").unwrap_err();
+		assert!(error.contains("'x' at 1, 1"), "{error}");
+	}
+
+	#[test]
+	fn ray_hits_door() {
+		let map = TileMap::parse(CORRIDOR).unwrap();
+		let hit = map.cast_ray(Vec2::new(1.5, 1.25), Vec2::X, 16.0).unwrap();
+		assert_eq!(hit.cell, IVec2::new(3, 1));
+		assert_eq!(hit.tile, Tile::Door { open: false });
+		assert_eq!(hit.side, Side::X);
+		assert_eq!(hit.normal, IVec2::NEG_X);
+		assert_eq!(hit.distance, 1.5);
+		assert_eq!(hit.face_u, 0.25);
+	}
+
+	#[test]
+	fn ray_passes_open_door() {
+		let map = TileMap::parse(&CORRIDOR.replace('D', "d")).unwrap();
+		let hit = map.cast_ray(Vec2::new(1.5, 1.5), Vec2::X, 16.0).unwrap();
+		assert_eq!(hit.cell, IVec2::new(5, 1));
+		assert_eq!(hit.tile, Tile::Wall(1));
+		assert_eq!(hit.distance, 3.5);
+	}
+
+	#[test]
+	fn ray_hits_walls() {
+		let map = TileMap::parse(CORRIDOR).unwrap();
+
+		let west = map
+			.cast_ray(Vec2::new(2.5, 1.75), Vec2::NEG_X, 16.0)
+			.unwrap();
+		assert_eq!(west.cell, IVec2::new(0, 1));
+		assert_eq!(west.side, Side::X);
+		assert_eq!(west.normal, IVec2::X);
+		assert_eq!(west.distance, 1.5);
+		assert_eq!(west.face_u, 0.75);
+
+		let north = map.cast_ray(Vec2::new(1.25, 1.5), Vec2::Y, 16.0).unwrap();
+		assert_eq!(north.cell, IVec2::new(1, 2));
+		assert_eq!(north.side, Side::Y);
+		assert_eq!(north.normal, IVec2::NEG_Y);
+		assert_eq!(north.distance, 0.5);
+		assert_eq!(north.face_u, 0.25);
+
+		let south = map
+			.cast_ray(Vec2::new(2.75, 1.5), Vec2::NEG_Y, 16.0)
+			.unwrap();
+		assert_eq!(south.cell, IVec2::new(2, 0));
+		assert_eq!(south.normal, IVec2::Y);
+		assert_eq!(south.face_u, 0.75);
+
+		// the ray parameter scales with the direction
+		let scaled = map
+			.cast_ray(Vec2::new(1.25, 1.5), Vec2::Y * 2.0, 16.0)
+			.unwrap();
+		assert_eq!(scaled.distance, 0.25);
+	}
+
+	#[test]
+	fn ray_misses_beyond_max_distance() {
+		let map = TileMap::parse(CORRIDOR).unwrap();
+		assert!(map.cast_ray(Vec2::new(1.5, 1.5), Vec2::X, 1.0).is_none());
+		assert!(map.cast_ray(Vec2::new(1.5, 1.5), Vec2::X, 1.5).is_some());
+	}
+
+	#[test]
+	fn level_lights() {
+		let source = "light: 1 0 2 2 0.5\nlight: 2 1 1 1 0.25\n....\n....\n";
+		let (map, _) = load_level(source).unwrap();
+		assert_eq!(map.light(IVec2::new(0, 0)), 1.0);
+		assert_eq!(map.light(IVec2::new(1, 0)), 0.5);
+		assert_eq!(map.light(IVec2::new(2, 0)), 0.5);
+		assert_eq!(map.light(IVec2::new(1, 1)), 0.5);
+		assert_eq!(map.light(IVec2::new(2, 1)), 0.25);
+		assert_eq!(map.light(IVec2::new(3, 1)), 1.0);
+
+		let (demo, _) = load_level(include_str!("maps/demo.txt")).unwrap();
+		assert_eq!(demo.light(IVec2::new(17, 12)), 0.35);
+
+		assert!(load_level("light: 1 2 3\n....\n").is_err());
+		assert!(load_level("light: 1 2 3 4 dim\n....\n").is_err());
+	}
+}